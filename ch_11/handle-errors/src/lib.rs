@@ -1,29 +1,86 @@
-use warp::{
-    filters::{body::BodyDeserializeError, cors::CorsForbidden},
-    http::StatusCode,
-    reject::Reject,
-    Rejection, Reply,
-};
-use tracing::{event, Level, instrument};
+use std::convert::Infallible;
+
 use argon2::Error as ArgonError;
 use reqwest::Error as ReqwestError;
 use reqwest_middleware::Error as MiddlewareReqwestError;
-
+use serde::Serialize;
+use tracing::{event, instrument, Level};
+use warp::{
+    filters::{body::BodyDeserializeError, cors::CorsForbidden},
+    http::{
+        header::{HeaderValue, CONTENT_TYPE, RETRY_AFTER},
+        StatusCode,
+    },
+    reject::{
+        InvalidQuery, MethodNotAllowed, MissingHeader, PayloadTooLarge, Reject,
+        UnsupportedMediaType,
+    },
+    reply::Response,
+    Filter, Rejection, Reply,
+};
 
 #[derive(Debug)]
 pub enum Error {
     ParseError(std::num::ParseIntError),
     MissingParameters,
+    InvalidParameter(String),
+    ValidationError(Vec<FieldError>),
     WrongPassword,
     CannotDecryptToken,
     Unauthorized,
+    /// The account may not change the resource
+    Forbidden,
+    /// A cookie authenticated request lacks a matching CSRF token
+    CsrfTokenMismatch,
+    NotFound,
+    /// A unique constraint, named by the value, was violated
+    Conflict(String),
+    /// A referenced row, e.g. the question of a new answer, doesn't exist
+    ForeignKeyViolation(String),
+    /// The database can't be reached right now
+    Unavailable,
+    /// The question was closed and takes no new answers
+    QuestionClosed,
+    /// The password reset token is unknown, expired or used already
+    InvalidResetToken,
+    /// The email verification token is unknown, expired or used already
+    InvalidVerificationToken,
+    /// Posting needs a verified email address
+    EmailNotVerified,
+    /// The API key lacks the scope the route needs
+    InsufficientScope(String),
+    /// The route needs a login, API keys can't be used for it
+    ApiKeyNotAllowed,
+    /// Logins are locked after too many failures, for the given seconds
+    LoginLocked(u64),
+    /// The client used up its request quota, which allows `limit`
+    /// requests a minute. It has to wait `retry_after` seconds
+    RateLimited {
+        limit: u32,
+        retry_after: u64,
+    },
+    /// The mail server didn't accept a message
+    MailError(String),
     ArgonLibraryError(ArgonError),
     DatabaseQueryError(sqlx::Error),
     MigrationError(sqlx::migrate::MigrateError),
     ReqwestAPIError(ReqwestError),
     MiddlewareReqwestAPIError(MiddlewareReqwestError),
     ClientError(APILayerError),
-    ServerError(APILayerError)
+    ServerError(APILayerError),
+}
+
+/// A request body field which didn't pass validation
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for FieldError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -43,9 +100,49 @@ impl std::fmt::Display for Error {
         match &*self {
             Error::ParseError(ref err) => write!(f, "Cannot parse parameter: {}", err),
             Error::MissingParameters => write!(f, "Missing parameter"),
+            Error::InvalidParameter(ref err) => write!(f, "Invalid parameter: {}", err),
+            Error::ValidationError(ref errors) => write!(
+                f,
+                "Invalid request body: {}",
+                errors
+                    .iter()
+                    .map(|error| error.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
             Error::WrongPassword => write!(f, "Wrong password"),
             Error::CannotDecryptToken => write!(f, "Cannot decrypt error"),
-            Error::Unauthorized => write!(f, "No permission to change the underlying resource"),
+            Error::Unauthorized => write!(f, "Missing or invalid credentials"),
+            Error::Forbidden => write!(f, "No permission to change the underlying resource"),
+            Error::CsrfTokenMismatch => write!(f, "Missing or invalid CSRF token"),
+            Error::NotFound => write!(f, "Requested resource not found"),
+            Error::Conflict(constraint) => write!(f, "Resource already exists: {}", constraint),
+            Error::ForeignKeyViolation(constraint) => {
+                write!(f, "Referenced resource not found: {}", constraint)
+            }
+            Error::Unavailable => write!(f, "Database unavailable"),
+            Error::QuestionClosed => write!(f, "The question is closed for new answers"),
+            Error::InvalidResetToken => write!(f, "Invalid or expired password reset token"),
+            Error::InvalidVerificationToken => write!(
+                f,
+                "Invalid or expired verification token, request a new one with POST /verify-email/resend"
+            ),
+            Error::EmailNotVerified => write!(f, "Verify your email address before posting"),
+            Error::InsufficientScope(scope) => {
+                write!(f, "The API key lacks the scope {}", scope)
+            }
+            Error::ApiKeyNotAllowed => write!(f, "API keys can't be used for this route"),
+            Error::LoginLocked(seconds) => write!(
+                f,
+                "Too many failed logins, retry in {} seconds",
+                seconds
+            ),
+            Error::RateLimited { retry_after, .. } => write!(
+                f,
+                "Too many requests, retry in {} seconds",
+                retry_after
+            ),
+            Error::MailError(err) => write!(f, "Cannot send mail: {}", err),
             Error::ArgonLibraryError(_) => write!(f, "Cannot verifiy password"),
             Error::DatabaseQueryError(_) => write!(f, "Cannot update, invalid data"),
             Error::MigrationError(_) => write!(f, "Cannot migrate data"),
@@ -57,96 +154,485 @@ impl std::fmt::Display for Error {
     }
 }
 
+impl Error {
+    /// HTTP status code the error is answered with
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Error::ParseError(_)
+            | Error::MissingParameters
+            | Error::InvalidParameter(_)
+            | Error::ValidationError(_)
+            | Error::InvalidResetToken
+            | Error::InvalidVerificationToken => StatusCode::BAD_REQUEST,
+            Error::WrongPassword | Error::CannotDecryptToken | Error::Unauthorized => {
+                StatusCode::UNAUTHORIZED
+            }
+            Error::Forbidden
+            | Error::CsrfTokenMismatch
+            | Error::EmailNotVerified
+            | Error::InsufficientScope(_)
+            | Error::ApiKeyNotAllowed => StatusCode::FORBIDDEN,
+            Error::NotFound | Error::ForeignKeyViolation(_) => StatusCode::NOT_FOUND,
+            Error::Conflict(_) | Error::QuestionClosed => StatusCode::CONFLICT,
+            Error::LoginLocked(_) | Error::RateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
+            Error::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+            Error::ArgonLibraryError(_)
+            | Error::DatabaseQueryError(_)
+            | Error::MigrationError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Error::ReqwestAPIError(_)
+            | Error::MiddlewareReqwestAPIError(_)
+            | Error::ClientError(_)
+            | Error::ServerError(_)
+            | Error::MailError(_) => StatusCode::BAD_GATEWAY,
+        }
+    }
+
+    /// Stable, machine readable name of the error. Clients branch on
+    /// this, so existing codes must never change
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::ParseError(_) => "parse_error",
+            Error::MissingParameters => "missing_parameters",
+            Error::InvalidParameter(_) => "invalid_parameter",
+            Error::ValidationError(_) => "validation_failed",
+            Error::WrongPassword => "wrong_credentials",
+            Error::CannotDecryptToken => "invalid_token",
+            Error::Unauthorized => "unauthorized",
+            Error::Forbidden => "forbidden",
+            Error::CsrfTokenMismatch => "csrf_token_mismatch",
+            Error::NotFound => "not_found",
+            Error::Conflict(_) => "conflict",
+            Error::ForeignKeyViolation(_) => "referenced_resource_not_found",
+            Error::LoginLocked(_) => "login_locked",
+            Error::RateLimited { .. } => "rate_limited",
+            Error::Unavailable => "unavailable",
+            Error::QuestionClosed => "question_closed",
+            Error::InvalidResetToken => "invalid_reset_token",
+            Error::InvalidVerificationToken => "invalid_verification_token",
+            Error::EmailNotVerified => "email_not_verified",
+            Error::InsufficientScope(_) => "insufficient_scope",
+            Error::ApiKeyNotAllowed => "api_key_not_allowed",
+            Error::ArgonLibraryError(_)
+            | Error::DatabaseQueryError(_)
+            | Error::MigrationError(_) => "internal_error",
+            Error::ReqwestAPIError(_)
+            | Error::MiddlewareReqwestAPIError(_)
+            | Error::ClientError(_)
+            | Error::ServerError(_)
+            | Error::MailError(_) => "upstream_error",
+        }
+    }
+
+    fn title(&self) -> &'static str {
+        match self {
+            Error::ParseError(_) => "Cannot parse parameter",
+            Error::MissingParameters => "Missing parameter",
+            Error::InvalidParameter(_) => "Invalid parameter",
+            Error::ValidationError(_) => "Invalid request body",
+            Error::WrongPassword => "Wrong E-Mail/Password combination",
+            Error::CannotDecryptToken => "Invalid token",
+            Error::Unauthorized => "Unauthorized",
+            Error::Forbidden => "Forbidden",
+            Error::CsrfTokenMismatch => "CSRF token mismatch",
+            Error::NotFound => "Not found",
+            Error::Conflict(_) => "Resource already exists",
+            Error::ForeignKeyViolation(_) => "Referenced resource not found",
+            Error::LoginLocked(_) => "Too many failed logins",
+            Error::RateLimited { .. } => "Too many requests",
+            Error::Unavailable => "Service unavailable",
+            Error::QuestionClosed => "Question closed",
+            Error::InvalidResetToken => "Invalid password reset token",
+            Error::InvalidVerificationToken => "Invalid verification token",
+            Error::EmailNotVerified => "Email not verified",
+            Error::InsufficientScope(_) => "Insufficient scope",
+            Error::ApiKeyNotAllowed => "API key not allowed",
+            Error::ArgonLibraryError(_)
+            | Error::DatabaseQueryError(_)
+            | Error::MigrationError(_) => "Internal Server Error",
+            Error::ReqwestAPIError(_)
+            | Error::MiddlewareReqwestAPIError(_)
+            | Error::ClientError(_)
+            | Error::ServerError(_)
+            | Error::MailError(_) => "External service error",
+        }
+    }
+
+    /// Seconds the client should wait before trying again
+    pub fn retry_after(&self) -> Option<u64> {
+        match self {
+            Error::LoginLocked(seconds) => Some(*seconds),
+            Error::RateLimited { retry_after, .. } => Some(*retry_after),
+            _ => None,
+        }
+    }
+
+    /// Human readable explanation. Server side errors and constraint
+    /// names don't leak, they only end up in the logs
+    fn detail(&self) -> String {
+        match self {
+            Error::Conflict(_) => {
+                "A resource with the same unique values exists already".to_string()
+            }
+            Error::ForeignKeyViolation(_) => "The referenced resource does not exist".to_string(),
+            _ if self.status().is_server_error() => {
+                "The request could not be processed, try again later".to_string()
+            }
+            _ => self.to_string(),
+        }
+    }
+}
+
 impl Reject for Error {}
 impl Reject for APILayerError {}
 
-const DUPLICATE_KEY: u32 = 23505;
+/// Header which carries the id of a request, in both directions
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// RFC 7807 problem details, the body of every error response
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Problem {
+    #[serde(rename = "type")]
+    pub problem_type: String,
+    pub title: String,
+    pub status: u16,
+    pub detail: String,
+    /// Stable error code, see `Error::code`
+    pub code: String,
+    pub request_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub errors: Option<Vec<FieldError>>,
+    /// Sent as the `Retry-After` header
+    #[serde(skip)]
+    pub retry_after: Option<u64>,
+    /// Sent as the `RateLimit-*` headers
+    #[serde(skip)]
+    pub quota: Option<Quota>,
+}
+
+/// State of a rate limit quota after a request, sent as the `RateLimit-*` headers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quota {
+    /// Requests a minute
+    pub limit: u32,
+    pub remaining: u32,
+    /// Seconds until the quota is full again
+    pub reset: u64,
+}
+
+impl Quota {
+    pub fn apply(&self, res: &mut Response) {
+        let headers = res.headers_mut();
+        headers.insert("ratelimit-limit", HeaderValue::from(self.limit));
+        headers.insert("ratelimit-remaining", HeaderValue::from(self.remaining));
+        headers.insert("ratelimit-reset", HeaderValue::from(self.reset));
+    }
+}
+
+/// A rejection of a request which used up part of its quota,
+/// so the problem carries the `RateLimit-*` headers as well
+#[derive(Debug)]
+pub struct QuotaRejection {
+    pub rejection: Rejection,
+    pub quota: Quota,
+}
+
+impl Reject for QuotaRejection {}
 
+/// The rejection without the quota wrapped around it
+fn cause(r: &Rejection) -> &Rejection {
+    match r.find::<QuotaRejection>() {
+        Some(limited) => &limited.rejection,
+        None => r,
+    }
+}
+
+impl Problem {
+    fn new(status: StatusCode, code: &str, title: &str, detail: String, request_id: &str) -> Self {
+        Problem {
+            problem_type: format!("/problems/{}", code.replace('_', "-")),
+            title: title.to_string(),
+            status: status.as_u16(),
+            detail,
+            code: code.to_string(),
+            request_id: request_id.to_string(),
+            errors: None,
+            retry_after: None,
+            quota: None,
+        }
+    }
+
+    /// Maps a rejection, either one of our own errors or one of warp's
+    /// built-in rejections, to its problem details
+    pub fn from_rejection(r: &Rejection, request_id: &str) -> Self {
+        if let Some(limited) = r.find::<QuotaRejection>() {
+            let mut problem = Problem::from_rejection(&limited.rejection, request_id);
+            problem.quota = Some(limited.quota);
+            problem
+        } else if let Some(error) = r.find::<Error>() {
+            let mut problem = Problem::new(
+                error.status(),
+                error.code(),
+                error.title(),
+                error.detail(),
+                request_id,
+            );
+            if let Error::ValidationError(errors) = error {
+                problem.errors = Some(errors.clone());
+            }
+            problem.retry_after = error.retry_after();
+            if let Error::RateLimited { limit, retry_after } = error {
+                problem.quota = Some(Quota {
+                    limit: *limit,
+                    remaining: 0,
+                    reset: *retry_after,
+                });
+            }
+            problem
+        } else if let Some(error) = r.find::<APILayerError>() {
+            Problem::new(
+                StatusCode::BAD_GATEWAY,
+                "upstream_error",
+                "External service error",
+                format!("External service answered with status {}", error.status),
+                request_id,
+            )
+        } else if let Some(error) = r.find::<CorsForbidden>() {
+            Problem::new(
+                StatusCode::FORBIDDEN,
+                "cors_forbidden",
+                "CORS request forbidden",
+                error.to_string(),
+                request_id,
+            )
+        } else if let Some(error) = r.find::<BodyDeserializeError>() {
+            Problem::new(
+                StatusCode::BAD_REQUEST,
+                "invalid_body",
+                "Cannot deserialize request body",
+                error.to_string(),
+                request_id,
+            )
+        } else if let Some(error) = r.find::<InvalidQuery>() {
+            Problem::new(
+                StatusCode::BAD_REQUEST,
+                "invalid_query",
+                "Invalid query string",
+                error.to_string(),
+                request_id,
+            )
+        } else if let Some(error) = r.find::<MissingHeader>() {
+            Problem::new(
+                StatusCode::BAD_REQUEST,
+                "missing_header",
+                "Missing request header",
+                error.to_string(),
+                request_id,
+            )
+        } else if let Some(error) = r.find::<UnsupportedMediaType>() {
+            Problem::new(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "unsupported_media_type",
+                "Unsupported media type",
+                error.to_string(),
+                request_id,
+            )
+        } else if let Some(error) = r.find::<PayloadTooLarge>() {
+            Problem::new(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "payload_too_large",
+                "Payload too large",
+                error.to_string(),
+                request_id,
+            )
+        } else if let Some(error) = r.find::<MethodNotAllowed>() {
+            Problem::new(
+                StatusCode::METHOD_NOT_ALLOWED,
+                "method_not_allowed",
+                "Method not allowed",
+                error.to_string(),
+                request_id,
+            )
+        } else {
+            Problem::new(
+                StatusCode::NOT_FOUND,
+                "route_not_found",
+                "Route not found",
+                "No route matches the requested path".to_string(),
+                request_id,
+            )
+        }
+    }
+
+    pub fn into_response(self) -> Response {
+        let status = StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        let mut res = warp::reply::with_status(warp::reply::json(&self), status).into_response();
+        res.headers_mut().insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("application/problem+json"),
+        );
+        if let Some(seconds) = self.retry_after {
+            res.headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from(seconds));
+        }
+        if let Some(quota) = self.quota {
+            quota.apply(&mut res);
+        }
+        res
+    }
+}
+
+/// Takes the request id from the `x-request-id` header if the client
+/// sent a sensible one, otherwise a new id is generated
+pub fn request_id() -> impl Filter<Extract = (String,), Error = Infallible> + Clone {
+    warp::header::optional::<String>(REQUEST_ID_HEADER)
+        .or(warp::any().map(|| None))
+        .unify()
+        .map(|id: Option<String>| {
+            id.filter(|id| {
+                !id.is_empty()
+                    && id.len() <= 64
+                    && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+            })
+            .unwrap_or_else(|| uuid::Uuid::new_v4().to_string())
+        })
+}
+
+/// Answers the rejection with a `application/problem+json` body
 #[instrument]
-pub async fn return_error(r: Rejection) -> Result<impl Reply, Rejection> {
-    if let Some(crate::Error::DatabaseQueryError(e)) = r.find() {
-        event!(Level::ERROR, "Database query error");
-
-        match e {
-            sqlx::Error::Database(err) => {
-                if err.code().unwrap().parse::<u32>().unwrap() == DUPLICATE_KEY {
-                    Ok(warp::reply::with_status(
-                        "Account already exsists".to_string(),
-                        StatusCode::UNPROCESSABLE_ENTITY,
-                    ))
-                } else {
-                    Ok(warp::reply::with_status(
-                        "Cannot update data".to_string(),
-                        StatusCode::UNPROCESSABLE_ENTITY,
-                    ))
+pub async fn return_error(request_id: String, r: Rejection) -> Result<Response, Infallible> {
+    let problem = Problem::from_rejection(&r, &request_id);
+
+    match cause(&r).find::<Error>() {
+        Some(error) if problem.status >= 500 => {
+            event!(Level::ERROR, request_id = %request_id, "{:?}", error)
+        }
+        Some(error) => event!(Level::WARN, request_id = %request_id, "{}", error),
+        None => event!(
+            Level::WARN,
+            request_id = %request_id,
+            code = %problem.code,
+            "{}",
+            problem.detail
+        ),
+    }
+
+    Ok(problem.into_response())
+}
+
+/// Wraps all routes, so every response carries the request id and
+/// every rejection is answered by `return_error`
+pub fn with_problem_details<F, R>(
+    routes: F,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone
+where
+    F: Filter<Extract = (R,), Error = Rejection> + Clone + Send + Sync + 'static,
+    R: Reply,
+{
+    request_id()
+        .and(
+            routes
+                .map(|reply: R| Ok::<_, Rejection>(reply.into_response()))
+                .or_else(|r: Rejection| async move { Ok::<_, Rejection>((Err(r),)) }),
+        )
+        .and_then(
+            |request_id: String, res: Result<Response, Rejection>| async move {
+                let mut res = match res {
+                    Ok(res) => res,
+                    Err(r) => return_error(request_id.clone(), r).await?,
+                };
+                if let Ok(value) = HeaderValue::from_str(&request_id) {
+                    res.headers_mut().insert(REQUEST_ID_HEADER, value);
                 }
+                Ok::<_, Infallible>(res)
             },
-            _ => { 
-                Ok(warp::reply::with_status(
-                    "Cannot update data".to_string(),
-                    StatusCode::UNPROCESSABLE_ENTITY,
-                ))
-            }
-        }
-    } else if let Some(crate::Error::ReqwestAPIError(e)) = r.find() {
-        event!(Level::ERROR, "{}", e);
-        Ok(warp::reply::with_status(
-            "Internal Server Error".to_string(),
-            StatusCode::INTERNAL_SERVER_ERROR,
-        ))
-    } else if let Some(crate::Error::Unauthorized) = r.find() {
-        event!(Level::ERROR, "Not matching account id");
-        Ok(warp::reply::with_status(
-            "No permission to change underlying resource".to_string(),
-            StatusCode::UNAUTHORIZED,
-        ))
-    } else if let Some(crate::Error::WrongPassword) = r.find() {
-        event!(Level::ERROR, "Entered wrong password");
-        Ok(warp::reply::with_status(
-            "Wrong E-Mail/Password combination".to_string(),
-            StatusCode::UNAUTHORIZED,
-        ))
-    } else if let Some(crate::Error::MiddlewareReqwestAPIError(e)) = r.find() {
-        event!(Level::ERROR, "{}", e);
-        Ok(warp::reply::with_status(
-            "Internal Server Error".to_string(),
-            StatusCode::INTERNAL_SERVER_ERROR,
-        ))
-    } else if let Some(crate::Error::ClientError(e)) = r.find() {
-        event!(Level::ERROR, "{}", e);
-        Ok(warp::reply::with_status(
-            "Internal Server Error".to_string(),
-            StatusCode::INTERNAL_SERVER_ERROR,
-        ))
-    } else if let Some(crate::Error::ServerError(e)) = r.find() {
-        event!(Level::ERROR, "{}", e);
-        Ok(warp::reply::with_status(
-            "Internal Server Error".to_string(),
-            StatusCode::INTERNAL_SERVER_ERROR,
-        ))
-    } else if let Some(error) = r.find::<CorsForbidden>() {
-        event!(Level::ERROR, "CORS forbidden error: {}", error);
-        Ok(warp::reply::with_status(
-            error.to_string(),
-            StatusCode::FORBIDDEN,
-        ))
-    } else if let Some(error) = r.find::<BodyDeserializeError>() {
-        event!(Level::ERROR, "Cannot deserizalize request body: {}", error);
-        Ok(warp::reply::with_status(
-            error.to_string(),
-            StatusCode::UNPROCESSABLE_ENTITY,
-        ))
-    } else if let Some(error) = r.find::<Error>() {
-        event!(Level::ERROR, "{}", error);
-        Ok(warp::reply::with_status(
-            error.to_string(),
-            StatusCode::UNPROCESSABLE_ENTITY,
-        )) 
-    } else {
-        event!(Level::WARN, "Requested route was not found");
-        Ok(warp::reply::with_status(
-            "Route not found".to_string(),
-            StatusCode::NOT_FOUND,
-        ))
+        )
+}
+
+#[cfg(test)]
+mod problem_tests {
+    use super::{Error, FieldError, Problem, Quota, QuotaRejection};
+
+    #[test]
+    fn error_problem() {
+        let rejection = warp::reject::custom(Error::NotFound);
+        let problem = Problem::from_rejection(&rejection, "abc");
+
+        assert_eq!(problem.status, 404);
+        assert_eq!(problem.code, "not_found");
+        assert_eq!(problem.problem_type, "/problems/not-found");
+        assert_eq!(problem.request_id, "abc");
+        assert_eq!(problem.errors, None);
+    }
+
+    #[test]
+    fn validation_problem_lists_fields() {
+        let errors = vec![FieldError {
+            field: "title".to_string(),
+            message: "must not be empty".to_string(),
+        }];
+        let rejection = warp::reject::custom(Error::ValidationError(errors.clone()));
+        let problem = Problem::from_rejection(&rejection, "abc");
+
+        assert_eq!(problem.status, 400);
+        assert_eq!(problem.code, "validation_failed");
+        assert_eq!(problem.errors, Some(errors));
+    }
+
+    #[test]
+    fn route_not_found() {
+        let problem = Problem::from_rejection(&warp::reject::not_found(), "abc");
+
+        assert_eq!(problem.status, 404);
+        assert_eq!(problem.code, "route_not_found");
+    }
+
+    #[test]
+    fn server_errors_hide_cause() {
+        let error = Error::DatabaseQueryError(sqlx::Error::RowNotFound);
+
+        assert_eq!(error.status().as_u16(), 500);
+        assert_eq!(
+            error.detail(),
+            "The request could not be processed, try again later"
+        );
+    }
+
+    #[test]
+    fn constraint_violations() {
+        let conflict = Error::Conflict("accounts_email_key".to_string());
+        assert_eq!(conflict.status().as_u16(), 409);
+        assert!(!conflict.detail().contains("accounts_email_key"));
+
+        let missing = Error::ForeignKeyViolation("answers_question_fkey".to_string());
+        assert_eq!(missing.status().as_u16(), 404);
+        assert_eq!(Error::Unavailable.status().as_u16(), 503);
+    }
+
+    #[test]
+    fn login_locked_has_retry_after() {
+        let rejection = warp::reject::custom(Error::LoginLocked(30));
+        let res = Problem::from_rejection(&rejection, "abc").into_response();
+
+        assert_eq!(res.status().as_u16(), 429);
+        assert_eq!(res.headers().get("retry-after").unwrap(), "30");
+    }
+
+    #[test]
+    fn rejections_keep_their_quota() {
+        let rejection = warp::reject::custom(QuotaRejection {
+            rejection: warp::reject::custom(Error::NotFound),
+            quota: Quota {
+                limit: 60,
+                remaining: 41,
+                reset: 19,
+            },
+        });
+        let problem = Problem::from_rejection(&rejection, "abc");
+        assert_eq!(problem.code, "not_found");
+
+        let res = problem.into_response();
+        assert_eq!(res.status().as_u16(), 404);
+        assert_eq!(res.headers().get("ratelimit-remaining").unwrap(), "41");
+        assert_eq!(res.headers().get("ratelimit-reset").unwrap(), "19");
     }
 }
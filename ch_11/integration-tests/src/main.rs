@@ -1,8 +1,11 @@
-use std::process::Command;
 use std::io::{self, Write};
+use std::process::Command;
 
 use futures_util::future::FutureExt;
 
+use rust_web_dev::types::account::{AccountId, LoginKey, Role};
+use rust_web_dev::types::pagination::Cursor;
+use rust_web_dev::types::moderation::{ModerationAction, ModerationTarget, NewModerationLogEntry};
 use rust_web_dev::{config, handle_errors, oneshot, setup_store};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
@@ -28,7 +31,10 @@ struct QuestionAnswer {
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
-struct Token(String);
+struct Token {
+    access_token: String,
+    refresh_token: String,
+}
 
 #[tokio::main]
 async fn main() -> Result<(), handle_errors::Error> {
@@ -39,7 +45,10 @@ async fn main() -> Result<(), handle_errors::Error> {
         .arg("database")
         .arg("drop")
         .arg("--database-url")
-        .arg(format!("postgres://{}:{}/{}", config.db_host, config.db_port, config.db_name))
+        .arg(format!(
+            "postgres://{}:{}/{}",
+            config.db_host, config.db_port, config.db_name
+        ))
         .arg("-y")
         .output()
         .expect("sqlx command failed to start");
@@ -47,12 +56,15 @@ async fn main() -> Result<(), handle_errors::Error> {
     io::stdout().write_all(&s.stderr).unwrap();
 
     let s = Command::new("sqlx")
-            .arg("database")
-            .arg("create")
-            .arg("--database-url")
-            .arg(format!("postgres://{}:{}/{}", config.db_host, config.db_port, config.db_name))
-            .output()
-            .expect("sqlx command failed to start");
+        .arg("database")
+        .arg("create")
+        .arg("--database-url")
+        .arg(format!(
+            "postgres://{}:{}/{}",
+            config.db_host, config.db_port, config.db_name
+        ))
+        .output()
+        .expect("sqlx command failed to start");
 
     // Exdcute DB commands to drop and create a new test database
     io::stdout().write_all(&s.stderr).unwrap();
@@ -61,7 +73,7 @@ async fn main() -> Result<(), handle_errors::Error> {
     let store = setup_store(&config).await?;
 
     // start the server and listen for a sender signal to shut it down
-    let handler = oneshot(store).await;
+    let handler = oneshot(store.clone()).await;
 
     // create a test user to use throughout the tests
     let u = User {
@@ -72,7 +84,9 @@ async fn main() -> Result<(), handle_errors::Error> {
     let token;
 
     print!("Running register_new_user...");
-    let result = std::panic::AssertUnwindSafe(register_new_user(&u)).catch_unwind().await;
+    let result = std::panic::AssertUnwindSafe(register_new_user(&u))
+        .catch_unwind()
+        .await;
     match result {
         Ok(_) => println!("✓"),
         Err(_) => {
@@ -86,15 +100,295 @@ async fn main() -> Result<(), handle_errors::Error> {
         Ok(t) => {
             token = t;
             println!("✓");
-        },
+        }
         Err(_) => {
             let _ = handler.sender.send(1);
             std::process::exit(1);
         }
     }
-    
+
     print!("Running post_question...");
-    match std::panic::AssertUnwindSafe(post_question(token)).catch_unwind().await {
+    match std::panic::AssertUnwindSafe(post_question(token.clone()))
+        .catch_unwind()
+        .await
+    {
+        Ok(_) => println!("✓"),
+        Err(_) => {
+            let _ = handler.sender.send(1);
+            std::process::exit(1);
+        }
+    }
+
+    print!("Running delete_question_hides_answers...");
+    match std::panic::AssertUnwindSafe(delete_question_hides_answers(token.clone()))
+        .catch_unwind()
+        .await
+    {
+        Ok(_) => println!("✓"),
+        Err(_) => {
+            let _ = handler.sender.send(1);
+            std::process::exit(1);
+        }
+    }
+
+    print!("Running get_question_with_answers...");
+    match std::panic::AssertUnwindSafe(get_question_with_answers(token.clone()))
+        .catch_unwind()
+        .await
+    {
+        Ok(_) => println!("✓"),
+        Err(_) => {
+            let _ = handler.sender.send(1);
+            std::process::exit(1);
+        }
+    }
+
+    print!("Running list_edit_and_delete_answers...");
+    match std::panic::AssertUnwindSafe(list_edit_and_delete_answers(token.clone()))
+        .catch_unwind()
+        .await
+    {
+        Ok(_) => println!("✓"),
+        Err(_) => {
+            let _ = handler.sender.send(1);
+            std::process::exit(1);
+        }
+    }
+
+    print!("Running accept_answer...");
+    match std::panic::AssertUnwindSafe(accept_answer(token.clone()))
+        .catch_unwind()
+        .await
+    {
+        Ok(_) => println!("✓"),
+        Err(_) => {
+            let _ = handler.sender.send(1);
+            std::process::exit(1);
+        }
+    }
+
+    print!("Running search_questions...");
+    match std::panic::AssertUnwindSafe(search_questions(token.clone()))
+        .catch_unwind()
+        .await
+    {
+        Ok(_) => println!("✓"),
+        Err(_) => {
+            let _ = handler.sender.send(1);
+            std::process::exit(1);
+        }
+    }
+
+    print!("Running comment_on_questions_and_answers...");
+    match std::panic::AssertUnwindSafe(comment_on_questions_and_answers(token.clone()))
+        .catch_unwind()
+        .await
+    {
+        Ok(_) => println!("✓"),
+        Err(_) => {
+            let _ = handler.sender.send(1);
+            std::process::exit(1);
+        }
+    }
+
+    print!("Running sort_by_score...");
+    match std::panic::AssertUnwindSafe(sort_by_score(token.clone()))
+        .catch_unwind()
+        .await
+    {
+        Ok(_) => println!("✓"),
+        Err(_) => {
+            let _ = handler.sender.send(1);
+            std::process::exit(1);
+        }
+    }
+
+    // The second user, registered while listing answers, becomes a moderator
+    store
+        .clone()
+        .set_role(
+            &AccountId(2),
+            Role::Moderator,
+            NewModerationLogEntry {
+                moderator_id: AccountId(1),
+                action: ModerationAction::ChangeRole,
+                target: ModerationTarget::Account(AccountId(2)),
+                reason: None,
+            },
+        )
+        .await?;
+
+    print!("Running moderation_is_logged...");
+    match std::panic::AssertUnwindSafe(moderation_is_logged(token.clone()))
+        .catch_unwind()
+        .await
+    {
+        Ok(_) => println!("✓"),
+        Err(_) => {
+            let _ = handler.sender.send(1);
+            std::process::exit(1);
+        }
+    }
+
+    print!("Running login_lockout...");
+    match std::panic::AssertUnwindSafe(login_lockout(token.clone()))
+        .catch_unwind()
+        .await
+    {
+        Ok(_) => println!("✓"),
+        Err(_) => {
+            let _ = handler.sender.send(1);
+            std::process::exit(1);
+        }
+    }
+
+    print!("Running login_clears_failures...");
+    match std::panic::AssertUnwindSafe(login_clears_failures(token.clone()))
+        .catch_unwind()
+        .await
+    {
+        Ok(_) => println!("✓"),
+        Err(_) => {
+            let _ = handler.sender.send(1);
+            std::process::exit(1);
+        }
+    }
+
+    print!("Running introspect_tokens...");
+    match std::panic::AssertUnwindSafe(introspect_tokens(token.clone()))
+        .catch_unwind()
+        .await
+    {
+        Ok(_) => println!("✓"),
+        Err(_) => {
+            let _ = handler.sender.send(1);
+            std::process::exit(1);
+        }
+    }
+
+    print!("Running rejections_carry_rate_limit...");
+    match std::panic::AssertUnwindSafe(rejections_carry_rate_limit(token.clone()))
+        .catch_unwind()
+        .await
+    {
+        Ok(_) => println!("✓"),
+        Err(_) => {
+            let _ = handler.sender.send(1);
+            std::process::exit(1);
+        }
+    }
+
+    print!("Running export_and_delete_account...");
+    match std::panic::AssertUnwindSafe(export_and_delete_account(token.clone()))
+        .catch_unwind()
+        .await
+    {
+        Ok(_) => println!("✓"),
+        Err(_) => {
+            let _ = handler.sender.send(1);
+            std::process::exit(1);
+        }
+    }
+
+    print!("Running emails_ignore_case...");
+    match std::panic::AssertUnwindSafe(emails_ignore_case(token.clone()))
+        .catch_unwind()
+        .await
+    {
+        Ok(_) => println!("✓"),
+        Err(_) => {
+            let _ = handler.sender.send(1);
+            std::process::exit(1);
+        }
+    }
+
+    print!("Running invalid_pagination...");
+    match std::panic::AssertUnwindSafe(invalid_pagination(token.clone()))
+        .catch_unwind()
+        .await
+    {
+        Ok(_) => println!("✓"),
+        Err(_) => {
+            let _ = handler.sender.send(1);
+            std::process::exit(1);
+        }
+    }
+
+    print!("Running answers_are_paged...");
+    match std::panic::AssertUnwindSafe(answers_are_paged(token.clone()))
+        .catch_unwind()
+        .await
+    {
+        Ok(_) => println!("✓"),
+        Err(_) => {
+            let _ = handler.sender.send(1);
+            std::process::exit(1);
+        }
+    }
+
+    print!("Running comments_are_paged...");
+    match std::panic::AssertUnwindSafe(comments_are_paged(token.clone()))
+        .catch_unwind()
+        .await
+    {
+        Ok(_) => println!("✓"),
+        Err(_) => {
+            let _ = handler.sender.send(1);
+            std::process::exit(1);
+        }
+    }
+
+    print!("Running tags_limit...");
+    match std::panic::AssertUnwindSafe(tags_limit(token.clone()))
+        .catch_unwind()
+        .await
+    {
+        Ok(_) => println!("✓"),
+        Err(_) => {
+            let _ = handler.sender.send(1);
+            std::process::exit(1);
+        }
+    }
+
+    print!("Running api_keys_count_for_account...");
+    match std::panic::AssertUnwindSafe(api_keys_count_for_account(token.clone()))
+        .catch_unwind()
+        .await
+    {
+        Ok(_) => println!("✓"),
+        Err(_) => {
+            let _ = handler.sender.send(1);
+            std::process::exit(1);
+        }
+    }
+
+    print!("Running password_change_revokes_keys...");
+    match std::panic::AssertUnwindSafe(password_change_revokes_keys(token.clone()))
+        .catch_unwind()
+        .await
+    {
+        Ok(_) => println!("✓"),
+        Err(_) => {
+            let _ = handler.sender.send(1);
+            std::process::exit(1);
+        }
+    }
+
+    // Locking an address out takes more attempts than its rate limit allows
+    let locked_ip = LoginKey::Ip([127, 0, 0, 12].into());
+    store.add_login_attempt(&locked_ip).await?;
+    store
+        .lock_login(
+            &locked_ip,
+            (chrono::Utc::now() + chrono::Duration::minutes(5)).naive_utc(),
+        )
+        .await?;
+
+    print!("Running locked_ip_spares_emails...");
+    match std::panic::AssertUnwindSafe(locked_ip_spares_emails(token.clone()))
+        .catch_unwind()
+        .await
+    {
         Ok(_) => println!("✓"),
         Err(_) => {
             let _ = handler.sender.send(1);
@@ -119,7 +413,6 @@ async fn register_new_user(user: &User) {
         .await;
 
     assert_eq!(res.unwrap(), "Account added".to_string());
-
 }
 
 async fn login(user: User) -> Token {
@@ -133,10 +426,7 @@ async fn login(user: User) -> Token {
 
     assert_eq!(res.status(), 200);
 
-    res
-        .json::<Token>()
-        .await
-        .unwrap()
+    res.json::<Token>().await.unwrap()
 }
 
 async fn post_question(token: Token) {
@@ -148,7 +438,7 @@ async fn post_question(token: Token) {
     let client = reqwest::Client::new();
     let res = client
         .post("http://localhost:3030/questions")
-        .header("Authorization", token.0)
+        .header("Authorization", token.access_token)
         .json(&q)
         .send()
         .await
@@ -160,3 +450,955 @@ async fn post_question(token: Token) {
     assert_eq!(res.id, 1);
     assert_eq!(res.title, q.title);
 }
+
+async fn add_question(token: &Token, title: &str) -> QuestionAnswer {
+    let client = reqwest::Client::new();
+    client
+        .post("http://localhost:3030/questions")
+        .header("Authorization", &token.access_token)
+        .json(&Question {
+            title: title.to_string(),
+            content: "How can I test?".to_string(),
+        })
+        .send()
+        .await
+        .unwrap()
+        .json::<QuestionAnswer>()
+        .await
+        .unwrap()
+}
+
+async fn add_answer(token: &Token, question_id: i32) -> i32 {
+    let client = reqwest::Client::new();
+    let res = client
+        .post("http://localhost:3030/answers")
+        .header("Authorization", &token.access_token)
+        .form(&[
+            ("content", "Like this"),
+            ("questionId", &question_id.to_string()),
+        ])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+
+    let answers = get_answers(question_id).await;
+    answers.last().unwrap()["id"].as_i64().unwrap() as i32
+}
+
+async fn get_answers(question_id: i32) -> Vec<Value> {
+    let res = reqwest::get(format!(
+        "http://localhost:3030/questions/{}/answers",
+        question_id
+    ))
+    .await
+    .unwrap();
+    assert_eq!(res.status(), 200);
+
+    res.json::<Vec<Value>>().await.unwrap()
+}
+
+async fn delete_question_hides_answers(token: Token) {
+    let question = add_question(&token, "Deleted Question").await;
+    let answer_id = add_answer(&token, question.id).await;
+
+    let client = reqwest::Client::new();
+    let res = client
+        .delete(format!("http://localhost:3030/questions/{}", question.id))
+        .header("Authorization", &token.access_token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+
+    // Everything below the question is gone with it
+    for path in [
+        format!("questions/{}", question.id),
+        format!("questions/{}/answers", question.id),
+        format!("questions/{}/comments", question.id),
+        format!("questions/{}/revisions", question.id),
+        format!("questions/{}/revisions/diff?from=1&to=1", question.id),
+        format!("answers/{}/comments", answer_id),
+    ] {
+        let res = reqwest::get(format!("http://localhost:3030/{}", path))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 404, "GET /{}", path);
+    }
+
+    let res = client
+        .put(format!("http://localhost:3030/answers/{}", answer_id))
+        .header("Authorization", &token.access_token)
+        .json(&serde_json::json!({ "content": "Edited" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 404);
+
+    let res = client
+        .put(format!("http://localhost:3030/answers/{}/vote", answer_id))
+        .header("Authorization", &token.access_token)
+        .json(&serde_json::json!({ "direction": "up" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 404);
+
+    let res = client
+        .put(format!(
+            "http://localhost:3030/questions/{}/accepted-answer",
+            question.id
+        ))
+        .header("Authorization", &token.access_token)
+        .json(&serde_json::json!({ "answer_id": answer_id }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 404);
+
+    // Restoring the question brings its answers back
+    let res = client
+        .post(format!(
+            "http://localhost:3030/questions/{}/restore",
+            question.id
+        ))
+        .header("Authorization", &token.access_token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+
+    let answers = get_answers(question.id).await;
+    assert_eq!(answers.len(), 1);
+    assert_eq!(answers[0]["id"], answer_id);
+}
+
+async fn get_question_with_answers(token: Token) {
+    let question = add_question(&token, "Detailed Question").await;
+    let answer_id = add_answer(&token, question.id).await;
+
+    let res = reqwest::get(format!("http://localhost:3030/questions/{}", question.id))
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+
+    let detail = res.json::<Value>().await.unwrap();
+    assert_eq!(detail["id"], question.id);
+    assert_eq!(detail["title"], question.title);
+    assert!(detail["account_id"].is_number());
+    assert!(detail["created_on"].is_string());
+    assert_eq!(detail["answers"][0]["id"], answer_id);
+    assert_eq!(detail["answers"][0]["account_id"], detail["account_id"]);
+
+    let res = reqwest::get("http://localhost:3030/questions/9999")
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 404);
+}
+
+async fn list_edit_and_delete_answers(token: Token) {
+    let question = add_question(&token, "Answered Question").await;
+    let first = add_answer(&token, question.id).await;
+    let second = add_answer(&token, question.id).await;
+
+    let res = reqwest::get(format!(
+        "http://localhost:3030/questions/{}/answers?limit=1&offset=1",
+        question.id
+    ))
+    .await
+    .unwrap()
+    .json::<Vec<Value>>()
+    .await
+    .unwrap();
+    assert_eq!(res.len(), 1);
+    assert_eq!(res[0]["id"], second);
+
+    let res = reqwest::get("http://localhost:3030/questions/9999/answers")
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 404);
+
+    // Only the author may change an answer
+    let other = User {
+        email: "other@email.com".to_string(),
+        password: "password".to_string(),
+    };
+    register_new_user(&other).await;
+    let other_token = login(other).await;
+
+    let client = reqwest::Client::new();
+    let res = client
+        .put(format!("http://localhost:3030/answers/{}", first))
+        .header("Authorization", &other_token.access_token)
+        .json(&serde_json::json!({ "content": "Not mine" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 403);
+
+    let res = client
+        .put(format!("http://localhost:3030/answers/{}", first))
+        .header("Authorization", &token.access_token)
+        .json(&serde_json::json!({ "content": "Edited" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+    assert_eq!(res.json::<Value>().await.unwrap()["content"], "Edited");
+
+    let res = client
+        .delete(format!("http://localhost:3030/answers/{}", first))
+        .header("Authorization", &other_token.access_token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 403);
+
+    let res = client
+        .delete(format!("http://localhost:3030/answers/{}", first))
+        .header("Authorization", &token.access_token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+
+    let answers = get_answers(question.id).await;
+    assert_eq!(answers.len(), 1);
+    assert_eq!(answers[0]["id"], second);
+
+    let res = client
+        .delete(format!("http://localhost:3030/answers/{}", first))
+        .header("Authorization", &token.access_token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 404);
+}
+
+async fn accept_answer(token: Token) {
+    let question = add_question(&token, "Solved Question").await;
+    let first = add_answer(&token, question.id).await;
+    let second = add_answer(&token, question.id).await;
+
+    let client = reqwest::Client::new();
+    let accept = |answer_id: Option<i32>| {
+        client
+            .put(format!(
+                "http://localhost:3030/questions/{}/accepted-answer",
+                question.id
+            ))
+            .header("Authorization", &token.access_token)
+            .json(&serde_json::json!({ "answer_id": answer_id }))
+            .send()
+    };
+
+    let res = accept(Some(second)).await.unwrap();
+    assert_eq!(res.status(), 200);
+    assert_eq!(
+        res.json::<Value>().await.unwrap()["accepted_answer"],
+        second
+    );
+
+    // The accepted answer is listed first
+    let answers = get_answers(question.id).await;
+    assert_eq!(answers[0]["id"], second);
+    assert_eq!(answers[1]["id"], first);
+
+    // The choice can be changed and cleared
+    let res = accept(Some(first)).await.unwrap();
+    assert_eq!(res.json::<Value>().await.unwrap()["accepted_answer"], first);
+    let res = accept(None).await.unwrap();
+    assert_eq!(
+        res.json::<Value>().await.unwrap()["accepted_answer"],
+        Value::Null
+    );
+
+    // Answers of other questions can't be accepted
+    let other_question = add_question(&token, "Other Question").await;
+    let other_answer = add_answer(&token, other_question.id).await;
+    let res = accept(Some(other_answer)).await.unwrap();
+    assert_eq!(res.status(), 404);
+}
+
+async fn search_questions(token: Token) {
+    let client = reqwest::Client::new();
+    let res = client
+        .post("http://localhost:3030/questions")
+        .header("Authorization", &token.access_token)
+        .json(&Question {
+            title: "Borrow checker".to_string(),
+            content: "Why does the borrow checker reject my closure?".to_string(),
+        })
+        .send()
+        .await
+        .unwrap()
+        .json::<QuestionAnswer>()
+        .await
+        .unwrap();
+
+    let page = reqwest::get("http://localhost:3030/questions?q=closures&limit=5")
+        .await
+        .unwrap()
+        .json::<Value>()
+        .await
+        .unwrap();
+    let items = page["items"].as_array().unwrap();
+    assert_eq!(items.len(), 1);
+    assert_eq!(items[0]["id"], res.id);
+    assert!(items[0]["rank"].as_f64().unwrap() > 0.0);
+    assert!(items[0]["snippet"]
+        .as_str()
+        .unwrap()
+        .contains("<mark>closure</mark>"));
+
+    let page = reqwest::get("http://localhost:3030/questions?q=lifetimes")
+        .await
+        .unwrap()
+        .json::<Value>()
+        .await
+        .unwrap();
+    assert!(page["items"].as_array().unwrap().is_empty());
+}
+
+async fn comment_on_questions_and_answers(token: Token) {
+    let question = add_question(&token, "Commented Question").await;
+    let answer_id = add_answer(&token, question.id).await;
+
+    let client = reqwest::Client::new();
+    let mut ids = Vec::new();
+    for path in [
+        format!("questions/{}/comments", question.id),
+        format!("answers/{}/comments", answer_id),
+    ] {
+        let res = client
+            .post(format!("http://localhost:3030/{}", path))
+            .header("Authorization", &token.access_token)
+            .json(&serde_json::json!({ "content": "What do you mean?" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 200);
+        ids.push(res.json::<Value>().await.unwrap()["id"].clone());
+
+        let comments = reqwest::get(format!("http://localhost:3030/{}", path))
+            .await
+            .unwrap()
+            .json::<Vec<Value>>()
+            .await
+            .unwrap();
+        assert_eq!(comments.len(), 1);
+        assert_eq!(comments[0]["content"], "What do you mean?");
+    }
+
+    let res = client
+        .put(format!("http://localhost:3030/comments/{}", ids[0]))
+        .header("Authorization", &token.access_token)
+        .json(&serde_json::json!({ "content": "Never mind" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+    let comment = res.json::<Value>().await.unwrap();
+    assert_eq!(comment["content"], "Never mind");
+    assert!(comment["updated_on"].is_string());
+
+    let res = client
+        .delete(format!("http://localhost:3030/comments/{}", ids[1]))
+        .header("Authorization", &token.access_token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+
+    let comments = reqwest::get(format!(
+        "http://localhost:3030/answers/{}/comments",
+        answer_id
+    ))
+    .await
+    .unwrap()
+    .json::<Vec<Value>>()
+    .await
+    .unwrap();
+    assert!(comments.is_empty());
+
+    let res = client
+        .post("http://localhost:3030/questions/9999/comments")
+        .header("Authorization", &token.access_token)
+        .json(&serde_json::json!({ "content": "Anyone?" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 404);
+}
+
+async fn vote(token: &Token, question_id: i32, direction: Option<&str>) -> Value {
+    let client = reqwest::Client::new();
+    let url = format!("http://localhost:3030/questions/{}/vote", question_id);
+    let req = match direction {
+        Some(direction) => client
+            .put(url)
+            .json(&serde_json::json!({ "direction": direction })),
+        None => client.delete(url),
+    };
+    let res = req
+        .header("Authorization", &token.access_token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+
+    res.json::<Value>().await.unwrap()
+}
+
+async fn sort_by_score(token: Token) {
+    let top = add_question(&token, "Top Question").await;
+    let bottom = add_question(&token, "Bottom Question").await;
+
+    assert_eq!(vote(&token, top.id, Some("up")).await["score"], 1);
+    assert_eq!(vote(&token, bottom.id, Some("down")).await["score"], -1);
+
+    let page = reqwest::get("http://localhost:3030/questions?sort=score&limit=1")
+        .await
+        .unwrap()
+        .json::<Value>()
+        .await
+        .unwrap();
+    assert_eq!(page["items"][0]["id"], top.id);
+    assert_eq!(page["items"][0]["score"], 1);
+    let cursor = page["next_cursor"].as_str().unwrap().to_string();
+
+    let page = reqwest::get(format!(
+        "http://localhost:3030/questions?sort=score&limit=1&cursor={}",
+        cursor
+    ))
+    .await
+    .unwrap()
+    .json::<Value>()
+    .await
+    .unwrap();
+    assert_eq!(page["items"][0]["score"], 0);
+
+    // The cursor only works with the sort it came from
+    let res = reqwest::get(format!(
+        "http://localhost:3030/questions?sort=newest&cursor={}",
+        cursor
+    ))
+    .await
+    .unwrap();
+    assert_eq!(res.status(), 400);
+
+    // Changed and retracted votes keep the score up to date
+    assert_eq!(vote(&token, top.id, Some("down")).await["score"], -1);
+    assert_eq!(vote(&token, top.id, None).await["score"], 0);
+    let detail = reqwest::get(format!("http://localhost:3030/questions/{}", top.id))
+        .await
+        .unwrap()
+        .json::<Value>()
+        .await
+        .unwrap();
+    assert_eq!(detail["score"], 0);
+}
+
+async fn moderation_is_logged(token: Token) {
+    let moderator = login(User {
+        email: "other@email.com".to_string(),
+        password: "password".to_string(),
+    })
+    .await;
+    let question = add_question(&token, "Moderated question").await;
+
+    let client = reqwest::Client::new();
+    let close = |token: &Token| {
+        client
+            .put(format!(
+                "http://localhost:3030/questions/{}/close",
+                question.id
+            ))
+            .header("Authorization", &token.access_token)
+            .json(&serde_json::json!({ "reason": "Off topic" }))
+            .send()
+    };
+
+    // Owners closing their own question aren't logged
+    assert_eq!(close(&token).await.unwrap().status(), 200);
+    let res = client
+        .delete(format!(
+            "http://localhost:3030/questions/{}/close",
+            question.id
+        ))
+        .header("Authorization", &token.access_token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+    assert_eq!(close(&moderator).await.unwrap().status(), 200);
+
+    let log = client
+        .get("http://localhost:3030/moderation-log")
+        .header("Authorization", &moderator.access_token)
+        .send()
+        .await
+        .unwrap()
+        .json::<Vec<Value>>()
+        .await
+        .unwrap();
+    let entries: Vec<&Value> = log
+        .iter()
+        .filter(|entry| entry["question_id"] == question.id)
+        .collect();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0]["action"], "close_question");
+    assert_eq!(entries[0]["moderator_id"], 2);
+    assert_eq!(entries[0]["reason"], "Off topic");
+
+    // A deleted question is missing, even if it was closed before
+    let res = client
+        .delete(format!("http://localhost:3030/questions/{}", question.id))
+        .header("Authorization", &token.access_token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+    let res = client
+        .post("http://localhost:3030/answers")
+        .header("Authorization", &token.access_token)
+        .form(&[
+            ("content", "Too late"),
+            ("questionId", &question.id.to_string()),
+        ])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 404);
+}
+
+/// Client with its own rate limit and login throttle, told apart by
+/// the loopback address it connects from
+fn client_from(ip: [u8; 4]) -> reqwest::Client {
+    reqwest::Client::builder()
+        .local_address(std::net::IpAddr::from(ip))
+        .build()
+        .unwrap()
+}
+
+async fn try_login(client: &reqwest::Client, email: &str, password: &str) -> (u16, Value) {
+    let res = client
+        .post("http://127.0.0.1:3030/login")
+        .json(&User {
+            email: email.to_string(),
+            password: password.to_string(),
+        })
+        .send()
+        .await
+        .unwrap();
+    let status = res.status().as_u16();
+
+    (status, res.json::<Value>().await.unwrap_or(Value::Null))
+}
+
+async fn login_lockout(_token: Token) {
+    let client = client_from([127, 0, 0, 2]);
+    let res = client
+        .post("http://127.0.0.1:3030/registration")
+        .json(&User {
+            email: "locked@email.com".to_string(),
+            password: "password".to_string(),
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+
+    for _ in 0..4 {
+        let (status, _) = try_login(&client, "locked@email.com", "wrong").await;
+        assert_eq!(status, 401);
+    }
+
+    // The last allowed attempt locks the email while it is verified,
+    // attempts made meanwhile don't get to guess
+    let results = futures_util::future::join_all(
+        (0..3).map(|_| try_login(&client, "locked@email.com", "wrong")),
+    )
+    .await;
+    let wrong = results.iter().filter(|(status, _)| *status == 401).count();
+    let locked = results
+        .iter()
+        .filter(|(status, body)| *status == 429 && body["code"] == "login_locked")
+        .count();
+    assert_eq!((wrong, locked), (1, 2));
+
+    let (status, body) = try_login(&client, "locked@email.com", "password").await;
+    assert_eq!(status, 429);
+    assert_eq!(body["code"], "login_locked");
+}
+
+async fn login_clears_failures(_token: Token) {
+    let client = client_from([127, 0, 0, 3]);
+    let res = client
+        .post("http://127.0.0.1:3030/registration")
+        .json(&User {
+            email: "forgetful@email.com".to_string(),
+            password: "password".to_string(),
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+
+    for failures in [2, 4] {
+        for _ in 0..failures {
+            let (status, _) = try_login(&client, "forgetful@email.com", "wrong").await;
+            assert_eq!(status, 401);
+        }
+        let (status, _) = try_login(&client, "forgetful@email.com", "password").await;
+        assert_eq!(status, 200);
+    }
+}
+
+async fn add_api_key(token: &Token, scope: &str) -> Value {
+    let client = reqwest::Client::new();
+    let res = client
+        .post("http://localhost:3030/api-keys")
+        .header("Authorization", &token.access_token)
+        .json(&serde_json::json!({ "name": scope, "scopes": [scope] }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 201);
+
+    res.json::<Value>().await.unwrap()
+}
+
+async fn introspect_tokens(token: Token) {
+    let voter = add_api_key(&token, "votes:write").await;
+    let introspector = add_api_key(&token, "introspect").await;
+    let voter_key = voter["key"].as_str().unwrap();
+
+    let client = reqwest::Client::new();
+    let introspect = |auth: Option<&str>, token: &str| {
+        let mut req = client
+            .post("http://localhost:3030/introspect")
+            .form(&[("token", token)]);
+        if let Some(auth) = auth {
+            req = req.header("Authorization", auth);
+        }
+        req.send()
+    };
+
+    let res = introspect(None, voter_key).await.unwrap();
+    assert_eq!(res.status(), 401);
+
+    let res = introspect(Some(introspector["key"].as_str().unwrap()), voter_key)
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+    let introspection = res.json::<Value>().await.unwrap();
+    assert_eq!(introspection["active"], true);
+    assert_eq!(introspection["token_type"], "api_key");
+    assert_eq!(introspection["scope"], "votes:write");
+
+    let res = introspect(Some(&token.access_token), "not a token")
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+    assert_eq!(res.json::<Value>().await.unwrap()["active"], false);
+
+    // Looking at a key isn't using it
+    let keys = client
+        .get("http://localhost:3030/api-keys")
+        .header("Authorization", &token.access_token)
+        .send()
+        .await
+        .unwrap()
+        .json::<Vec<Value>>()
+        .await
+        .unwrap();
+    let key = keys.iter().find(|key| key["id"] == voter["id"]).unwrap();
+    assert_eq!(key["last_used_on"], Value::Null);
+
+    let res = introspect(Some(voter_key), voter_key).await.unwrap();
+    assert_eq!(res.status(), 403);
+}
+
+async fn rejections_carry_rate_limit(_token: Token) {
+    let res = reqwest::get("http://localhost:3030/questions/999999")
+        .await
+        .unwrap();
+
+    assert_eq!(res.status(), 404);
+    assert_eq!(res.headers()["ratelimit-limit"], "300");
+    assert!(res.headers().contains_key("ratelimit-remaining"));
+}
+
+async fn export_and_delete_account(_token: Token) {
+    let client = client_from([127, 0, 0, 4]);
+    let res = client
+        .post("http://127.0.0.1:3030/registration")
+        .json(&User {
+            email: "leaving@email.com".to_string(),
+            password: "password".to_string(),
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+    let (status, body) = try_login(&client, "leaving@email.com", "password").await;
+    assert_eq!(status, 200);
+    let token: Token = serde_json::from_value(body).unwrap();
+
+    let question = add_question(&token, "Question of a leaving user").await;
+    let answer_id = add_answer(&token, question.id).await;
+    let comments = format!("http://localhost:3030/questions/{}/comments", question.id);
+    let res = client
+        .post(&comments)
+        .header("Authorization", &token.access_token)
+        .json(&serde_json::json!({ "content": "Goodbye" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+
+    let export = client
+        .get("http://localhost:3030/accounts/me/export")
+        .header("Authorization", &token.access_token)
+        .send()
+        .await
+        .unwrap()
+        .json::<Value>()
+        .await
+        .unwrap();
+    assert_eq!(export["account"]["email"], "leaving@email.com");
+    assert_eq!(export["questions"][0]["id"], question.id);
+    assert_eq!(export["answers"][0]["id"], answer_id);
+    assert_eq!(export["comments"][0]["content"], "Goodbye");
+    let account_id = export["account"]["id"].clone();
+
+    let res = client
+        .delete("http://localhost:3030/accounts/me")
+        .header("Authorization", &token.access_token)
+        .json(&serde_json::json!({ "password": "password" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+
+    // The posts stay, by an author shared with all deleted accounts
+    let detail = reqwest::get(format!("http://localhost:3030/questions/{}", question.id))
+        .await
+        .unwrap()
+        .json::<Value>()
+        .await
+        .unwrap();
+    assert_eq!(detail["account_id"], 0);
+    assert_eq!(detail["answers"][0]["account_id"], 0);
+    let comments = reqwest::get(&comments)
+        .await
+        .unwrap()
+        .json::<Vec<Value>>()
+        .await
+        .unwrap();
+    assert_eq!(comments[0]["account_id"], 0);
+    assert_ne!(account_id, 0);
+
+    let res = client
+        .get("http://localhost:3030/accounts/me/export")
+        .header("Authorization", &token.access_token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 401);
+    let (status, _) = try_login(&client, "leaving@email.com", "password").await;
+    assert_eq!(status, 401);
+}
+
+async fn emails_ignore_case(_token: Token) {
+    let client = client_from([127, 0, 0, 5]);
+    let register = |email: &str| {
+        client
+            .post("http://127.0.0.1:3030/registration")
+            .json(&User {
+                email: email.to_string(),
+                password: "password".to_string(),
+            })
+            .send()
+    };
+
+    assert_eq!(register("Mixed@Email.com").await.unwrap().status(), 200);
+    let res = register("mixed@email.COM").await.unwrap();
+    assert_eq!(res.status(), 409);
+
+    for email in ["mixed@email.com", "MIXED@EMAIL.COM"] {
+        let (status, body) = try_login(&client, email, "password").await;
+        assert_eq!(status, 200);
+        assert!(body["access_token"].is_string());
+    }
+}
+
+async fn invalid_pagination(_token: Token) {
+    let cursor = Cursor {
+        sort: Some("relevance".to_string()),
+        offset: Some(i32::MAX),
+        ..Default::default()
+    };
+
+    for query in [
+        "limit=-1".to_string(),
+        "offset=-1".to_string(),
+        format!("search=test&cursor={}", cursor.encode()),
+    ] {
+        let res = reqwest::get(format!("http://localhost:3030/questions?{}", query))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 400);
+        let problem = res.json::<Value>().await.unwrap();
+        assert_eq!(problem["code"], "invalid_parameter");
+    }
+}
+
+/// A fresh account, registered from its own loopback address
+async fn new_account(ip: [u8; 4], email: &str) -> Token {
+    let client = client_from(ip);
+    let res = client
+        .post("http://127.0.0.1:3030/registration")
+        .json(&User {
+            email: email.to_string(),
+            password: "password".to_string(),
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+
+    let (status, body) = try_login(&client, email, "password").await;
+    assert_eq!(status, 200);
+    serde_json::from_value(body).unwrap()
+}
+
+async fn answers_are_paged(_token: Token) {
+    let token = new_account([127, 0, 0, 6], "answers@email.com").await;
+    let question = add_question(&token, "Much answered question").await;
+    for _ in 0..21 {
+        add_answer(&token, question.id).await;
+    }
+
+    for (query, count) in [("", 20), ("?limit=1000", 21), ("?offset=20", 1)] {
+        let answers = reqwest::get(format!(
+            "http://localhost:3030/questions/{}/answers{}",
+            question.id, query
+        ))
+        .await
+        .unwrap()
+        .json::<Vec<Value>>()
+        .await
+        .unwrap();
+        assert_eq!(answers.len(), count);
+    }
+}
+
+async fn comments_are_paged(_token: Token) {
+    let token = new_account([127, 0, 0, 7], "comments@email.com").await;
+    let question = add_question(&token, "Much discussed question").await;
+    let comments = format!("http://localhost:3030/questions/{}/comments", question.id);
+
+    let client = reqwest::Client::new();
+    for _ in 0..21 {
+        let res = client
+            .post(&comments)
+            .header("Authorization", &token.access_token)
+            .json(&serde_json::json!({ "content": "And another thing" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 200);
+    }
+
+    for (query, count) in [("", 20), ("?limit=1000", 21), ("?offset=20", 1)] {
+        let page = reqwest::get(format!("{}{}", comments, query))
+            .await
+            .unwrap()
+            .json::<Vec<Value>>()
+            .await
+            .unwrap();
+        assert_eq!(page.len(), count);
+    }
+}
+
+async fn tags_limit(_token: Token) {
+    let res = reqwest::get("http://localhost:3030/tags?limit=-1")
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 400);
+
+    let res = reqwest::get("http://localhost:3030/tags?limit=100000")
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+}
+
+async fn api_keys_count_for_account(_token: Token) {
+    let token = new_account([127, 0, 0, 8], "keys@email.com").await;
+    let key = add_api_key(&token, "questions:write").await;
+
+    // A fresh IP, only the account has used up part of its write quota
+    let res = client_from([127, 0, 0, 9])
+        .post("http://127.0.0.1:3030/questions")
+        .header("Authorization", key["key"].as_str().unwrap())
+        .json(&Question {
+            title: "Asked with a key".to_string(),
+            content: "Whose quota is this?".to_string(),
+        })
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(res.status(), 200);
+    assert_eq!(res.headers()["ratelimit-remaining"], "58");
+}
+
+async fn password_change_revokes_keys(_token: Token) {
+    let token = new_account([127, 0, 0, 10], "changer@email.com").await;
+    let key = add_api_key(&token, "questions:write").await;
+
+    let client = reqwest::Client::new();
+    let res = client
+        .put("http://localhost:3030/accounts/me/password")
+        .header("Authorization", &token.access_token)
+        .json(&serde_json::json!({
+            "current_password": "password",
+            "new_password": "new password",
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+
+    let res = client
+        .post("http://localhost:3030/questions")
+        .header("Authorization", key["key"].as_str().unwrap())
+        .json(&Question {
+            title: "Asked with an old key".to_string(),
+            content: "Does it still work?".to_string(),
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 401);
+}
+
+async fn locked_ip_spares_emails(_token: Token) {
+    new_account([127, 0, 0, 11], "spared@email.com").await;
+
+    // 127.0.0.12 is locked out, its attempt must not count for the email
+    let (status, body) = try_login(&client_from([127, 0, 0, 12]), "spared@email.com", "wrong").await;
+    assert_eq!(status, 429);
+    assert_eq!(body["code"], "login_locked");
+
+    // Four failures leave the email one attempt short of its lockout
+    let client = client_from([127, 0, 0, 13]);
+    for _ in 0..4 {
+        let (status, _) = try_login(&client, "spared@email.com", "wrong").await;
+        assert_eq!(status, 401);
+    }
+    let (status, _) = try_login(&client, "spared@email.com", "password").await;
+    assert_eq!(status, 200);
+}
@@ -0,0 +1,15 @@
+CREATE TABLE IF NOT EXISTS votes (
+    id serial PRIMARY KEY,
+    account_id integer NOT NULL,
+    question_id integer REFERENCES questions ON DELETE CASCADE,
+    answer_id integer REFERENCES answers ON DELETE CASCADE,
+    value SMALLINT NOT NULL CHECK (value IN (-1, 1)),
+    created_on TIMESTAMP NOT NULL DEFAULT NOW(),
+    CHECK ((question_id IS NULL) <> (answer_id IS NULL))
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS votes_question_account_idx
+ON votes (question_id, account_id) WHERE question_id IS NOT NULL;
+
+CREATE UNIQUE INDEX IF NOT EXISTS votes_answer_account_idx
+ON votes (answer_id, account_id) WHERE answer_id IS NOT NULL;
@@ -0,0 +1,9 @@
+ALTER TABLE questions
+ADD COLUMN search_vector tsvector
+GENERATED ALWAYS AS (
+    setweight(to_tsvector('english', coalesce(title, '')), 'A') ||
+    setweight(to_tsvector('english', coalesce(content, '')), 'B')
+) STORED;
+
+CREATE INDEX IF NOT EXISTS questions_search_vector_idx
+ON questions USING GIN (search_vector);
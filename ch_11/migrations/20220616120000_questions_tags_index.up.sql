@@ -0,0 +1,2 @@
+CREATE INDEX IF NOT EXISTS questions_tags_idx
+ON questions USING GIN (tags);
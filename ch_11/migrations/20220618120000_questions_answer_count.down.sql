@@ -0,0 +1,8 @@
+DROP INDEX IF EXISTS questions_answer_count_idx;
+DROP INDEX IF EXISTS questions_created_on_idx;
+
+DROP TRIGGER IF EXISTS answers_answer_count ON answers;
+DROP FUNCTION IF EXISTS update_answer_count();
+
+ALTER TABLE questions
+DROP COLUMN answer_count;
@@ -0,0 +1,29 @@
+ALTER TABLE questions
+ADD COLUMN answer_count integer NOT NULL DEFAULT 0;
+
+UPDATE questions SET answer_count = (
+    SELECT COUNT(*) FROM answers WHERE answers.corresponding_question = questions.id
+);
+
+CREATE OR REPLACE FUNCTION update_answer_count() RETURNS trigger AS $$
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        UPDATE questions SET answer_count = answer_count + 1
+        WHERE id = NEW.corresponding_question;
+    ELSIF TG_OP = 'DELETE' THEN
+        UPDATE questions SET answer_count = answer_count - 1
+        WHERE id = OLD.corresponding_question;
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER answers_answer_count
+AFTER INSERT OR DELETE ON answers
+FOR EACH ROW EXECUTE FUNCTION update_answer_count();
+
+CREATE INDEX IF NOT EXISTS questions_created_on_idx
+ON questions (created_on, id);
+
+CREATE INDEX IF NOT EXISTS questions_answer_count_idx
+ON questions (answer_count, id);
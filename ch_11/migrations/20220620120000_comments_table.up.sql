@@ -0,0 +1,16 @@
+CREATE TABLE IF NOT EXISTS comments (
+    id serial PRIMARY KEY,
+    content TEXT NOT NULL,
+    question_id integer REFERENCES questions ON DELETE CASCADE,
+    answer_id integer REFERENCES answers ON DELETE CASCADE,
+    account_id integer NOT NULL,
+    created_on TIMESTAMP NOT NULL DEFAULT NOW(),
+    updated_on TIMESTAMP,
+    CHECK ((question_id IS NULL) <> (answer_id IS NULL))
+);
+
+CREATE INDEX IF NOT EXISTS comments_question_idx
+ON comments (question_id, created_on) WHERE question_id IS NOT NULL;
+
+CREATE INDEX IF NOT EXISTS comments_answer_idx
+ON comments (answer_id, created_on) WHERE answer_id IS NOT NULL;
@@ -0,0 +1,14 @@
+CREATE TABLE IF NOT EXISTS question_revisions (
+    id serial PRIMARY KEY,
+    question_id integer NOT NULL REFERENCES questions ON DELETE CASCADE,
+    revision integer NOT NULL,
+    title VARCHAR (255) NOT NULL,
+    content TEXT NOT NULL,
+    tags TEXT [],
+    account_id integer NOT NULL,
+    created_on TIMESTAMP NOT NULL DEFAULT NOW(),
+    UNIQUE (question_id, revision)
+);
+
+INSERT INTO question_revisions (question_id, revision, title, content, tags, account_id, created_on)
+SELECT id, 1, title, content, tags, account_id, created_on FROM questions;
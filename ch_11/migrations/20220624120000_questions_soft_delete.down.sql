@@ -0,0 +1,3 @@
+ALTER TABLE questions
+DROP COLUMN deleted_at,
+DROP COLUMN deleted_by;
@@ -0,0 +1,3 @@
+ALTER TABLE questions
+ADD COLUMN deleted_at TIMESTAMP,
+ADD COLUMN deleted_by integer;
@@ -0,0 +1,13 @@
+CREATE TABLE IF NOT EXISTS sessions (
+    id serial PRIMARY KEY,
+    account_id integer NOT NULL,
+    refresh_token VARCHAR (255) NOT NULL,
+    previous_refresh_token VARCHAR (255),
+    created_on TIMESTAMP NOT NULL DEFAULT NOW(),
+    last_used_on TIMESTAMP NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMP NOT NULL,
+    revoked_at TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS sessions_account_idx
+ON sessions (account_id) WHERE revoked_at IS NULL;
@@ -0,0 +1,17 @@
+ALTER TABLE accounts
+ADD COLUMN IF NOT EXISTS role VARCHAR (20) NOT NULL DEFAULT 'user'
+CHECK (role IN ('user', 'moderator', 'admin'));
+
+CREATE TABLE IF NOT EXISTS moderation_log (
+    id serial PRIMARY KEY,
+    moderator_id integer NOT NULL,
+    action VARCHAR (50) NOT NULL,
+    question_id integer,
+    answer_id integer,
+    account_id integer,
+    reason TEXT,
+    created_on TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS moderation_log_created_idx
+ON moderation_log (created_on DESC, id DESC);
@@ -0,0 +1,4 @@
+ALTER TABLE questions
+DROP COLUMN IF EXISTS closed_at,
+DROP COLUMN IF EXISTS closed_by,
+DROP COLUMN IF EXISTS close_reason;
@@ -0,0 +1,4 @@
+ALTER TABLE questions
+ADD COLUMN IF NOT EXISTS closed_at TIMESTAMP,
+ADD COLUMN IF NOT EXISTS closed_by integer,
+ADD COLUMN IF NOT EXISTS close_reason TEXT;
@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS password_resets (
+    id serial PRIMARY KEY,
+    account_id integer NOT NULL,
+    token VARCHAR (255) NOT NULL,
+    created_on TIMESTAMP NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMP NOT NULL,
+    used_at TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS password_resets_account_idx
+ON password_resets (account_id) WHERE used_at IS NULL;
@@ -0,0 +1,6 @@
+DROP TABLE IF EXISTS email_verifications;
+
+DROP INDEX IF EXISTS accounts_email_lower_idx;
+
+ALTER TABLE accounts
+DROP COLUMN IF EXISTS email_verified_at;
@@ -0,0 +1,37 @@
+ALTER TABLE accounts
+ADD COLUMN IF NOT EXISTS email_verified_at TIMESTAMP;
+
+-- Accounts from before the verification existed keep posting
+UPDATE accounts SET email_verified_at = NOW() WHERE email_verified_at IS NULL;
+
+-- Accounts which only differ in the case of their email have to be
+-- merged or renamed by hand before emails can be case-insensitive
+DO $$
+DECLARE
+    duplicates TEXT;
+BEGIN
+    SELECT string_agg(email, ', ') INTO duplicates FROM (
+        SELECT LOWER(email) AS email FROM accounts
+        GROUP BY LOWER(email) HAVING COUNT(*) > 1
+    ) AS duplicate_emails;
+
+    IF duplicates IS NOT NULL THEN
+        RAISE EXCEPTION 'Emails of several accounts only differ in case: %', duplicates
+        USING HINT = 'Merge or rename these accounts, then run the migrations again';
+    END IF;
+END $$;
+
+CREATE UNIQUE INDEX IF NOT EXISTS accounts_email_lower_idx
+ON accounts (LOWER(email));
+
+CREATE TABLE IF NOT EXISTS email_verifications (
+    id serial PRIMARY KEY,
+    account_id integer NOT NULL,
+    token VARCHAR (255) NOT NULL,
+    created_on TIMESTAMP NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMP NOT NULL,
+    used_at TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS email_verifications_account_idx
+ON email_verifications (account_id) WHERE used_at IS NULL;
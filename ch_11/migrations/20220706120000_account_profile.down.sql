@@ -0,0 +1,4 @@
+ALTER TABLE accounts
+DROP COLUMN IF EXISTS display_name,
+DROP COLUMN IF EXISTS bio,
+DROP COLUMN IF EXISTS deleted_at;
@@ -0,0 +1,4 @@
+ALTER TABLE accounts
+ADD COLUMN IF NOT EXISTS display_name VARCHAR (100),
+ADD COLUMN IF NOT EXISTS bio TEXT,
+ADD COLUMN IF NOT EXISTS deleted_at TIMESTAMP;
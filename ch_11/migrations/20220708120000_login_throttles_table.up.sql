@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS login_throttles (
+    kind VARCHAR (10) NOT NULL,
+    value VARCHAR (255) NOT NULL,
+    failures integer NOT NULL DEFAULT 0,
+    last_failure_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    locked_until TIMESTAMP,
+    PRIMARY KEY (kind, value)
+);
@@ -0,0 +1,14 @@
+CREATE TABLE IF NOT EXISTS api_keys (
+    id serial PRIMARY KEY,
+    account_id integer NOT NULL,
+    name VARCHAR (100) NOT NULL,
+    key VARCHAR (255) NOT NULL,
+    scopes TEXT [] NOT NULL,
+    created_on TIMESTAMP NOT NULL DEFAULT NOW(),
+    last_used_on TIMESTAMP,
+    expires_at TIMESTAMP NOT NULL,
+    revoked_at TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS api_keys_account_idx
+ON api_keys (account_id) WHERE revoked_at IS NULL;
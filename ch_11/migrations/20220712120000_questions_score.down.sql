@@ -0,0 +1,7 @@
+DROP INDEX IF EXISTS questions_score_idx;
+
+DROP TRIGGER IF EXISTS votes_question_score ON votes;
+DROP FUNCTION IF EXISTS update_question_score();
+
+ALTER TABLE questions
+DROP COLUMN score;
@@ -0,0 +1,27 @@
+ALTER TABLE questions
+ADD COLUMN score bigint NOT NULL DEFAULT 0;
+
+UPDATE questions SET score = (
+    SELECT COALESCE(SUM(value), 0) FROM votes WHERE votes.question_id = questions.id
+);
+
+CREATE OR REPLACE FUNCTION update_question_score() RETURNS trigger AS $$
+BEGIN
+    IF TG_OP IN ('UPDATE', 'DELETE') AND OLD.question_id IS NOT NULL THEN
+        UPDATE questions SET score = score - OLD.value
+        WHERE id = OLD.question_id;
+    END IF;
+    IF TG_OP IN ('INSERT', 'UPDATE') AND NEW.question_id IS NOT NULL THEN
+        UPDATE questions SET score = score + NEW.value
+        WHERE id = NEW.question_id;
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER votes_question_score
+AFTER INSERT OR UPDATE OR DELETE ON votes
+FOR EACH ROW EXECUTE FUNCTION update_question_score();
+
+CREATE INDEX IF NOT EXISTS questions_score_idx
+ON questions (score, id);
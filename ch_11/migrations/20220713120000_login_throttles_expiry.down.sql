@@ -0,0 +1 @@
+DROP INDEX IF EXISTS login_throttles_last_failure_at_idx;
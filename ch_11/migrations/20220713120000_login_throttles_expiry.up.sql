@@ -0,0 +1 @@
+CREATE INDEX IF NOT EXISTS login_throttles_last_failure_at_idx ON login_throttles (last_failure_at);
@@ -0,0 +1,4 @@
+-- Author of the posts of deleted accounts, see DELETED_ACCOUNT
+INSERT INTO accounts (id, email, password, email_verified_at, deleted_at)
+VALUES (0, 'deleted@invalid', '', NOW(), NOW())
+ON CONFLICT DO NOTHING;
@@ -1,9 +1,9 @@
+use bytes::Bytes;
 use serde_json::json;
+use std::collections::HashMap;
 use std::net::SocketAddr;
 use tokio::sync::{oneshot, oneshot::Sender};
 use warp::{http, Filter, Reply};
-use bytes::Bytes;
-use std::collections::HashMap;
 
 #[derive(Clone, Debug)]
 pub struct MockServer {
@@ -16,17 +16,12 @@ pub struct OneshotHandler {
 
 impl MockServer {
     pub fn new(bind_addr: SocketAddr) -> MockServer {
-        MockServer {
-            socket: bind_addr,
-        }
+        MockServer { socket: bind_addr }
     }
 
-    async fn check_profanity(
-        _: (),
-        content: Bytes,
-    ) -> Result<impl warp::Reply, warp::Rejection> {
+    async fn check_profanity(_: (), content: Bytes) -> Result<impl warp::Reply, warp::Rejection> {
         let content = String::from_utf8(content.to_vec()).expect("Invalid UTF-8");
-        if content.contains("shitty") {            
+        if content.contains("shitty") {
             Ok(warp::reply::with_status(
                 warp::reply::json(&json!({
                     "bad_words_list": [
@@ -44,7 +39,8 @@ impl MockServer {
                     "censored_content": "this is a ****** sentence",
                     "content": "this is a shitty sentence"
                 })),
-                http::StatusCode::OK))
+                http::StatusCode::OK,
+            ))
         } else {
             Ok(warp::reply::with_status(
                 warp::reply::json(&json!({
@@ -78,8 +74,6 @@ impl MockServer {
 
         tokio::task::spawn(server);
 
-        OneshotHandler {
-            sender: tx,
-        }
+        OneshotHandler { sender: tx }
     }
 }
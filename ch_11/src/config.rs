@@ -1,5 +1,8 @@
 use clap::Parser;
 use std::env;
+use std::str::FromStr;
+
+use crate::token_keys::TokenKeys;
 
 /// Q&A web service API
 #[derive(Parser, Debug, PartialEq)]
@@ -26,6 +29,229 @@ pub struct Config {
     /// Database name
     #[clap(long, default_value = "rustwebdev")]
     pub db_name: String,
+    /// Minutes an access token is valid
+    #[clap(long, default_value = "15")]
+    pub access_token_minutes: i64,
+    /// Days a refresh token is valid, every refresh starts them anew
+    #[clap(long, default_value = "30")]
+    pub refresh_token_days: i64,
+    /// Also hand out HttpOnly session cookies at login, for browser clients
+    #[clap(long)]
+    pub cookie_sessions: bool,
+    /// SameSite attribute of the session cookies (Strict, Lax or None)
+    #[clap(long, default_value = "Strict")]
+    pub cookie_same_site: SameSite,
+    /// Leave out the Secure attribute of the cookies, for local development over HTTP
+    #[clap(long)]
+    pub insecure_cookies: bool,
+    /// Only accounts with a verified email may post questions, answers and comments
+    #[clap(long)]
+    pub require_verified_email: bool,
+    /// Hours an email verification token is valid
+    #[clap(long, default_value = "24")]
+    pub email_verification_hours: i64,
+    /// Minutes a password reset token is valid
+    #[clap(long, default_value = "60")]
+    pub password_reset_minutes: i64,
+    /// Failed logins of one email before it gets locked out
+    #[clap(long, default_value = "5")]
+    pub login_attempts: i32,
+    /// Failed logins from one IP address before it gets locked out
+    #[clap(long, default_value = "20")]
+    pub login_ip_attempts: i32,
+    /// Seconds of the first lockout, every further failure doubles it
+    #[clap(long, default_value = "30")]
+    pub login_lockout_seconds: i64,
+    /// Upper bound of the lockout in minutes
+    #[clap(long, default_value = "60")]
+    pub login_max_lockout_minutes: i64,
+    /// Requests a minute a client may read, 0 for no limit
+    #[clap(long, default_value = "300")]
+    pub read_rate_limit: u32,
+    /// Requests a minute a client may change data with, 0 for no limit
+    #[clap(long, default_value = "60")]
+    pub write_rate_limit: u32,
+    /// Requests a minute a client may send to login, registration and the
+    /// other authentication routes, 0 for no limit
+    #[clap(long, default_value = "10")]
+    pub auth_rate_limit: u32,
+    /// Take the client IP from the X-Forwarded-For header of a reverse proxy
+    #[clap(long)]
+    pub trust_proxy: bool,
+    /// SMTP server for outgoing mail, e.g. a local mail catcher
+    #[clap(long)]
+    pub smtp_host: Option<String>,
+    /// PORT of the SMTP server
+    #[clap(long, default_value = "1025")]
+    pub smtp_port: u16,
+    /// Sender address of outgoing mail
+    #[clap(long, default_value = "noreply@localhost")]
+    pub mail_from: String,
+    /// Without an SMTP server, write outgoing mail to this directory instead of the log
+    #[clap(long)]
+    pub mail_dir: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+impl FromStr for SameSite {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "strict" => Ok(SameSite::Strict),
+            "lax" => Ok(SameSite::Lax),
+            "none" => Ok(SameSite::None),
+            _ => Err(format!("{} is not one of Strict, Lax or None", value)),
+        }
+    }
+}
+
+impl std::fmt::Display for SameSite {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SameSite::Strict => write!(f, "Strict"),
+            SameSite::Lax => write!(f, "Lax"),
+            SameSite::None => write!(f, "None"),
+        }
+    }
+}
+
+/// Settings of the authentication routes
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuthConfig {
+    pub access_token_lifetime: chrono::Duration,
+    pub refresh_token_lifetime: chrono::Duration,
+    /// If login sets session cookies, and `auth()` accepts them
+    pub cookie_sessions: bool,
+    pub cookie_secure: bool,
+    pub cookie_same_site: SameSite,
+    pub password_reset_lifetime: chrono::Duration,
+    pub email_verification_lifetime: chrono::Duration,
+    /// If posting needs a verified email, see `auth_verified()`
+    pub require_verified_email: bool,
+    pub login_throttle: LoginThrottle,
+    /// Keys which issue and verify the access tokens
+    pub keys: TokenKeys,
+}
+
+/// When failed logins lock out an email or an IP address
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoginThrottle {
+    /// Failures of an email before the first lockout
+    pub attempts: i32,
+    /// Failures of an IP address before the first lockout, it may
+    /// be shared by many people behind the same NAT
+    pub ip_attempts: i32,
+    pub lockout: chrono::Duration,
+    pub max_lockout: chrono::Duration,
+}
+
+impl Default for LoginThrottle {
+    fn default() -> Self {
+        LoginThrottle {
+            attempts: 5,
+            ip_attempts: 20,
+            lockout: chrono::Duration::seconds(30),
+            max_lockout: chrono::Duration::minutes(60),
+        }
+    }
+}
+
+impl LoginThrottle {
+    /// How long to lock out after the given number of failures. The
+    /// lockout doubles with every failure past the allowed attempts
+    pub fn lockout(&self, failures: i32, attempts: i32) -> Option<chrono::Duration> {
+        if failures < attempts {
+            return None;
+        }
+
+        let doublings = (failures - attempts).min(30) as u32;
+        let seconds = self
+            .lockout
+            .num_seconds()
+            .saturating_mul(2_i64.pow(doublings))
+            .min(self.max_lockout.num_seconds());
+        Some(chrono::Duration::seconds(seconds))
+    }
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        AuthConfig {
+            access_token_lifetime: chrono::Duration::minutes(15),
+            refresh_token_lifetime: chrono::Duration::days(30),
+            cookie_sessions: false,
+            cookie_secure: true,
+            cookie_same_site: SameSite::Strict,
+            password_reset_lifetime: chrono::Duration::minutes(60),
+            email_verification_lifetime: chrono::Duration::hours(24),
+            require_verified_email: false,
+            login_throttle: LoginThrottle::default(),
+            keys: TokenKeys::generate(),
+        }
+    }
+}
+
+impl From<&Config> for AuthConfig {
+    fn from(config: &Config) -> Self {
+        AuthConfig {
+            access_token_lifetime: chrono::Duration::minutes(config.access_token_minutes),
+            refresh_token_lifetime: chrono::Duration::days(config.refresh_token_days),
+            cookie_sessions: config.cookie_sessions,
+            cookie_secure: !config.insecure_cookies,
+            cookie_same_site: config.cookie_same_site,
+            password_reset_lifetime: chrono::Duration::minutes(config.password_reset_minutes),
+            email_verification_lifetime: chrono::Duration::hours(config.email_verification_hours),
+            require_verified_email: config.require_verified_email,
+            login_throttle: LoginThrottle {
+                attempts: config.login_attempts,
+                ip_attempts: config.login_ip_attempts,
+                lockout: chrono::Duration::seconds(config.login_lockout_seconds),
+                max_lockout: chrono::Duration::minutes(config.login_max_lockout_minutes),
+            },
+            // The keys are secrets, they come from the environment and not the command line
+            keys: TokenKeys::from_env().expect("PASETO keys are checked by Config::new()"),
+        }
+    }
+}
+
+/// Request quotas of the clients, see `rate_limit::with_rate_limit()`
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateLimitConfig {
+    /// Requests a minute, 0 for no limit
+    pub read: u32,
+    pub write: u32,
+    pub auth: u32,
+    /// If the client IP is taken from the X-Forwarded-For header
+    pub trust_proxy: bool,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig {
+            read: 300,
+            write: 60,
+            auth: 10,
+            trust_proxy: false,
+        }
+    }
+}
+
+impl From<&Config> for RateLimitConfig {
+    fn from(config: &Config) -> Self {
+        RateLimitConfig {
+            read: config.read_rate_limit,
+            write: config.write_rate_limit,
+            auth: config.auth_rate_limit,
+            trust_proxy: config.trust_proxy,
+        }
+    }
 }
 
 impl Config {
@@ -36,8 +262,8 @@ impl Config {
             panic!("BadWords API key not set");
         }
 
-        if env::var("PASETO_KEY").is_err() {
-            panic!("PASETO_KEY not set");
+        if let Err(e) = TokenKeys::from_env() {
+            panic!("{}", e);
         }
 
         let port = std::env::var("PORT")
@@ -62,6 +288,26 @@ impl Config {
                 .parse::<u16>()
                 .map_err(handle_errors::Error::ParseError)?,
             db_name,
+            access_token_minutes: config.access_token_minutes,
+            refresh_token_days: config.refresh_token_days,
+            cookie_sessions: config.cookie_sessions,
+            cookie_same_site: config.cookie_same_site,
+            insecure_cookies: config.insecure_cookies,
+            require_verified_email: config.require_verified_email,
+            email_verification_hours: config.email_verification_hours,
+            password_reset_minutes: config.password_reset_minutes,
+            read_rate_limit: config.read_rate_limit,
+            write_rate_limit: config.write_rate_limit,
+            auth_rate_limit: config.auth_rate_limit,
+            trust_proxy: config.trust_proxy,
+            login_attempts: config.login_attempts,
+            login_ip_attempts: config.login_ip_attempts,
+            login_lockout_seconds: config.login_lockout_seconds,
+            login_max_lockout_minutes: config.login_max_lockout_minutes,
+            smtp_host: config.smtp_host,
+            smtp_port: config.smtp_port,
+            mail_from: config.mail_from,
+            mail_dir: config.mail_dir,
         })
     }
 }
@@ -83,7 +329,7 @@ mod config_tests {
     #[test]
     fn unset_and_set_api_key() {
         // ENV VARIABLES ARE NOT SET
-        let result = std::panic::catch_unwind(|| Config::new());
+        let result = std::panic::catch_unwind(Config::new);
         assert!(result.is_err());
 
         // NOW WE SET THEM
@@ -97,10 +343,43 @@ mod config_tests {
             db_host: "localhost".to_string(),
             db_port: 5432,
             db_name: "rustwebdev".to_string(),
+            access_token_minutes: 15,
+            refresh_token_days: 30,
+            cookie_sessions: false,
+            cookie_same_site: SameSite::Strict,
+            insecure_cookies: false,
+            require_verified_email: false,
+            email_verification_hours: 24,
+            password_reset_minutes: 60,
+            read_rate_limit: 300,
+            write_rate_limit: 60,
+            auth_rate_limit: 10,
+            trust_proxy: false,
+            login_attempts: 5,
+            login_ip_attempts: 20,
+            login_lockout_seconds: 30,
+            login_max_lockout_minutes: 60,
+            smtp_host: None,
+            smtp_port: 1025,
+            mail_from: "noreply@localhost".to_string(),
+            mail_dir: None,
         };
 
         let config = Config::new().unwrap();
 
         assert_eq!(config, expected);
     }
+
+    #[test]
+    fn login_lockout_doubles_up_to_max() {
+        let throttle = LoginThrottle::default();
+
+        assert_eq!(throttle.lockout(4, 5), None);
+        assert_eq!(throttle.lockout(5, 5), Some(chrono::Duration::seconds(30)));
+        assert_eq!(throttle.lockout(7, 5), Some(chrono::Duration::seconds(120)));
+        assert_eq!(
+            throttle.lockout(100, 5),
+            Some(chrono::Duration::minutes(60))
+        );
+    }
 }
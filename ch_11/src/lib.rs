@@ -6,22 +6,59 @@ use tokio::sync::{oneshot, oneshot::Sender};
 use tracing_subscriber::fmt::format::FmtSpan;
 use warp::{http::Method, Filter, Reply};
 
+use crate::types::{account::Role, api_key::Scope};
+
 pub mod config;
+mod mailer;
 mod profanity;
+mod rate_limit;
 mod routes;
 mod store;
+mod token_keys;
 pub mod types;
+mod validation;
 
 pub struct OneshotHandler {
     pub sender: Sender<i32>,
 }
 
-async fn build_routes(store: store::Store) -> impl Filter<Extract = impl Reply> + Clone {
+async fn build_routes(
+    store: store::Store,
+    auth_config: config::AuthConfig,
+    mailer: mailer::SharedMailer,
+    rate_limit_config: config::RateLimitConfig,
+) -> impl Filter<Extract = impl Reply> + Clone {
+    let user = routes::authentication::auth_user(store.clone(), auth_config.clone());
+    let scoped =
+        |scope| routes::authentication::auth_scope(store.clone(), auth_config.clone(), scope);
+    let question_writer = scoped(Scope::QuestionsWrite);
+    let answer_writer = scoped(Scope::AnswersWrite);
+    let comment_writer = scoped(Scope::CommentsWrite);
+    let voter = scoped(Scope::VotesWrite);
+    let introspector = scoped(Scope::Introspect);
+    let client_ip = rate_limit::client_ip(rate_limit_config.trust_proxy);
+    let rate_limiter = rate_limit::RateLimiter::new(rate_limit_config);
+    let token_keys = auth_config.keys.clone();
+    let rate_limit_store = store.clone();
+    let poster =
+        |scope| routes::authentication::auth_verified(store.clone(), auth_config.clone(), scope);
+    let question_poster = poster(Scope::QuestionsWrite);
+    let answer_poster = poster(Scope::AnswersWrite);
+    let comment_poster = poster(Scope::CommentsWrite);
+    let moderator =
+        routes::authentication::auth_role(store.clone(), auth_config.clone(), Role::Moderator);
+    let admin = routes::authentication::auth_role(store.clone(), auth_config.clone(), Role::Admin);
+    let refresh_cookie = routes::authentication::refresh_cookie(auth_config.clone());
     let store_filter = warp::any().map(move || store.clone());
+    let auth_config_filter = warp::any().map(move || auth_config.clone());
+    let mailer_filter = warp::any().map(move || mailer.clone());
 
     let cors = warp::cors()
         .allow_any_origin()
         .allow_header("content-type")
+        .allow_header("authorization")
+        .allow_header(routes::authentication::CSRF_HEADER)
+        .allow_header(handle_errors::REQUEST_ID_HEADER)
         .allow_methods(&[Method::PUT, Method::DELETE, Method::GET, Method::POST]);
 
     let get_questions = warp::get()
@@ -31,63 +68,524 @@ async fn build_routes(store: store::Store) -> impl Filter<Extract = impl Reply>
         .and(store_filter.clone())
         .and_then(routes::question::get_questions);
 
+    let get_question = warp::get()
+        .and(warp::path("questions"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path::end())
+        .and(store_filter.clone())
+        .and_then(routes::question::get_question);
+
     let update_question = warp::put()
         .and(warp::path("questions"))
         .and(warp::path::param::<i32>())
         .and(warp::path::end())
-        .and(routes::authentication::auth())
+        .and(question_writer.clone())
         .and(store_filter.clone())
-        .and(warp::body::json())
+        .and(validation::json_body())
         .and_then(routes::question::update_question);
 
     let delete_question = warp::delete()
         .and(warp::path("questions"))
         .and(warp::path::param::<i32>())
         .and(warp::path::end())
-        .and(routes::authentication::auth())
+        .and(question_writer.clone())
         .and(store_filter.clone())
         .and_then(routes::question::delete_question);
 
+    let restore_question = warp::post()
+        .and(warp::path("questions"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path("restore"))
+        .and(warp::path::end())
+        .and(question_writer.clone())
+        .and(store_filter.clone())
+        .and_then(routes::question::restore_question);
+
+    let close_question = warp::put()
+        .and(warp::path("questions"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path("close"))
+        .and(warp::path::end())
+        .and(question_writer.clone())
+        .and(store_filter.clone())
+        .and(validation::json_body())
+        .and_then(routes::question::close_question);
+
+    let reopen_question = warp::delete()
+        .and(warp::path("questions"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path("close"))
+        .and(warp::path::end())
+        .and(question_writer.clone())
+        .and(store_filter.clone())
+        .and_then(routes::question::reopen_question);
+
+    let accept_answer = warp::put()
+        .and(warp::path("questions"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path("accepted-answer"))
+        .and(warp::path::end())
+        .and(question_writer.clone())
+        .and(store_filter.clone())
+        .and(validation::json_body())
+        .and_then(routes::question::accept_answer);
+
     let add_question = warp::post()
         .and(warp::path("questions"))
         .and(warp::path::end())
-        .and(routes::authentication::auth())
+        .and(question_poster.clone())
         .and(store_filter.clone())
-        .and(warp::body::json())
+        .and(validation::json_body())
         .and_then(routes::question::add_question);
 
     let add_answer = warp::post()
         .and(warp::path("answers"))
         .and(warp::path::end())
-        .and(routes::authentication::auth())
+        .and(answer_poster.clone())
         .and(store_filter.clone())
         .and(warp::body::form())
         .and_then(routes::answer::add_answer);
 
+    let get_answers = warp::get()
+        .and(warp::path("questions"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path("answers"))
+        .and(warp::path::end())
+        .and(warp::query())
+        .and(store_filter.clone())
+        .and_then(routes::answer::get_answers);
+
+    let update_answer = warp::put()
+        .and(warp::path("answers"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path::end())
+        .and(answer_writer.clone())
+        .and(store_filter.clone())
+        .and(validation::json_body())
+        .and_then(routes::answer::update_answer);
+
+    let delete_answer = warp::delete()
+        .and(warp::path("answers"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path::end())
+        .and(answer_writer.clone())
+        .and(store_filter.clone())
+        .and_then(routes::answer::delete_answer);
+
+    let vote_question = warp::put()
+        .and(warp::path("questions"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path("vote"))
+        .and(warp::path::end())
+        .and(voter.clone())
+        .and(store_filter.clone())
+        .and(validation::json_body())
+        .and_then(routes::vote::vote_question);
+
+    let retract_question_vote = warp::delete()
+        .and(warp::path("questions"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path("vote"))
+        .and(warp::path::end())
+        .and(voter.clone())
+        .and(store_filter.clone())
+        .and_then(routes::vote::retract_question_vote);
+
+    let vote_answer = warp::put()
+        .and(warp::path("answers"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path("vote"))
+        .and(warp::path::end())
+        .and(voter.clone())
+        .and(store_filter.clone())
+        .and(validation::json_body())
+        .and_then(routes::vote::vote_answer);
+
+    let retract_answer_vote = warp::delete()
+        .and(warp::path("answers"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path("vote"))
+        .and(warp::path::end())
+        .and(voter.clone())
+        .and(store_filter.clone())
+        .and_then(routes::vote::retract_answer_vote);
+
+    let get_question_comments = warp::get()
+        .and(warp::path("questions"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path("comments"))
+        .and(warp::path::end())
+        .and(warp::query())
+        .and(store_filter.clone())
+        .and_then(routes::comment::get_question_comments);
+
+    let add_question_comment = warp::post()
+        .and(warp::path("questions"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path("comments"))
+        .and(warp::path::end())
+        .and(comment_poster.clone())
+        .and(store_filter.clone())
+        .and(validation::json_body())
+        .and_then(routes::comment::add_question_comment);
+
+    let get_answer_comments = warp::get()
+        .and(warp::path("answers"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path("comments"))
+        .and(warp::path::end())
+        .and(warp::query())
+        .and(store_filter.clone())
+        .and_then(routes::comment::get_answer_comments);
+
+    let add_answer_comment = warp::post()
+        .and(warp::path("answers"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path("comments"))
+        .and(warp::path::end())
+        .and(comment_poster.clone())
+        .and(store_filter.clone())
+        .and(validation::json_body())
+        .and_then(routes::comment::add_answer_comment);
+
+    let update_comment = warp::put()
+        .and(warp::path("comments"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path::end())
+        .and(comment_writer.clone())
+        .and(store_filter.clone())
+        .and(validation::json_body())
+        .and_then(routes::comment::update_comment);
+
+    let delete_comment = warp::delete()
+        .and(warp::path("comments"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path::end())
+        .and(comment_writer.clone())
+        .and(store_filter.clone())
+        .and_then(routes::comment::delete_comment);
+
+    let get_revisions = warp::get()
+        .and(warp::path("questions"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path("revisions"))
+        .and(warp::path::end())
+        .and(store_filter.clone())
+        .and_then(routes::revision::get_revisions);
+
+    let get_revision_diff = warp::get()
+        .and(warp::path("questions"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path("revisions"))
+        .and(warp::path("diff"))
+        .and(warp::path::end())
+        .and(warp::query())
+        .and(store_filter.clone())
+        .and_then(routes::revision::get_revision_diff);
+
+    let rollback_question = warp::post()
+        .and(warp::path("questions"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path("revisions"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path("rollback"))
+        .and(warp::path::end())
+        .and(question_writer.clone())
+        .and(store_filter.clone())
+        .and_then(routes::revision::rollback_question);
+
+    let get_tags = warp::get()
+        .and(warp::path("tags"))
+        .and(warp::path::end())
+        .and(warp::query())
+        .and(store_filter.clone())
+        .and_then(routes::tag::get_tags);
+
     let registration = warp::post()
         .and(warp::path("registration"))
         .and(warp::path::end())
         .and(store_filter.clone())
-        .and(warp::body::json())
+        .and(auth_config_filter.clone())
+        .and(mailer_filter.clone())
+        .and(validation::json_body())
         .and_then(routes::authentication::register);
 
+    let verify_email = warp::post()
+        .and(warp::path("verify-email"))
+        .and(warp::path::end())
+        .and(store_filter.clone())
+        .and(validation::json_body())
+        .and_then(routes::authentication::verify_email);
+
+    let resend_verification = warp::post()
+        .and(warp::path("verify-email"))
+        .and(warp::path("resend"))
+        .and(warp::path::end())
+        .and(user.clone())
+        .and(store_filter.clone())
+        .and(auth_config_filter.clone())
+        .and(mailer_filter.clone())
+        .and_then(routes::authentication::resend_verification);
+
     let login = warp::post()
         .and(warp::path("login"))
         .and(warp::path::end())
         .and(store_filter.clone())
-        .and(warp::body::json())
+        .and(auth_config_filter.clone())
+        .and(client_ip.clone())
+        .and(validation::json_body())
         .and_then(routes::authentication::login);
 
-    get_questions
+    let refresh = warp::post()
+        .and(warp::path("refresh"))
+        .and(warp::path::end())
+        .and(store_filter.clone())
+        .and(auth_config_filter.clone())
+        .and(validation::json_body().or(refresh_cookie).unify())
+        .and_then(routes::authentication::refresh);
+
+    let logout = warp::post()
+        .and(warp::path("logout"))
+        .and(warp::path::end())
+        .and(user.clone())
+        .and(store_filter.clone())
+        .and(auth_config_filter.clone())
+        .and_then(routes::authentication::logout);
+
+    let request_password_reset = warp::post()
+        .and(warp::path("password-reset"))
+        .and(warp::path::end())
+        .and(store_filter.clone())
+        .and(auth_config_filter.clone())
+        .and(mailer_filter.clone())
+        .and(validation::json_body())
+        .and_then(routes::authentication::request_password_reset);
+
+    let confirm_password_reset = warp::post()
+        .and(warp::path("password-reset"))
+        .and(warp::path("confirm"))
+        .and(warp::path::end())
+        .and(store_filter.clone())
+        .and(validation::json_body())
+        .and_then(routes::authentication::confirm_password_reset);
+
+    let introspect = warp::post()
+        .and(warp::path("introspect"))
+        .and(warp::path::end())
+        .and(introspector)
+        .and(store_filter.clone())
+        .and(auth_config_filter.clone())
+        .and(warp::body::form())
+        .and_then(routes::authentication::introspect);
+
+    let get_token_keys = warp::get()
+        .and(warp::path("token-keys"))
+        .and(warp::path::end())
+        .and(auth_config_filter.clone())
+        .and_then(routes::authentication::get_token_keys);
+
+    let get_profile = warp::get()
+        .and(warp::path("accounts"))
+        .and(warp::path("me"))
+        .and(warp::path::end())
+        .and(user.clone())
+        .and(store_filter.clone())
+        .and_then(routes::account::get_profile);
+
+    let update_profile = warp::put()
+        .and(warp::path("accounts"))
+        .and(warp::path("me"))
+        .and(warp::path::end())
+        .and(user.clone())
+        .and(store_filter.clone())
+        .and(validation::json_body())
+        .and_then(routes::account::update_profile);
+
+    let change_password = warp::put()
+        .and(warp::path("accounts"))
+        .and(warp::path("me"))
+        .and(warp::path("password"))
+        .and(warp::path::end())
+        .and(user.clone())
+        .and(store_filter.clone())
+        .and(validation::json_body())
+        .and_then(routes::authentication::change_password);
+
+    let delete_account = warp::delete()
+        .and(warp::path("accounts"))
+        .and(warp::path("me"))
+        .and(warp::path::end())
+        .and(user.clone())
+        .and(store_filter.clone())
+        .and(auth_config_filter.clone())
+        .and(validation::json_body())
+        .and_then(routes::authentication::delete_account);
+
+    let export_account = warp::get()
+        .and(warp::path("accounts"))
+        .and(warp::path("me"))
+        .and(warp::path("export"))
+        .and(warp::path::end())
+        .and(user.clone())
+        .and(store_filter.clone())
+        .and_then(routes::account::export_account);
+
+    let get_sessions = warp::get()
+        .and(warp::path("sessions"))
+        .and(warp::path::end())
+        .and(user.clone())
+        .and(store_filter.clone())
+        .and_then(routes::session::get_sessions);
+
+    let revoke_session = warp::delete()
+        .and(warp::path("sessions"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path::end())
+        .and(user.clone())
+        .and(store_filter.clone())
+        .and_then(routes::session::revoke_session);
+
+    let add_api_key = warp::post()
+        .and(warp::path("api-keys"))
+        .and(warp::path::end())
+        .and(user.clone())
+        .and(store_filter.clone())
+        .and(validation::json_body())
+        .and_then(routes::api_key::add_api_key);
+
+    let get_api_keys = warp::get()
+        .and(warp::path("api-keys"))
+        .and(warp::path::end())
+        .and(user.clone())
+        .and(store_filter.clone())
+        .and_then(routes::api_key::get_api_keys);
+
+    let revoke_api_key = warp::delete()
+        .and(warp::path("api-keys"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path::end())
+        .and(user.clone())
+        .and(store_filter.clone())
+        .and_then(routes::api_key::revoke_api_key);
+
+    let unlock_login = warp::delete()
+        .and(warp::path("accounts"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path("lockout"))
+        .and(warp::path::end())
+        .and(admin.clone())
+        .and(store_filter.clone())
+        .and_then(routes::account::unlock_login);
+
+    let get_moderation_log = warp::get()
+        .and(warp::path("moderation-log"))
+        .and(warp::path::end())
+        .and(warp::query())
+        .and(moderator.clone())
+        .and(store_filter.clone())
+        .and_then(routes::moderation::get_moderation_log);
+
+    let get_accounts = warp::get()
+        .and(warp::path("accounts"))
+        .and(warp::path::end())
+        .and(warp::query())
+        .and(admin.clone())
+        .and(store_filter.clone())
+        .and_then(routes::account::get_accounts);
+
+    let set_role = warp::put()
+        .and(warp::path("accounts"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path("role"))
+        .and(warp::path::end())
+        .and(admin.clone())
+        .and(store_filter.clone())
+        .and(validation::json_body())
+        .and_then(routes::account::set_role);
+
+    let revoke_account_sessions = warp::delete()
+        .and(warp::path("accounts"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path("sessions"))
+        .and(warp::path::end())
+        .and(admin.clone())
+        .and(store_filter.clone())
+        .and_then(routes::account::revoke_sessions);
+
+    // Boxing each group keeps the filter types small enough to compile
+    let question_routes = get_questions
+        .or(get_question)
         .or(update_question)
         .or(add_question)
         .or(delete_question)
-        .or(add_answer)
-        .or(registration)
+        .or(restore_question)
+        .or(close_question)
+        .or(reopen_question)
+        .or(accept_answer)
+        .or(get_revisions)
+        .or(get_revision_diff)
+        .or(rollback_question)
+        .or(get_tags)
+        .boxed();
+    let answer_routes = add_answer
+        .or(get_answers)
+        .or(update_answer)
+        .or(delete_answer)
+        .boxed();
+    let vote_routes = vote_question
+        .or(retract_question_vote)
+        .or(vote_answer)
+        .or(retract_answer_vote)
+        .boxed();
+    let comment_routes = get_question_comments
+        .or(add_question_comment)
+        .or(get_answer_comments)
+        .or(add_answer_comment)
+        .or(update_comment)
+        .or(delete_comment)
+        .boxed();
+    let auth_routes = registration
+        .or(verify_email)
+        .or(resend_verification)
         .or(login)
+        .or(refresh)
+        .or(logout)
+        .or(request_password_reset)
+        .or(confirm_password_reset)
+        .or(introspect)
+        .or(get_token_keys)
+        .boxed();
+    let account_routes = get_profile
+        .or(update_profile)
+        .or(change_password)
+        .or(delete_account)
+        .or(export_account)
+        .or(get_sessions)
+        .or(revoke_session)
+        .or(add_api_key)
+        .or(get_api_keys)
+        .or(revoke_api_key)
+        .boxed();
+    let admin_routes = get_moderation_log
+        .or(get_accounts)
+        .or(set_role)
+        .or(revoke_account_sessions)
+        .or(unlock_login)
+        .boxed();
+
+    let routes = question_routes
+        .or(answer_routes)
+        .or(vote_routes)
+        .or(comment_routes)
+        .or(auth_routes)
+        .or(account_routes)
+        .or(admin_routes);
+
+    let routes = rate_limit::with_rate_limit(rate_limiter, rate_limit_store, token_keys, routes)
         .with(cors)
-        .with(warp::trace::request())
-        .recover(handle_errors::return_error)
+        .with(warp::trace::request());
+
+    handle_errors::with_problem_details(routes)
 }
 
 pub async fn setup_store(config: &config::Config) -> Result<store::Store, handle_errors::Error> {
@@ -120,12 +618,24 @@ pub async fn setup_store(config: &config::Config) -> Result<store::Store, handle
 }
 
 pub async fn run(config: config::Config, store: store::Store) {
-    let routes = build_routes(store).await;
+    let routes = build_routes(
+        store,
+        config::AuthConfig::from(&config),
+        mailer::from_config(&config),
+        config::RateLimitConfig::from(&config),
+    )
+    .await;
     warp::serve(routes).run(([0, 0, 0, 0], config.port)).await;
 }
 
 pub async fn oneshot(store: store::Store) -> OneshotHandler {
-    let routes = build_routes(store).await;
+    let routes = build_routes(
+        store,
+        config::AuthConfig::default(),
+        std::sync::Arc::new(mailer::LogMailer),
+        config::RateLimitConfig::default(),
+    )
+    .await;
     let (tx, rx) = oneshot::channel::<i32>();
 
     let socket: std::net::SocketAddr = "127.0.0.1:3030"
@@ -140,4 +650,4 @@ pub async fn oneshot(store: store::Store) -> OneshotHandler {
     tokio::task::spawn(server);
 
     OneshotHandler { sender: tx }
-}
\ No newline at end of file
+}
@@ -0,0 +1,291 @@
+use std::fmt::Debug;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use chrono::prelude::*;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tracing::{event, Level};
+
+use handle_errors::Error;
+
+use crate::config::Config;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// Delivers the mails of the service, e.g. password reset links
+#[async_trait]
+pub trait Mailer: Debug + Send + Sync {
+    async fn send(&self, email: Email) -> Result<(), Error>;
+}
+
+/// The mailer all routes share
+pub type SharedMailer = Arc<dyn Mailer>;
+
+/// Picks the mailer the configuration asks for. Without an SMTP
+/// host or a mail directory, mails only end up in the log
+pub fn from_config(config: &Config) -> SharedMailer {
+    match (&config.smtp_host, &config.mail_dir) {
+        (Some(host), _) => Arc::new(SmtpMailer {
+            host: host.clone(),
+            port: config.smtp_port,
+            from: config.mail_from.clone(),
+        }),
+        (None, Some(dir)) => Arc::new(FileMailer {
+            dir: PathBuf::from(dir),
+            from: config.mail_from.clone(),
+        }),
+        (None, None) => Arc::new(LogMailer),
+    }
+}
+
+/// Writes mails to the log, for local development
+#[derive(Debug, Clone)]
+pub struct LogMailer;
+
+#[async_trait]
+impl Mailer for LogMailer {
+    async fn send(&self, email: Email) -> Result<(), Error> {
+        event!(
+            Level::INFO,
+            to = email.to.as_str(),
+            subject = email.subject.as_str(),
+            "{}",
+            email.body
+        );
+        Ok(())
+    }
+}
+
+/// Stores every mail as an `.eml` file in `dir`, so tests
+/// and developers can pick them up from there
+#[derive(Debug, Clone)]
+pub struct FileMailer {
+    pub dir: PathBuf,
+    pub from: String,
+}
+
+#[async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, email: Email) -> Result<(), Error> {
+        let now = Utc::now();
+        let path = self.dir.join(format!(
+            "{}-{}.eml",
+            now.format("%Y%m%d%H%M%S%f"),
+            email.to.replace(|c: char| !c.is_ascii_alphanumeric(), "_")
+        ));
+
+        tokio::fs::create_dir_all(&self.dir)
+            .await
+            .map_err(|e| Error::MailError(e.to_string()))?;
+        tokio::fs::write(&path, message(&self.from, &email, now))
+            .await
+            .map_err(|e| Error::MailError(e.to_string()))
+    }
+}
+
+/// Plain SMTP without TLS or authentication, meant for a local
+/// mail catcher like MailHog rather than a public relay
+#[derive(Debug, Clone)]
+pub struct SmtpMailer {
+    pub host: String,
+    pub port: u16,
+    pub from: String,
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, email: Email) -> Result<(), Error> {
+        let stream = TcpStream::connect((self.host.as_str(), self.port))
+            .await
+            .map_err(|e| Error::MailError(e.to_string()))?;
+        let mut conn = SmtpConnection {
+            stream: BufReader::new(stream),
+        };
+
+        conn.expect(220).await?;
+        conn.command("EHLO localhost", 250).await?;
+        conn.command(&format!("MAIL FROM:<{}>", self.from), 250)
+            .await?;
+        conn.command(&format!("RCPT TO:<{}>", email.to), 250)
+            .await?;
+        conn.command("DATA", 354).await?;
+        let data = dot_stuff(&message(&self.from, &email, Utc::now()));
+        conn.command(&format!("{}\r\n.", data), 250).await?;
+        conn.command("QUIT", 221).await
+    }
+}
+
+struct SmtpConnection {
+    stream: BufReader<TcpStream>,
+}
+
+impl SmtpConnection {
+    async fn command(&mut self, line: &str, expected: u16) -> Result<(), Error> {
+        self.stream
+            .get_mut()
+            .write_all(format!("{}\r\n", line).as_bytes())
+            .await
+            .map_err(|e| Error::MailError(e.to_string()))?;
+        self.expect(expected).await
+    }
+
+    /// Reads a reply, which spans several lines as long as
+    /// there is a `-` after the status code
+    async fn expect(&mut self, expected: u16) -> Result<(), Error> {
+        loop {
+            let mut line = String::new();
+            let read = self
+                .stream
+                .read_line(&mut line)
+                .await
+                .map_err(|e| Error::MailError(e.to_string()))?;
+            if read == 0 {
+                return Err(Error::MailError("connection closed".to_string()));
+            }
+
+            let code = line.get(..3).and_then(|code| code.parse::<u16>().ok());
+            if code != Some(expected) {
+                return Err(Error::MailError(format!(
+                    "unexpected reply: {}",
+                    line.trim()
+                )));
+            }
+            if line.as_bytes().get(3) != Some(&b'-') {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// The mail with its headers, lines end with CRLF
+fn message(from: &str, email: &Email, date: DateTime<Utc>) -> String {
+    let body = email.body.lines().collect::<Vec<&str>>().join("\r\n");
+    format!(
+        "From: <{}>\r\nTo: <{}>\r\nSubject: {}\r\nDate: {}\r\nMIME-Version: 1.0\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n{}",
+        from,
+        email.to,
+        email.subject,
+        date.to_rfc2822(),
+        body
+    )
+}
+
+/// Doubles leading dots, so no line of the body can end the DATA command
+fn dot_stuff(message: &str) -> String {
+    message
+        .split("\r\n")
+        .map(|line| {
+            if line.starts_with('.') {
+                format!(".{}", line)
+            } else {
+                line.to_string()
+            }
+        })
+        .collect::<Vec<String>>()
+        .join("\r\n")
+}
+
+#[cfg(test)]
+mod mailer_tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    fn email() -> Email {
+        Email {
+            to: "user@example.com".to_string(),
+            subject: "Hello".to_string(),
+            body: "first line\n.second line".to_string(),
+        }
+    }
+
+    #[test]
+    fn leading_dots_are_doubled() {
+        assert_eq!(dot_stuff("a\r\n.b\r\n..c"), "a\r\n..b\r\n...c");
+    }
+
+    #[test]
+    fn message_has_headers_and_crlf_body() {
+        let message = message("noreply@localhost", &email(), Utc::now());
+
+        assert!(message.starts_with("From: <noreply@localhost>\r\nTo: <user@example.com>\r\n"));
+        assert!(message.ends_with("\r\n\r\nfirst line\r\n.second line"));
+    }
+
+    #[tokio::test]
+    async fn file_mailer_writes_eml() {
+        let dir = std::env::temp_dir().join(format!("mailer-test-{}", std::process::id()));
+        let mailer = FileMailer {
+            dir: dir.clone(),
+            from: "noreply@localhost".to_string(),
+        };
+
+        mailer.send(email()).await.unwrap();
+
+        let mut entries = std::fs::read_dir(&dir).unwrap();
+        let path = entries.next().unwrap().unwrap().path();
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert!(content.contains("Subject: Hello"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn smtp_mailer_talks_smtp() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = BufReader::new(stream);
+            let mut received = Vec::new();
+            stream.get_mut().write_all(b"220 ready\r\n").await.unwrap();
+
+            let mut in_data = false;
+            loop {
+                let mut line = String::new();
+                if stream.read_line(&mut line).await.unwrap() == 0 {
+                    break;
+                }
+                let line = line.trim_end().to_string();
+                let reply: &[u8] = if in_data {
+                    if line == "." {
+                        in_data = false;
+                        b"250 queued\r\n"
+                    } else {
+                        received.push(line);
+                        continue;
+                    }
+                } else if line.starts_with("EHLO") {
+                    b"250-localhost\r\n250 SIZE 1000\r\n"
+                } else if line == "DATA" {
+                    in_data = true;
+                    b"354 go ahead\r\n"
+                } else if line == "QUIT" {
+                    stream.get_mut().write_all(b"221 bye\r\n").await.unwrap();
+                    break;
+                } else {
+                    b"250 ok\r\n"
+                };
+                stream.get_mut().write_all(reply).await.unwrap();
+            }
+            received
+        });
+
+        let mailer = SmtpMailer {
+            host: "127.0.0.1".to_string(),
+            port,
+            from: "noreply@localhost".to_string(),
+        };
+        mailer.send(email()).await.unwrap();
+
+        let received = server.await.unwrap();
+        assert!(received.contains(&"Subject: Hello".to_string()));
+        assert!(received.contains(&"..second line".to_string()));
+    }
+}
@@ -0,0 +1,486 @@
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use hashlink::LinkedHashMap;
+use warp::{http::Method, path::FullPath, reply::Response, Filter, Rejection, Reply};
+
+use handle_errors::{Error, Quota, QuotaRejection};
+
+use crate::config::RateLimitConfig;
+use crate::routes::authentication::{
+    api_key_account, bearer_token, hash_api_key, verify_token, API_KEY_PREFIX, SESSION_COOKIE,
+};
+use crate::store::Store;
+use crate::token_keys::TokenKeys;
+use crate::types::account::AccountId;
+
+/// Which quota a request counts against
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RouteClass {
+    Read,
+    Write,
+    /// Routes which check credentials or send mail
+    Auth,
+}
+
+/// Paths of the `Auth` routes, with everything below them
+const AUTH_PATHS: [&str; 5] = [
+    "/login",
+    "/registration",
+    "/refresh",
+    "/password-reset",
+    "/verify-email",
+];
+
+impl RouteClass {
+    fn of(method: &Method, path: &str) -> Self {
+        let auth = AUTH_PATHS.iter().any(|auth| {
+            path.strip_prefix(auth)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        });
+
+        if auth {
+            RouteClass::Auth
+        } else if method == Method::GET || method == Method::HEAD {
+            RouteClass::Read
+        } else {
+            RouteClass::Write
+        }
+    }
+}
+
+/// Whose quota a request uses up. Requests with a valid access token
+/// or API key count for the account, all others for the client IP
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Client {
+    Account(AccountId),
+    Ip(IpAddr),
+}
+
+impl Client {
+    /// IPv6 clients usually have a whole /64 to pick addresses
+    /// from, so they are counted by their network
+    fn ip(ip: IpAddr) -> Self {
+        match ip {
+            IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+                Some(ip) => Client::Ip(IpAddr::V4(ip)),
+                None => {
+                    let [a, b, c, d, ..] = ip.segments();
+                    Client::Ip(IpAddr::V6(Ipv6Addr::new(a, b, c, d, 0, 0, 0, 0)))
+                }
+            },
+            ip => Client::Ip(ip),
+        }
+    }
+}
+
+/// Token bucket which holds up to a minute worth of requests,
+/// and refills at the rate of the limit
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn full(limit: u32, now: Instant) -> Self {
+        Bucket {
+            tokens: limit as f64,
+            updated: now,
+        }
+    }
+
+    /// Refills the bucket for the time passed, then takes a token if there is one
+    fn take(&mut self, limit: u32, now: Instant) -> Result<Quota, Error> {
+        let capacity = limit as f64;
+        let seconds_per_token = 60.0 / capacity;
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed / seconds_per_token).min(capacity);
+        self.updated = now;
+
+        if self.tokens < 1.0 {
+            return Err(Error::RateLimited {
+                limit,
+                retry_after: ((1.0 - self.tokens) * seconds_per_token).ceil() as u64,
+            });
+        }
+
+        self.tokens -= 1.0;
+        Ok(Quota {
+            limit,
+            remaining: self.tokens.floor() as u32,
+            reset: ((capacity - self.tokens) * seconds_per_token).ceil() as u64,
+        })
+    }
+}
+
+/// Buckets untouched for this long are full again and can be dropped
+const BUCKET_IDLE: Duration = Duration::from_secs(60);
+/// Most buckets kept, past it the least recently used ones get dropped
+const MAX_BUCKETS: usize = 10_000;
+
+/// How long the account of an API key is remembered. A revoked
+/// key keeps counting against its account for at most this long
+const KEY_TTL: Duration = Duration::from_secs(60);
+/// Most API keys remembered, past it the oldest ones get dropped
+const MAX_KEYS: usize = 10_000;
+
+/// Account of an API key, `None` if the key isn't valid
+#[derive(Debug, Clone)]
+struct KeyAccount {
+    account_id: Option<AccountId>,
+    looked_up: Instant,
+}
+
+/// Quotas of all clients, least recently used first. They live in
+/// memory, so every instance of the service counts on its own
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    buckets: Arc<Mutex<LinkedHashMap<(RouteClass, Client), Bucket>>>,
+    /// Accounts of API keys by the hash of the whole key, oldest first,
+    /// so not every request with a key needs a query
+    key_accounts: Arc<Mutex<LinkedHashMap<String, KeyAccount>>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        RateLimiter {
+            config,
+            buckets: Arc::new(Mutex::new(LinkedHashMap::new())),
+            key_accounts: Arc::new(Mutex::new(LinkedHashMap::new())),
+        }
+    }
+
+    /// The remembered account of an API key, `None` if it has to be looked up
+    fn cached_key(&self, hash: &str, now: Instant) -> Option<Option<AccountId>> {
+        let key_accounts = self
+            .key_accounts
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        key_accounts
+            .get(hash)
+            .filter(|key| now.saturating_duration_since(key.looked_up) < KEY_TTL)
+            .map(|key| key.account_id.clone())
+    }
+
+    fn cache_key(&self, hash: String, account_id: Option<AccountId>, now: Instant) {
+        let mut key_accounts = self
+            .key_accounts
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        key_accounts.remove(&hash);
+        while key_accounts.front().is_some_and(|(_, key)| {
+            key_accounts.len() >= MAX_KEYS
+                || now.saturating_duration_since(key.looked_up) >= KEY_TTL
+        }) {
+            key_accounts.pop_front();
+        }
+        key_accounts.insert(
+            hash,
+            KeyAccount {
+                account_id,
+                looked_up: now,
+            },
+        );
+    }
+
+    /// The account of an API key, from the cache or the store. If the
+    /// store fails the request counts against the client IP instead
+    async fn key_account(&self, store: &Store, token: &str) -> Option<AccountId> {
+        let hash = hash_api_key(token);
+        let now = Instant::now();
+        if let Some(account_id) = self.cached_key(&hash, now) {
+            return account_id;
+        }
+
+        match api_key_account(store, token).await {
+            Ok(account_id) => {
+                self.cache_key(hash, account_id.clone(), now);
+                account_id
+            }
+            Err(_) => None,
+        }
+    }
+
+    fn limit(&self, class: RouteClass) -> u32 {
+        match class {
+            RouteClass::Read => self.config.read,
+            RouteClass::Write => self.config.write,
+            RouteClass::Auth => self.config.auth,
+        }
+    }
+
+    /// The quota after the request, `None` if the class has no limit
+    fn take(
+        &self,
+        class: RouteClass,
+        client: Client,
+        now: Instant,
+    ) -> Result<Option<Quota>, Error> {
+        let limit = self.limit(class);
+        if limit == 0 {
+            return Ok(None);
+        }
+
+        let mut buckets = self
+            .buckets
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let key = (class, client);
+        if buckets.to_back(&key).is_none() {
+            while buckets.front().is_some_and(|(_, bucket)| {
+                buckets.len() >= MAX_BUCKETS
+                    || now.saturating_duration_since(bucket.updated) >= BUCKET_IDLE
+            }) {
+                buckets.pop_front();
+            }
+        }
+
+        buckets
+            .entry(key)
+            .or_insert_with(|| Bucket::full(limit, now))
+            .take(limit, now)
+            .map(Some)
+    }
+}
+
+/// IP address of the client. Behind a reverse proxy it's the
+/// last address of `X-Forwarded-For`, the one the proxy added
+pub fn client_ip(
+    trust_proxy: bool,
+) -> impl Filter<Extract = (Option<IpAddr>,), Error = Rejection> + Clone {
+    warp::addr::remote()
+        .and(warp::header::optional::<String>("x-forwarded-for"))
+        .map(
+            move |remote: Option<SocketAddr>, forwarded: Option<String>| {
+                forwarded
+                    .filter(|_| trust_proxy)
+                    .and_then(|forwarded| {
+                        forwarded
+                            .rsplit(',')
+                            .next()
+                            .and_then(|ip| ip.trim().parse().ok())
+                    })
+                    .or_else(|| remote.map(|remote| remote.ip()))
+            },
+        )
+}
+
+/// Identifies the client by its access token or API key, without the
+/// session check of `auth()`. A revoked token still counts against its
+/// account, and so does a revoked key until its cache entry expires
+fn client(
+    limiter: RateLimiter,
+    store: Store,
+    keys: TokenKeys,
+) -> impl Filter<Extract = (Option<Client>,), Error = Rejection> + Clone {
+    warp::header::optional::<String>("Authorization")
+        .and(warp::cookie::optional::<String>(SESSION_COOKIE))
+        .and(client_ip(limiter.config.trust_proxy))
+        .and_then(
+            move |header: Option<String>, cookie: Option<String>, ip: Option<IpAddr>| {
+                let limiter = limiter.clone();
+                let store = store.clone();
+                let keys = keys.clone();
+                async move {
+                    let token = header
+                        .map(|header| bearer_token(&header).to_string())
+                        .or(cookie);
+                    let account_id = match token {
+                        Some(token) if token.starts_with(API_KEY_PREFIX) => {
+                            limiter.key_account(&store, &token).await
+                        }
+                        Some(token) => verify_token(&keys, &token)
+                            .ok()
+                            .map(|session| session.account_id),
+                        None => None,
+                    };
+
+                    Ok::<_, Rejection>(
+                        account_id
+                            .map(Client::Account)
+                            .or_else(|| ip.map(Client::ip)),
+                    )
+                }
+            },
+        )
+}
+
+/// Wraps all routes, so every request uses up a token of its client's
+/// quota first. Exhausted quotas are answered with a 429, all other
+/// responses carry the `RateLimit-*` headers, rejections by way of
+/// `QuotaRejection`
+pub fn with_rate_limit<F, R>(
+    limiter: RateLimiter,
+    store: Store,
+    keys: TokenKeys,
+    routes: F,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone
+where
+    F: Filter<Extract = (R,), Error = Rejection> + Clone + Send + Sync + 'static,
+    R: Reply,
+{
+    warp::method()
+        .and(warp::path::full())
+        .and(client(limiter.clone(), store, keys))
+        .and_then(
+            move |method: Method, path: FullPath, client: Option<Client>| {
+                let limiter = limiter.clone();
+                async move {
+                    let class = RouteClass::of(&method, path.as_str());
+                    match client {
+                        Some(client) => limiter
+                            .take(class, client, Instant::now())
+                            .map_err(warp::reject::custom),
+                        None => Ok(None),
+                    }
+                }
+            },
+        )
+        .and(
+            routes
+                .map(|reply: R| Ok::<_, Rejection>(reply.into_response()))
+                .or_else(|r: Rejection| async move { Ok::<_, Rejection>((Err(r),)) }),
+        )
+        .and_then(
+            |quota: Option<Quota>, res: Result<Response, Rejection>| async move {
+                match (res, quota) {
+                    (Ok(mut res), Some(quota)) => {
+                        quota.apply(&mut res);
+                        Ok(res)
+                    }
+                    (Ok(res), None) => Ok(res),
+                    (Err(rejection), Some(quota)) => {
+                        Err(warp::reject::custom(QuotaRejection { rejection, quota }))
+                    }
+                    (Err(rejection), None) => Err(rejection),
+                }
+            },
+        )
+}
+
+#[cfg(test)]
+mod rate_limit_tests {
+    use super::*;
+
+    #[test]
+    fn route_classes() {
+        assert_eq!(RouteClass::of(&Method::GET, "/questions"), RouteClass::Read);
+        assert_eq!(
+            RouteClass::of(&Method::POST, "/questions"),
+            RouteClass::Write
+        );
+        assert_eq!(RouteClass::of(&Method::POST, "/login"), RouteClass::Auth);
+        assert_eq!(
+            RouteClass::of(&Method::POST, "/password-reset/confirm"),
+            RouteClass::Auth
+        );
+        assert_eq!(RouteClass::of(&Method::POST, "/logout"), RouteClass::Write);
+    }
+
+    #[test]
+    fn bucket_runs_dry_and_refills() {
+        let start = Instant::now();
+        let mut bucket = Bucket::full(2, start);
+
+        assert_eq!(bucket.take(2, start).unwrap().remaining, 1);
+        assert_eq!(bucket.take(2, start).unwrap().remaining, 0);
+        match bucket.take(2, start) {
+            Err(Error::RateLimited { limit, retry_after }) => {
+                assert_eq!(limit, 2);
+                assert_eq!(retry_after, 30);
+            }
+            other => panic!("expected rate limit, got {:?}", other),
+        }
+
+        // Two requests a minute refill one token every 30 seconds
+        assert!(bucket.take(2, start + Duration::from_secs(30)).is_ok());
+    }
+
+    #[test]
+    fn quotas_are_per_client_and_class() {
+        let limiter = RateLimiter::new(RateLimitConfig {
+            read: 1,
+            write: 0,
+            auth: 1,
+            trust_proxy: false,
+        });
+        let now = Instant::now();
+        let alice = Client::Account(AccountId(1));
+        let bob = Client::Account(AccountId(2));
+
+        assert!(limiter.take(RouteClass::Read, alice.clone(), now).is_ok());
+        assert!(limiter.take(RouteClass::Read, alice.clone(), now).is_err());
+        assert!(limiter.take(RouteClass::Read, bob, now).is_ok());
+        assert!(limiter.take(RouteClass::Auth, alice.clone(), now).is_ok());
+        assert_eq!(limiter.take(RouteClass::Write, alice, now).unwrap(), None);
+    }
+
+    #[test]
+    fn ipv6_clients_count_by_network() {
+        let ip = |ip: &str| Client::ip(ip.parse().unwrap());
+
+        assert_eq!(ip("2001:db8:1:2::1"), ip("2001:db8:1:2:ffff::7"));
+        assert_ne!(ip("2001:db8:1:2::1"), ip("2001:db8:1:3::1"));
+        assert_eq!(ip("::ffff:192.0.2.1"), ip("192.0.2.1"));
+        assert_ne!(ip("192.0.2.1"), ip("192.0.2.2"));
+    }
+
+    #[test]
+    fn key_accounts_expire() {
+        let limiter = RateLimiter::new(RateLimitConfig::default());
+        let now = Instant::now();
+
+        assert_eq!(limiter.cached_key("valid", now), None);
+        limiter.cache_key("valid".to_string(), Some(AccountId(1)), now);
+        limiter.cache_key("revoked".to_string(), None, now);
+
+        assert_eq!(limiter.cached_key("valid", now), Some(Some(AccountId(1))));
+        assert_eq!(limiter.cached_key("revoked", now), Some(None));
+        assert_eq!(limiter.cached_key("valid", now + KEY_TTL), None);
+
+        // Looked up again, the outdated entries make room
+        limiter.cache_key("valid".to_string(), Some(AccountId(1)), now + KEY_TTL);
+        let key_accounts = limiter.key_accounts.lock().unwrap();
+        assert_eq!(key_accounts.keys().collect::<Vec<_>>(), vec!["valid"]);
+    }
+
+    #[test]
+    fn idle_buckets_get_dropped() {
+        let limiter = RateLimiter::new(RateLimitConfig::default());
+        let now = Instant::now();
+
+        for id in 0..3 {
+            assert!(limiter
+                .take(RouteClass::Read, Client::Account(AccountId(id)), now)
+                .is_ok());
+        }
+        // Used again, so it's no longer the least recently used
+        assert!(limiter
+            .take(
+                RouteClass::Read,
+                Client::Account(AccountId(0)),
+                now + Duration::from_secs(30)
+            )
+            .is_ok());
+        assert!(limiter
+            .take(
+                RouteClass::Read,
+                Client::Account(AccountId(3)),
+                now + Duration::from_secs(60)
+            )
+            .is_ok());
+
+        let buckets = limiter.buckets.lock().unwrap();
+        let clients: Vec<&Client> = buckets.keys().map(|(_, client)| client).collect();
+        assert_eq!(
+            clients,
+            vec![
+                &Client::Account(AccountId(0)),
+                &Client::Account(AccountId(3))
+            ]
+        );
+    }
+}
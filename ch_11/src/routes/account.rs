@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+
+use warp::http::{header::CONTENT_DISPOSITION, StatusCode};
+
+use crate::store::Store;
+use crate::types::account::{AccountId, NewRole, Session, UpdateProfile};
+use crate::types::moderation::{ModerationAction, ModerationTarget, NewModerationLogEntry};
+use crate::types::pagination::{extract_pagination, Pagination};
+
+pub async fn get_profile(
+    session: Session,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    match store.get_profile(&session.account_id).await {
+        Ok(res) => Ok(warp::reply::json(&res)),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+pub async fn update_profile(
+    session: Session,
+    store: Store,
+    update: UpdateProfile,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    match store.update_profile(&session.account_id, update).await {
+        Ok(res) => Ok(warp::reply::json(&res)),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+/// Everything the account wrote as a JSON download, for data access requests
+pub async fn export_account(
+    session: Session,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    match store.get_account_export(&session.account_id).await {
+        Ok(res) => Ok(warp::reply::with_header(
+            warp::reply::json(&res),
+            CONTENT_DISPOSITION,
+            "attachment; filename=\"account-export.json\"",
+        )),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+pub async fn get_accounts(
+    params: HashMap<String, String>,
+    _session: Session,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let mut pagination = Pagination::default();
+
+    if !params.is_empty() {
+        pagination = extract_pagination(params)?;
+    }
+
+    match store
+        .get_accounts(Some(pagination.page_size()), pagination.offset)
+        .await
+    {
+        Ok(res) => Ok(warp::reply::json(&res)),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+pub async fn set_role(
+    id: i32,
+    session: Session,
+    store: Store,
+    new_role: NewRole,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    // Admins can't demote themselves, so there is always one left
+    if AccountId(id) == session.account_id {
+        return Err(warp::reject::custom(handle_errors::Error::Forbidden));
+    }
+
+    let log = NewModerationLogEntry {
+        moderator_id: session.account_id.clone(),
+        action: ModerationAction::ChangeRole,
+        target: ModerationTarget::Account(AccountId(id)),
+        reason: Some(format!("New role {}", new_role.role.as_str())),
+    };
+
+    match store
+        .clone()
+        .set_role(&AccountId(id), new_role.role, log)
+        .await
+    {
+        Ok(res) => Ok(warp::reply::json(&res)),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+/// Lifts the lockout of the account after too many failed logins
+pub async fn unlock_login(
+    id: i32,
+    session: Session,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let log = NewModerationLogEntry {
+        moderator_id: session.account_id.clone(),
+        action: ModerationAction::UnlockLogin,
+        target: ModerationTarget::Account(AccountId(id)),
+        reason: None,
+    };
+
+    match store.clone().unlock_login(&AccountId(id), log).await {
+        Ok(_) => Ok(warp::reply::with_status(
+            format!("Login of account {} unlocked", id),
+            StatusCode::OK,
+        )),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+/// Logs the account out everywhere
+pub async fn revoke_sessions(
+    id: i32,
+    session: Session,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let log = NewModerationLogEntry {
+        moderator_id: session.account_id.clone(),
+        action: ModerationAction::RevokeSessions,
+        target: ModerationTarget::Account(AccountId(id)),
+        reason: None,
+    };
+
+    match store.clone().revoke_sessions(&AccountId(id), log).await {
+        Ok(revoked) => Ok(warp::reply::with_status(
+            format!("{} sessions of account {} revoked", revoked, id),
+            StatusCode::OK,
+        )),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
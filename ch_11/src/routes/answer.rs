@@ -1,9 +1,38 @@
 use std::collections::HashMap;
+
+use tracing::{event, instrument, Level};
 use warp::http::StatusCode;
 
 use crate::profanity::check_profanity;
+use crate::routes::moderation::moderation_entry;
 use crate::store::Store;
-use crate::types::{account::Session, answer::Answer};
+use crate::types::account::Session;
+use crate::types::answer::{AnswerId, NewAnswer, UpdateAnswer};
+use crate::types::moderation::{ModerationAction, ModerationTarget};
+use crate::types::pagination::{extract_pagination, Pagination};
+
+#[instrument]
+pub async fn get_answers(
+    question_id: i32,
+    params: HashMap<String, String>,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    event!(Level::INFO, "querying answers for question {}", question_id);
+    let mut pagination = Pagination::default();
+
+    if !params.is_empty() {
+        event!(Level::INFO, pagination = true);
+        pagination = extract_pagination(params)?;
+    }
+
+    match store
+        .get_answers(question_id, Some(pagination.page_size()), pagination.offset)
+        .await
+    {
+        Ok(res) => Ok(warp::reply::json(&res)),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
 
 pub async fn add_answer(
     session: Session,
@@ -11,14 +40,15 @@ pub async fn add_answer(
     params: HashMap<String, String>,
 ) -> Result<impl warp::Reply, warp::Rejection> {
     let account_id = session.account_id;
-    let content = match check_profanity(params.get("content").unwrap().to_string()).await {
+    let answer = NewAnswer::from_form(&params)?;
+    let content = match check_profanity(answer.content).await {
         Ok(res) => res,
         Err(e) => return Err(warp::reject::custom(e)),
     };
 
-    let answer = Answer {
+    let answer = NewAnswer {
         content,
-        question_id: params.get("questionId").unwrap().parse().unwrap(),
+        question_id: answer.question_id,
     };
 
     match store.add_answer(answer, account_id).await {
@@ -26,3 +56,62 @@ pub async fn add_answer(
         Err(e) => Err(warp::reject::custom(e)),
     }
 }
+
+pub async fn update_answer(
+    id: i32,
+    session: Session,
+    store: Store,
+    answer: UpdateAnswer,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let account_id = session.account_id;
+    let access = store
+        .answer_ownership(id, &account_id)
+        .await?
+        .require_owner_or_moderator(session.role)?;
+
+    let content = match check_profanity(answer.content).await {
+        Ok(res) => res,
+        Err(e) => return Err(warp::reject::custom(e)),
+    };
+
+    let log = moderation_entry(
+        access,
+        &account_id,
+        ModerationAction::EditAnswer,
+        ModerationTarget::Answer(AnswerId(id)),
+        None,
+    );
+
+    match store.clone().update_answer(content, id, log).await {
+        Ok(res) => Ok(warp::reply::json(&res)),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+pub async fn delete_answer(
+    id: i32,
+    session: Session,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let account_id = session.account_id;
+    let access = store
+        .answer_ownership(id, &account_id)
+        .await?
+        .require_owner_or_moderator(session.role)?;
+
+    let log = moderation_entry(
+        access,
+        &account_id,
+        ModerationAction::DeleteAnswer,
+        ModerationTarget::Answer(AnswerId(id)),
+        None,
+    );
+
+    match store.clone().delete_answer(id, log).await {
+        Ok(_) => Ok(warp::reply::with_status(
+            format!("Answer {} deleted", id),
+            StatusCode::OK,
+        )),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
@@ -0,0 +1,66 @@
+use chrono::prelude::*;
+use warp::http::StatusCode;
+
+use crate::routes::authentication::{hash_api_key, new_secret, API_KEY_PREFIX};
+use crate::store::Store;
+use crate::types::account::Session;
+use crate::types::api_key::{ApiKeyId, CreatedApiKey, NewApiKey, MAX_API_KEY_DAYS};
+
+/// Creates a key for the account. Only its hash is stored,
+/// so the answer is the one chance to copy the key
+pub async fn add_api_key(
+    session: Session,
+    store: Store,
+    new_key: NewApiKey,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let secret = new_secret();
+    let days = new_key.expires_in_days.unwrap_or(MAX_API_KEY_DAYS);
+    let expires_at = (Utc::now() + chrono::Duration::days(days as i64)).naive_utc();
+
+    match store
+        .add_api_key(
+            &session.account_id,
+            new_key.name,
+            hash_api_key(&secret),
+            &new_key.scopes,
+            expires_at,
+        )
+        .await
+    {
+        Ok(info) => Ok(warp::reply::with_status(
+            warp::reply::json(&CreatedApiKey {
+                key: format!("{}{}.{}", API_KEY_PREFIX, info.id.0, secret),
+                info,
+            }),
+            StatusCode::CREATED,
+        )),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+pub async fn get_api_keys(
+    session: Session,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    match store.get_api_keys(&session.account_id).await {
+        Ok(res) => Ok(warp::reply::json(&res)),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+pub async fn revoke_api_key(
+    id: i32,
+    session: Session,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    match store
+        .revoke_api_key(&ApiKeyId(id), &session.account_id)
+        .await
+    {
+        Ok(_) => Ok(warp::reply::with_status(
+            format!("API key {} revoked", id),
+            StatusCode::OK,
+        )),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
@@ -1,47 +1,762 @@
 use argon2::{self, Config};
 use chrono::prelude::*;
 use rand::Rng;
-use std::{env, future};
-use warp::Filter;
+use sha2::{Digest, Sha256};
+use std::net::IpAddr;
+use tracing::{event, Level};
+use warp::{
+    http::{
+        header::{HeaderValue, SET_COOKIE},
+        Method, StatusCode,
+    },
+    reply::Response,
+    Filter, Reply,
+};
 
+use crate::config::AuthConfig;
+use crate::mailer::{Email, SharedMailer};
 use crate::store::Store;
-use crate::types::account::{Account, AccountId, Session};
+use crate::token_keys::TokenKeys;
+use crate::types::account::{
+    Account, AccountDeletion, AccountId, IntrospectionRequest, LoginKey, PasswordChange,
+    PasswordResetConfirm, PasswordResetRequest, RefreshRequest, Role, Session, TokenId,
+    TokenIntrospection, TokenPair, VerifyEmail, MIN_PASSWORD_LENGTH,
+};
+use crate::types::api_key::{ApiKeyGrant, ApiKeyId, Scope, StoredApiKey};
+use crate::validation::Validator;
+
+/// Adds an unverified account and mails it a verification token
+pub async fn register(
+    store: Store,
+    auth_config: AuthConfig,
+    mailer: SharedMailer,
+    account: Account,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    Validator::new()
+        .length("password", &account.password, MIN_PASSWORD_LENGTH, 128)
+        .finish()?;
 
-pub async fn register(store: Store, account: Account) -> Result<impl warp::Reply, warp::Rejection> {
     let hashed_password = hash_password(account.password.as_bytes());
+    let email = account.email.clone();
 
     let account = Account {
         id: account.id,
         email: account.email,
         password: hashed_password,
+        role: Role::User,
+    };
+
+    match store.clone().add_account(account).await {
+        Ok(account_id) => {
+            tokio::spawn(async move {
+                if let Err(e) =
+                    send_email_verification(&store, &auth_config, &mailer, &account_id, email).await
+                {
+                    event!(Level::ERROR, "Cannot send email verification: {:?}", e);
+                }
+            });
+            Ok(warp::reply::json(&"Account added".to_string()))
+        }
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+/// Mails a new verification token, unless the email is verified already
+pub async fn resend_verification(
+    session: Session,
+    store: Store,
+    auth_config: AuthConfig,
+    mailer: SharedMailer,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let account = store.get_account_info(&session.account_id).await?;
+    if account.email_verified {
+        return Ok(warp::reply::with_status(
+            "Email verified already",
+            StatusCode::OK,
+        ));
+    }
+
+    send_email_verification(
+        &store,
+        &auth_config,
+        &mailer,
+        &session.account_id,
+        account.email,
+    )
+    .await?;
+
+    Ok(warp::reply::with_status(
+        "Verification mail sent",
+        StatusCode::ACCEPTED,
+    ))
+}
+
+async fn send_email_verification(
+    store: &Store,
+    auth_config: &AuthConfig,
+    mailer: &SharedMailer,
+    account_id: &AccountId,
+    email: String,
+) -> Result<(), handle_errors::Error> {
+    let secret = new_secret();
+    let lifetime = auth_config.email_verification_lifetime;
+    let expires_at = (Utc::now() + lifetime).naive_utc();
+    let id = store
+        .add_email_verification(account_id, hash_password(secret.as_bytes()), expires_at)
+        .await?;
+
+    mailer
+        .send(Email {
+            to: email,
+            subject: "Verify your email address".to_string(),
+            body: format!(
+                "Use this token to verify your email address with POST /verify-email:\n\n{}.{}\n\n\
+                It expires in {} hours.",
+                id,
+                secret,
+                lifetime.num_hours()
+            ),
+        })
+        .await
+}
+
+pub async fn verify_email(
+    store: Store,
+    verification: VerifyEmail,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let (id, secret) =
+        split_token(&verification.token).ok_or(handle_errors::Error::InvalidVerificationToken)?;
+
+    let pending = match store.get_email_verification(id).await {
+        Ok(pending) => pending,
+        Err(handle_errors::Error::NotFound) => {
+            return Err(warp::reject::custom(
+                handle_errors::Error::InvalidVerificationToken,
+            ))
+        }
+        Err(e) => return Err(warp::reject::custom(e)),
     };
 
-    match store.add_account(account).await {
-        Ok(_) => Ok(warp::reply::json(&"Account added".to_string())),
+    if !verify_password(&pending.token, secret.as_bytes())
+        .map_err(handle_errors::Error::ArgonLibraryError)?
+    {
+        return Err(warp::reject::custom(
+            handle_errors::Error::InvalidVerificationToken,
+        ));
+    }
+
+    match store.verify_email(id, &pending.account_id).await {
+        Ok(_) => Ok(warp::reply::with_status("Email verified", StatusCode::OK)),
+        // A concurrent request used the same token first
+        Err(handle_errors::Error::NotFound) => Err(warp::reject::custom(
+            handle_errors::Error::InvalidVerificationToken,
+        )),
         Err(e) => Err(warp::reject::custom(e)),
     }
 }
 
-pub async fn login(store: Store, login: Account) -> Result<impl warp::Reply, warp::Rejection> {
-    match store.get_account(login.email).await {
+/// Failed logins are counted per client IP address and per email. Past the
+/// allowed attempts both get locked out, before any password is verified.
+/// The address is checked first, so a locked out address can't add to the
+/// failures of the emails it tries
+pub async fn login(
+    store: Store,
+    auth_config: AuthConfig,
+    client_ip: Option<IpAddr>,
+    login: Account,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let mut keys: Vec<LoginKey> = client_ip.map(LoginKey::Ip).into_iter().collect();
+    keys.push(LoginKey::email(&login.email));
+
+    let locks = add_login_attempt(&store, &keys, &auth_config).await?;
+
+    let verified = match store.clone().get_account(login.email).await {
         Ok(account) => match verify_password(&account.password, login.password.as_bytes()) {
-            Ok(verified) => {
-                if verified {
-                    Ok(warp::reply::json(&issue_token(
-                        account.id.expect("id not found"),
-                    )))
-                } else {
-                    Err(warp::reject::custom(handle_errors::Error::WrongPassword))
-                }
+            Ok(true) => Some(account),
+            Ok(false) => None,
+            Err(e) => {
+                return Err(warp::reject::custom(
+                    handle_errors::Error::ArgonLibraryError(e),
+                ))
             }
-            Err(e) => Err(warp::reject::custom(
-                handle_errors::Error::ArgonLibraryError(e),
-            )),
         },
-        Err(_) => Err(warp::reject::custom(handle_errors::Error::WrongPassword)),
+        // Unknown emails look the same as wrong passwords
+        Err(handle_errors::Error::NotFound) => None,
+        Err(e) => return Err(warp::reject::custom(e)),
+    };
+
+    match verified {
+        Some(account) => {
+            // The email is trusted again, the address only gets its attempt back
+            for (key, took_lock) in keys.iter().zip(locks) {
+                match key {
+                    LoginKey::Email(_) => store.clear_login_failures(key).await?,
+                    LoginKey::Ip(_) => store.release_login_attempt(key, took_lock).await?,
+                };
+            }
+
+            let account_id = account.id.expect("id not found");
+            let tokens = start_session(&store, account_id, account.role, &auth_config).await?;
+            Ok(tokens_reply(&tokens, &auth_config))
+        }
+        None => {
+            store.expire_login_throttles().await?;
+            Err(warp::reject::custom(handle_errors::Error::WrongPassword))
+        }
+    }
+}
+
+/// Counts the attempt for every key in order and locks the ones out which
+/// ran out of attempts, for as long as the password is verified and beyond
+/// if it is wrong. The first locked key ends the attempt, the keys after it
+/// aren't counted. Returns which keys this attempt locked
+async fn add_login_attempt(
+    store: &Store,
+    keys: &[LoginKey],
+    auth_config: &AuthConfig,
+) -> Result<Vec<bool>, handle_errors::Error> {
+    let throttle = &auth_config.login_throttle;
+    let mut locks = Vec::with_capacity(keys.len());
+
+    for key in keys {
+        let attempt = store.add_login_attempt(key).await?;
+        if let Some(locked_until) = attempt.locked_until {
+            return Err(login_locked(locked_until));
+        }
+
+        let attempts = match key {
+            LoginKey::Email(_) => throttle.attempts,
+            LoginKey::Ip(_) => throttle.ip_attempts,
+        };
+        let lockout = match throttle.lockout(attempt.failures, attempts) {
+            Some(lockout) => lockout,
+            None => {
+                locks.push(false);
+                continue;
+            }
+        };
+
+        // Only one of concurrent attempts gets to take the lock and go on
+        if !store
+            .lock_login(key, (Utc::now() + lockout).naive_utc())
+            .await?
+        {
+            let locked_until = store
+                .login_locked_until(std::slice::from_ref(key))
+                .await?
+                .unwrap_or_else(|| Utc::now().naive_utc());
+            return Err(login_locked(locked_until));
+        }
+
+        event!(
+            Level::WARN,
+            kind = key.kind(),
+            value = key.value().as_str(),
+            failures = attempt.failures,
+            lockout_seconds = lockout.num_seconds(),
+            "Login locked"
+        );
+        locks.push(true);
+    }
+
+    Ok(locks)
+}
+
+fn login_locked(locked_until: NaiveDateTime) -> handle_errors::Error {
+    let seconds = (locked_until - Utc::now().naive_utc()).num_seconds() + 1;
+    handle_errors::Error::LoginLocked(seconds.max(1) as u64)
+}
+
+/// Trades a refresh token for a new pair of tokens. The refresh token
+/// is rotated, so each one can only be used once
+pub async fn refresh(
+    store: Store,
+    auth_config: AuthConfig,
+    request: RefreshRequest,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let (token_id, secret) =
+        parse_refresh_token(&request.refresh_token).ok_or(handle_errors::Error::Unauthorized)?;
+
+    let session = match store.get_session(&token_id).await {
+        Ok(session) => session,
+        Err(handle_errors::Error::NotFound) => {
+            return Err(warp::reject::custom(handle_errors::Error::Unauthorized))
+        }
+        Err(e) => return Err(warp::reject::custom(e)),
+    };
+
+    if !verify_password(&session.refresh_token, secret.as_bytes())
+        .map_err(handle_errors::Error::ArgonLibraryError)?
+    {
+        let reused = match &session.previous_refresh_token {
+            Some(previous) => verify_password(previous, secret.as_bytes())
+                .map_err(handle_errors::Error::ArgonLibraryError)?,
+            None => false,
+        };
+
+        // A replaced token was used again, so it might have been stolen.
+        // Ending the session locks out both the thief and the owner
+        if reused {
+            event!(
+                Level::WARN,
+                token_id = token_id.0,
+                "Refresh token reused, revoking session"
+            );
+            store.revoke_session(&token_id, &session.account_id).await?;
+        }
+
+        return Err(warp::reject::custom(handle_errors::Error::Unauthorized));
+    }
+
+    let secret = new_secret();
+    let expires_at = (Utc::now() + auth_config.refresh_token_lifetime).naive_utc();
+    match store
+        .rotate_session(
+            &token_id,
+            &session.refresh_token,
+            hash_password(secret.as_bytes()),
+            expires_at,
+        )
+        .await
+    {
+        Ok(_) => Ok(tokens_reply(
+            &token_pair(
+                session.account_id,
+                session.role,
+                token_id,
+                &secret,
+                &auth_config,
+            ),
+            &auth_config,
+        )),
+        // A concurrent request used the same refresh token first
+        Err(handle_errors::Error::NotFound) => {
+            Err(warp::reject::custom(handle_errors::Error::Unauthorized))
+        }
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+/// Revokes the session of the access token, which
+/// invalidates its refresh token as well
+pub async fn logout(
+    session: Session,
+    store: Store,
+    auth_config: AuthConfig,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    match store
+        .revoke_session(&session.token_id, &session.account_id)
+        .await
+    {
+        Ok(_) => {
+            let mut res = warp::reply::with_status("Logged out", StatusCode::OK).into_response();
+            clear_cookies(&mut res, &auth_config);
+            Ok(res)
+        }
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+/// Sets a new password after checking the current one. The other sessions
+/// of the account end and its API keys get revoked, the current session
+/// stays logged in
+pub async fn change_password(
+    session: Session,
+    store: Store,
+    change: PasswordChange,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    check_password(&store, &session.account_id, &change.current_password).await?;
+
+    match store
+        .change_password(
+            &session.account_id,
+            hash_password(change.new_password.as_bytes()),
+            &session.token_id,
+        )
+        .await
+    {
+        Ok(revoked) => {
+            event!(
+                Level::INFO,
+                account_id = session.account_id.0,
+                revoked_sessions = revoked,
+                "Password changed"
+            );
+            Ok(warp::reply::with_status("Password changed", StatusCode::OK))
+        }
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+/// Deletes the account of the session, see `Store::delete_account`
+/// for what happens to its questions, answers and comments
+pub async fn delete_account(
+    session: Session,
+    store: Store,
+    auth_config: AuthConfig,
+    deletion: AccountDeletion,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    check_password(&store, &session.account_id, &deletion.password).await?;
+
+    match store.delete_account(&session.account_id).await {
+        Ok(_) => {
+            event!(
+                Level::INFO,
+                account_id = session.account_id.0,
+                "Account deleted"
+            );
+            let mut res =
+                warp::reply::with_status("Account deleted", StatusCode::OK).into_response();
+            clear_cookies(&mut res, &auth_config);
+            Ok(res)
+        }
+        Err(e) => Err(warp::reject::custom(e)),
     }
 }
 
+async fn check_password(
+    store: &Store,
+    account_id: &AccountId,
+    password: &str,
+) -> Result<(), handle_errors::Error> {
+    let hash = store.get_password(account_id).await?;
+    match verify_password(&hash, password.as_bytes()) {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(handle_errors::Error::WrongPassword),
+        Err(e) => Err(handle_errors::Error::ArgonLibraryError(e)),
+    }
+}
+
+/// Mails a single use password reset token. The reply is the same
+/// whether the account exists or not, and the token is issued in the
+/// background, so neither the reply nor its timing tell them apart
+pub async fn request_password_reset(
+    store: Store,
+    auth_config: AuthConfig,
+    mailer: SharedMailer,
+    request: PasswordResetRequest,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let account = match store.clone().get_account(request.email).await {
+        Ok(account) => Some(account),
+        Err(handle_errors::Error::NotFound) => None,
+        Err(e) => return Err(warp::reject::custom(e)),
+    };
+
+    if let Some(account) = account {
+        tokio::spawn(async move {
+            if let Err(e) = send_password_reset(store, auth_config, mailer, account).await {
+                event!(Level::ERROR, "Cannot send password reset: {:?}", e);
+            }
+        });
+    }
+
+    Ok(warp::reply::with_status(
+        "If the account exists, a password reset mail is on its way",
+        StatusCode::ACCEPTED,
+    ))
+}
+
+async fn send_password_reset(
+    store: Store,
+    auth_config: AuthConfig,
+    mailer: SharedMailer,
+    account: Account,
+) -> Result<(), handle_errors::Error> {
+    let account_id = account.id.expect("id not found");
+    let secret = new_secret();
+    let lifetime = auth_config.password_reset_lifetime;
+    let expires_at = (Utc::now() + lifetime).naive_utc();
+    let id = store
+        .add_password_reset(&account_id, hash_password(secret.as_bytes()), expires_at)
+        .await?;
+
+    mailer
+        .send(Email {
+            to: account.email,
+            subject: "Reset your password".to_string(),
+            body: format!(
+                "Use this token to set a new password with POST /password-reset/confirm:\n\n{}.{}\n\n\
+                It expires in {} minutes. If you didn't ask for a new password, ignore this mail.",
+                id,
+                secret,
+                lifetime.num_minutes()
+            ),
+        })
+        .await
+}
+
+/// Sets a new password with the token of a reset mail.
+/// All sessions of the account end, wherever they are
+pub async fn confirm_password_reset(
+    store: Store,
+    confirm: PasswordResetConfirm,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let (id, secret) =
+        split_token(&confirm.token).ok_or(handle_errors::Error::InvalidResetToken)?;
+
+    let reset = match store.get_password_reset(id).await {
+        Ok(reset) => reset,
+        Err(handle_errors::Error::NotFound) => {
+            return Err(warp::reject::custom(
+                handle_errors::Error::InvalidResetToken,
+            ))
+        }
+        Err(e) => return Err(warp::reject::custom(e)),
+    };
+
+    if !verify_password(&reset.token, secret.as_bytes())
+        .map_err(handle_errors::Error::ArgonLibraryError)?
+    {
+        return Err(warp::reject::custom(
+            handle_errors::Error::InvalidResetToken,
+        ));
+    }
+
+    match store
+        .reset_password(
+            id,
+            &reset.account_id,
+            hash_password(confirm.password.as_bytes()),
+        )
+        .await
+    {
+        Ok(revoked) => {
+            event!(
+                Level::INFO,
+                account_id = reset.account_id.0,
+                revoked_sessions = revoked,
+                "Password reset"
+            );
+            Ok(warp::reply::with_status("Password changed", StatusCode::OK))
+        }
+        // A concurrent request used the same token first
+        Err(handle_errors::Error::NotFound) => Err(warp::reject::custom(
+            handle_errors::Error::InvalidResetToken,
+        )),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+/// Tells other services if an access token or API key is valid and whose
+/// it is, after RFC 7662. Unlike checking the token themselves, this
+/// also catches revoked sessions. Callers need a login or an API key
+/// with the `introspect` scope, looking doesn't count as using the token
+pub async fn introspect(
+    _session: Session,
+    store: Store,
+    auth_config: AuthConfig,
+    request: IntrospectionRequest,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    match check_token(&store, &auth_config.keys, &request.token).await {
+        Ok(session) => Ok(warp::reply::json(&TokenIntrospection::from(&session))),
+        Err(handle_errors::Error::Unauthorized) => {
+            Ok(warp::reply::json(&TokenIntrospection::default()))
+        }
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+/// Public keys of the v2.public access tokens, so other
+/// services can verify them without a shared secret
+pub async fn get_token_keys(auth_config: AuthConfig) -> Result<impl warp::Reply, warp::Rejection> {
+    Ok(warp::reply::json(&auth_config.keys.public_keys()))
+}
+
+/// Cookie holding the access token of browser sessions
+pub const SESSION_COOKIE: &str = "session";
+/// Cookie holding the refresh token, only sent to `/refresh`
+pub const REFRESH_COOKIE: &str = "refresh_token";
+/// Readable by scripts, which have to echo it in the `CSRF_HEADER`
+pub const CSRF_COOKIE: &str = "csrf_token";
+pub const CSRF_HEADER: &str = "x-csrf-token";
+
+/// Name, path and HttpOnly attribute of the cookies set at login
+const COOKIES: [(&str, &str, bool); 3] = [
+    (SESSION_COOKIE, "/", true),
+    (REFRESH_COOKIE, "/refresh", true),
+    (CSRF_COOKIE, "/", false),
+];
+
+/// The tokens as JSON. With cookie sessions enabled they are
+/// set as cookies as well, together with a new CSRF token
+fn tokens_reply(tokens: &TokenPair, auth_config: &AuthConfig) -> Response {
+    let mut res = warp::reply::json(tokens).into_response();
+
+    if auth_config.cookie_sessions {
+        let refresh_max_age = auth_config.refresh_token_lifetime.num_seconds();
+        let csrf_token = new_secret();
+        for (name, path, http_only) in COOKIES {
+            let (value, max_age) = match name {
+                SESSION_COOKIE => (tokens.access_token.as_str(), tokens.expires_in),
+                REFRESH_COOKIE => (tokens.refresh_token.as_str(), refresh_max_age),
+                _ => (csrf_token.as_str(), refresh_max_age),
+            };
+            set_cookie(&mut res, name, value, path, max_age, http_only, auth_config);
+        }
+    }
+
+    res
+}
+
+/// Expires the cookies of a cookie session
+fn clear_cookies(res: &mut Response, auth_config: &AuthConfig) {
+    if auth_config.cookie_sessions {
+        for (name, path, http_only) in COOKIES {
+            set_cookie(res, name, "", path, 0, http_only, auth_config);
+        }
+    }
+}
+
+fn set_cookie(
+    res: &mut Response,
+    name: &str,
+    value: &str,
+    path: &str,
+    max_age: i64,
+    http_only: bool,
+    auth_config: &AuthConfig,
+) {
+    let cookie = cookie(name, value, path, max_age, http_only, auth_config);
+    if let Ok(cookie) = HeaderValue::from_str(&cookie) {
+        res.headers_mut().append(SET_COOKIE, cookie);
+    }
+}
+
+fn cookie(
+    name: &str,
+    value: &str,
+    path: &str,
+    max_age: i64,
+    http_only: bool,
+    auth_config: &AuthConfig,
+) -> String {
+    let mut cookie = format!(
+        "{}={}; Path={}; Max-Age={}; SameSite={}",
+        name, value, path, max_age, auth_config.cookie_same_site
+    );
+    if auth_config.cookie_secure {
+        cookie.push_str("; Secure");
+    }
+    if http_only {
+        cookie.push_str("; HttpOnly");
+    }
+    cookie
+}
+
+/// Double-submit check: the CSRF cookie can only be read by scripts of our
+/// own origin, so a forged cross-site request can't repeat it in the header
+fn check_csrf(cookie: Option<String>, header: Option<String>) -> Result<(), handle_errors::Error> {
+    match (cookie, header) {
+        (Some(cookie), Some(header))
+            if !cookie.is_empty() && constant_time_eq(cookie.as_bytes(), header.as_bytes()) =>
+        {
+            Ok(())
+        }
+        _ => Err(handle_errors::Error::CsrfTokenMismatch),
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// CSRF cookie and header of the request
+fn csrf_token(
+) -> impl Filter<Extract = (Option<String>, Option<String>), Error = warp::Rejection> + Clone {
+    warp::cookie::optional::<String>(CSRF_COOKIE).and(warp::header::optional::<String>(CSRF_HEADER))
+}
+
+/// The refresh token from its cookie, for browser sessions
+pub fn refresh_cookie(
+    auth_config: AuthConfig,
+) -> impl Filter<Extract = (RefreshRequest,), Error = warp::Rejection> + Clone {
+    warp::cookie::<String>(REFRESH_COOKIE)
+        .and(csrf_token())
+        .and_then(
+            move |refresh_token: String,
+                  csrf_cookie: Option<String>,
+                  csrf_header: Option<String>| {
+                let cookie_sessions = auth_config.cookie_sessions;
+                async move {
+                    if !cookie_sessions {
+                        return Err(warp::reject::custom(handle_errors::Error::Unauthorized));
+                    }
+                    check_csrf(csrf_cookie, csrf_header)?;
+                    Ok(RefreshRequest { refresh_token })
+                }
+            },
+        )
+}
+
+async fn start_session(
+    store: &Store,
+    account_id: AccountId,
+    role: Role,
+    auth_config: &AuthConfig,
+) -> Result<TokenPair, handle_errors::Error> {
+    let secret = new_secret();
+    let expires_at = (Utc::now() + auth_config.refresh_token_lifetime).naive_utc();
+    let token_id = store
+        .add_session(&account_id, hash_password(secret.as_bytes()), expires_at)
+        .await?;
+
+    Ok(token_pair(account_id, role, token_id, &secret, auth_config))
+}
+
+fn token_pair(
+    account_id: AccountId,
+    role: Role,
+    token_id: TokenId,
+    secret: &str,
+    auth_config: &AuthConfig,
+) -> TokenPair {
+    TokenPair {
+        refresh_token: format!("{}.{}", token_id.0, secret),
+        access_token: issue_token(
+            &auth_config.keys,
+            account_id,
+            token_id,
+            role,
+            auth_config.access_token_lifetime,
+        ),
+        token_type: "Bearer".to_string(),
+        expires_in: auth_config.access_token_lifetime.num_seconds(),
+    }
+}
+
+pub fn new_secret() -> String {
+    let secret = rand::thread_rng().gen::<[u8; 32]>();
+    base64::encode_config(secret, base64::URL_SAFE_NO_PAD)
+}
+
+/// Refresh tokens have the form `<token id>.<secret>`
+fn parse_refresh_token(token: &str) -> Option<(TokenId, &str)> {
+    split_token(token).map(|(token_id, secret)| (TokenId(token_id), secret))
+}
+
+/// Splits tokens of the form `<id>.<secret>`, the id
+/// finds the row which holds the hash of the secret
+fn split_token(token: &str) -> Option<(i32, &str)> {
+    let (id, secret) = token.split_once('.')?;
+    let id = id.parse().ok()?;
+    (!secret.is_empty()).then_some((id, secret))
+}
+
+/// API keys start with this, so `auth()` tells them apart from access tokens
+pub const API_KEY_PREFIX: &str = "qa_";
+
+/// API keys have the form `qa_<key id>.<secret>`
+fn parse_api_key(token: &str) -> Option<(ApiKeyId, &str)> {
+    let token = token.strip_prefix(API_KEY_PREFIX)?;
+    split_token(token).map(|(id, secret)| (ApiKeyId(id), secret))
+}
+
+/// Keys come with every request, so unlike passwords they aren't hashed
+/// with argon2. Their secrets are random, a plain SHA-256 is enough
+pub fn hash_api_key(secret: &str) -> String {
+    format!("{:x}", Sha256::digest(secret.as_bytes()))
+}
+
 fn hash_password(password: &[u8]) -> String {
     let salt = rand::thread_rng().gen::<[u8; 32]>();
     let config = Config::default();
@@ -52,62 +767,388 @@ fn verify_password(hash: &str, password: &[u8]) -> Result<bool, argon2::Error> {
     argon2::verify_encoded(hash, password)
 }
 
-pub fn verify_token(token: String) -> Result<Session, handle_errors::Error> {
-    let key = env::var("PASETO_KEY").unwrap();
-    let token = paseto::tokens::validate_local_token(
-        &token,
-        None,
-        key.as_bytes(),
-        &paseto::tokens::TimeBackend::Chrono,
-    )
-    .map_err(|_| handle_errors::Error::CannotDecryptToken)?;
-    serde_json::from_value::<Session>(token).map_err(|_| handle_errors::Error::CannotDecryptToken)
+/// The claims of an access token, without the check if its session is active
+pub fn verify_token(keys: &TokenKeys, token: &str) -> Result<Session, handle_errors::Error> {
+    keys.verify(token)
 }
 
-fn issue_token(account_id: AccountId) -> String {
-    let key = env::var("PASETO_KEY").unwrap();
+fn issue_token(
+    keys: &TokenKeys,
+    account_id: AccountId,
+    token_id: TokenId,
+    role: Role,
+    lifetime: chrono::Duration,
+) -> String {
+    keys.issue(&Session {
+        exp: Utc::now() + lifetime,
+        account_id,
+        token_id,
+        role,
+        api_key: None,
+    })
+}
 
-    let current_date_time = Utc::now();
-    let dt = current_date_time + chrono::Duration::days(1);
+/// The token of the `Authorization` header, with or without the `Bearer` scheme
+pub fn bearer_token(header: &str) -> &str {
+    match header.split_once(' ') {
+        Some((scheme, token)) if scheme.eq_ignore_ascii_case("bearer") => token.trim(),
+        _ => header.trim(),
+    }
+}
+
+/// The session of a request which came with an API key
+async fn api_key_session(
+    store: &Store,
+    id: ApiKeyId,
+    secret: &str,
+) -> Result<Session, handle_errors::Error> {
+    let key = match store.get_api_key(&id).await {
+        Ok(key) => key,
+        Err(handle_errors::Error::NotFound) => return Err(handle_errors::Error::Unauthorized),
+        Err(e) => return Err(e),
+    };
+    if !constant_time_eq(key.key.as_bytes(), hash_api_key(secret).as_bytes()) {
+        return Err(handle_errors::Error::Unauthorized);
+    }
+
+    Ok(key_session(id, key))
+}
+
+/// The account of a valid API key, `None` for anything else. Unlike
+/// `auth()` it leaves the key's last use alone
+pub async fn api_key_account(
+    store: &Store,
+    token: &str,
+) -> Result<Option<AccountId>, handle_errors::Error> {
+    let (id, secret) = match parse_api_key(token) {
+        Some(key) => key,
+        None => return Ok(None),
+    };
 
-    paseto::tokens::PasetoBuilder::new()
-        .set_encryption_key(&Vec::from(key.as_bytes()))
-        .set_expiration(&dt)
-        .set_claim("account_id", serde_json::json!(account_id))
-        .build()
-        .expect("Failed to construct paseto token w/ builder!")
+    match api_key_session(store, id, secret).await {
+        Ok(session) => Ok(Some(session.account_id)),
+        Err(handle_errors::Error::Unauthorized) => Ok(None),
+        Err(e) => Err(e),
+    }
 }
 
-pub fn auth() -> impl Filter<Extract = (Session,), Error = warp::Rejection> + Clone {
-    warp::header::<String>("Authorization").and_then(|token: String| {
-        let token = match verify_token(token) {
-            Ok(t) => t,
-            Err(_) => {
-                return future::ready(Err(warp::reject::custom(
-                    handle_errors::Error::Unauthorized,
-                )))
+/// Keys act with the powers of a user, whatever the role of their
+/// account. Moderation and administration need a login
+fn key_session(id: ApiKeyId, key: StoredApiKey) -> Session {
+    Session {
+        exp: Utc.from_utc_datetime(&key.expires_at),
+        account_id: key.account_id,
+        token_id: TokenId(0),
+        role: Role::User,
+        api_key: Some(ApiKeyGrant {
+            id,
+            scopes: key.scopes,
+        }),
+    }
+}
+
+/// The session of a request with an access token or API key, keys
+/// get their last use updated. Whatever is wrong with the token, the
+/// error is `Unauthorized`
+async fn token_session(
+    store: &Store,
+    keys: &TokenKeys,
+    token: &str,
+) -> Result<Session, handle_errors::Error> {
+    let session = check_token(store, keys, token).await?;
+    if let Some(grant) = &session.api_key {
+        store.touch_api_key(&grant.id).await?;
+    }
+
+    Ok(session)
+}
+
+/// Like `token_session()`, but doesn't count as a use of the token
+async fn check_token(
+    store: &Store,
+    keys: &TokenKeys,
+    token: &str,
+) -> Result<Session, handle_errors::Error> {
+    if let Some((id, secret)) = parse_api_key(token) {
+        return api_key_session(store, id, secret).await;
+    }
+
+    let session = verify_token(keys, token).map_err(|_| handle_errors::Error::Unauthorized)?;
+
+    // Tokens of revoked sessions are rejected before they expire
+    if store
+        .is_session_active(&session.token_id, &session.account_id)
+        .await?
+    {
+        Ok(session)
+    } else {
+        Err(handle_errors::Error::Unauthorized)
+    }
+}
+
+/// Authenticates the request by the access token or API key of the
+/// `Authorization` header or, with cookie sessions enabled, by the access
+/// token of the session cookie. Cookie authenticated requests which change
+/// data need a CSRF token. API key sessions are restricted by `auth_scope()`
+/// and `auth_user()`, so routes shouldn't use this filter on its own
+pub fn auth(
+    store: Store,
+    auth_config: AuthConfig,
+) -> impl Filter<Extract = (Session,), Error = warp::Rejection> + Clone {
+    let keys = auth_config.keys.clone();
+    request_token(auth_config).and_then(move |token: String| {
+        let store = store.clone();
+        let keys = keys.clone();
+        async move {
+            token_session(&store, &keys, &token)
+                .await
+                .map_err(warp::reject::custom)
+        }
+    })
+}
+
+/// The token `auth()` checks, taken from the header or the session cookie
+fn request_token(
+    auth_config: AuthConfig,
+) -> impl Filter<Extract = (String,), Error = warp::Rejection> + Clone {
+    warp::method()
+        .and(warp::header::optional::<String>("Authorization"))
+        .and(warp::cookie::optional::<String>(SESSION_COOKIE))
+        .and(csrf_token())
+        .and_then(
+            move |method: Method,
+                  header: Option<String>,
+                  cookie: Option<String>,
+                  csrf_cookie: Option<String>,
+                  csrf_header: Option<String>| {
+                let cookie_sessions = auth_config.cookie_sessions;
+                async move {
+                    match (header, cookie) {
+                        (Some(header), _) => Ok(bearer_token(&header).to_string()),
+                        (None, Some(cookie)) if cookie_sessions => {
+                            let safe = method == Method::GET
+                                || method == Method::HEAD
+                                || method == Method::OPTIONS;
+                            if !safe {
+                                check_csrf(csrf_cookie, csrf_header)?;
+                            }
+                            Ok(cookie)
+                        }
+                        _ => Err(warp::reject::custom(handle_errors::Error::Unauthorized)),
+                    }
+                }
+            },
+        )
+}
+
+/// Like `auth()`, but API keys need the given scope
+pub fn auth_scope(
+    store: Store,
+    auth_config: AuthConfig,
+    scope: Scope,
+) -> impl Filter<Extract = (Session,), Error = warp::Rejection> + Clone {
+    auth(store, auth_config).and_then(move |session: Session| async move {
+        session.require_scope(scope)?;
+        Ok::<_, warp::Rejection>(session)
+    })
+}
+
+/// Like `auth()`, but only for logins. Guards the routes which manage
+/// the account, its sessions and its API keys
+pub fn auth_user(
+    store: Store,
+    auth_config: AuthConfig,
+) -> impl Filter<Extract = (Session,), Error = warp::Rejection> + Clone {
+    auth(store, auth_config).and_then(|session: Session| async move {
+        session.require_login()?;
+        Ok::<_, warp::Rejection>(session)
+    })
+}
+
+/// Like `auth_scope()`, but with `require_verified_email` set the account needs
+/// a verified email. Guards the routes which post questions, answers and comments
+pub fn auth_verified(
+    store: Store,
+    auth_config: AuthConfig,
+    scope: Scope,
+) -> impl Filter<Extract = (Session,), Error = warp::Rejection> + Clone {
+    let required = auth_config.require_verified_email;
+    auth_scope(store.clone(), auth_config, scope).and_then(move |session: Session| {
+        let store = store.clone();
+        async move {
+            if required
+                && !store
+                    .get_account_info(&session.account_id)
+                    .await?
+                    .email_verified
+            {
+                return Err(warp::reject::custom(handle_errors::Error::EmailNotVerified));
             }
-        };
+            Ok(session)
+        }
+    })
+}
 
-        future::ready(Ok(token))
+/// Like `auth_user()`, but the account needs at least the given role
+pub fn auth_role(
+    store: Store,
+    auth_config: AuthConfig,
+    role: Role,
+) -> impl Filter<Extract = (Session,), Error = warp::Rejection> + Clone {
+    auth_user(store, auth_config).and_then(move |session: Session| async move {
+        if session.role >= role {
+            Ok(session)
+        } else {
+            Err(warp::reject::custom(handle_errors::Error::Forbidden))
+        }
     })
 }
 
 #[cfg(test)]
 mod authentication_tests {
-    use super::{auth, env, issue_token, AccountId};
+    use super::{
+        bearer_token, check_csrf, cookie, hash_api_key, issue_token, key_session, parse_api_key,
+        parse_refresh_token, request_token, verify_token, AccountId, ApiKeyId, AuthConfig, Role,
+        Scope, StoredApiKey, TokenId, TokenKeys,
+    };
+
+    #[test]
+    fn issued_token_verifies() {
+        let keys = TokenKeys::generate();
+        let token = issue_token(
+            &keys,
+            AccountId(3),
+            TokenId(7),
+            Role::Moderator,
+            chrono::Duration::minutes(15),
+        );
+
+        let session = verify_token(&keys, &token).unwrap();
+
+        assert_eq!(session.account_id, AccountId(3));
+        assert_eq!(session.token_id, TokenId(7));
+        assert_eq!(session.role, Role::Moderator);
+    }
 
     #[tokio::test]
     async fn post_questions_auth() {
-        env::set_var("PASETO_KEY", "RANDOM WORDS WINTER MACINTOSH PC");
-        let token = issue_token(AccountId(3));
+        let auth_config = AuthConfig::default();
+        let token = issue_token(
+            &auth_config.keys,
+            AccountId(3),
+            TokenId(7),
+            Role::User,
+            chrono::Duration::minutes(15),
+        );
 
-        let filter = auth();
+        let filter = request_token(auth_config.clone());
 
         let res = warp::test::request()
-            .header("Authorization", token)
-            .filter(&filter);
+            .method("POST")
+            .header("Authorization", format!("Bearer {}", token))
+            .filter(&filter)
+            .await
+            .unwrap();
+
+        let session = verify_token(&auth_config.keys, &res).unwrap();
+        assert_eq!(session.account_id, AccountId(3));
+    }
+
+    #[test]
+    fn expired_token() {
+        let keys = TokenKeys::generate();
+        let token = issue_token(
+            &keys,
+            AccountId(3),
+            TokenId(7),
+            Role::User,
+            chrono::Duration::minutes(-1),
+        );
+
+        assert!(verify_token(&keys, &token).is_err());
+    }
+
+    #[test]
+    fn refresh_token_format() {
+        assert_eq!(
+            parse_refresh_token("7.secret"),
+            Some((TokenId(7), "secret"))
+        );
+        assert_eq!(parse_refresh_token("secret"), None);
+        assert_eq!(parse_refresh_token("x.secret"), None);
+        assert_eq!(parse_refresh_token("7."), None);
+    }
+
+    #[test]
+    fn bearer_scheme_is_optional() {
+        assert_eq!(bearer_token("Bearer v2.local.abc"), "v2.local.abc");
+        assert_eq!(bearer_token("bearer v2.local.abc"), "v2.local.abc");
+        assert_eq!(bearer_token("v2.local.abc"), "v2.local.abc");
+    }
+
+    #[test]
+    fn csrf_tokens_have_to_match() {
+        let token = || Some("abc".to_string());
+
+        assert!(check_csrf(token(), token()).is_ok());
+        assert!(check_csrf(token(), Some("abd".to_string())).is_err());
+        assert!(check_csrf(token(), None).is_err());
+        assert!(check_csrf(None, token()).is_err());
+        assert!(check_csrf(Some(String::new()), Some(String::new())).is_err());
+    }
+
+    #[test]
+    fn cookie_attributes() {
+        let mut auth_config = AuthConfig::default();
+        assert_eq!(
+            cookie("session", "abc", "/", 900, true, &auth_config),
+            "session=abc; Path=/; Max-Age=900; SameSite=Strict; Secure; HttpOnly"
+        );
+
+        auth_config.cookie_secure = false;
+        assert_eq!(
+            cookie("csrf_token", "abc", "/", 900, false, &auth_config),
+            "csrf_token=abc; Path=/; Max-Age=900; SameSite=Strict"
+        );
+    }
+
+    #[test]
+    fn api_keys_are_told_apart_from_tokens() {
+        assert_eq!(
+            parse_api_key("qa_12.secret"),
+            Some((ApiKeyId(12), "secret"))
+        );
+        assert_eq!(parse_api_key("12.secret"), None);
+        assert_eq!(parse_api_key("v2.local.abc"), None);
+        assert_eq!(parse_api_key("qa_12."), None);
+
+        let hash = hash_api_key("secret");
+        assert_eq!(hash.len(), 64);
+        assert_eq!(hash, hash_api_key("secret"));
+        assert_ne!(hash, hash_api_key("secreT"));
+    }
+
+    #[test]
+    fn api_keys_act_as_users() {
+        let expires_at = chrono::NaiveDate::from_ymd_opt(2030, 1, 1)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap();
+        let session = key_session(
+            ApiKeyId(4),
+            StoredApiKey {
+                account_id: AccountId(3),
+                key: hash_api_key("secret"),
+                scopes: vec![Scope::AnswersWrite],
+                expires_at,
+            },
+        );
 
-        assert_eq!(res.await.unwrap().account_id, AccountId(3));
+        assert_eq!(session.account_id, AccountId(3));
+        assert_eq!(session.role, Role::User);
+        assert_eq!(session.exp.naive_utc(), expires_at);
+        assert!(session.require_scope(Scope::AnswersWrite).is_ok());
+        assert!(session.require_scope(Scope::QuestionsWrite).is_err());
+        assert!(session.require_login().is_err());
     }
 }
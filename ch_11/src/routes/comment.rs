@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+
+use tracing::{event, instrument, Level};
+use warp::http::StatusCode;
+
+use crate::profanity::check_profanity;
+use crate::store::Store;
+use crate::types::account::Session;
+use crate::types::answer::AnswerId;
+use crate::types::comment::{CommentTarget, NewComment};
+use crate::types::pagination::{extract_pagination, Pagination};
+use crate::types::question::QuestionId;
+
+#[instrument]
+pub async fn get_question_comments(
+    id: i32,
+    params: HashMap<String, String>,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    event!(Level::INFO, "querying comments for question {}", id);
+    get_comments(CommentTarget::Question(QuestionId(id)), params, store).await
+}
+
+#[instrument]
+pub async fn get_answer_comments(
+    id: i32,
+    params: HashMap<String, String>,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    event!(Level::INFO, "querying comments for answer {}", id);
+    get_comments(CommentTarget::Answer(AnswerId(id)), params, store).await
+}
+
+async fn get_comments(
+    target: CommentTarget,
+    params: HashMap<String, String>,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let mut pagination = Pagination::default();
+
+    if !params.is_empty() {
+        event!(Level::INFO, pagination = true);
+        pagination = extract_pagination(params)?;
+    }
+
+    match store
+        .get_comments(target, Some(pagination.page_size()), pagination.offset)
+        .await
+    {
+        Ok(res) => Ok(warp::reply::json(&res)),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+pub async fn add_question_comment(
+    id: i32,
+    session: Session,
+    store: Store,
+    comment: NewComment,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    add_comment(
+        CommentTarget::Question(QuestionId(id)),
+        session,
+        store,
+        comment,
+    )
+    .await
+}
+
+pub async fn add_answer_comment(
+    id: i32,
+    session: Session,
+    store: Store,
+    comment: NewComment,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    add_comment(CommentTarget::Answer(AnswerId(id)), session, store, comment).await
+}
+
+async fn add_comment(
+    target: CommentTarget,
+    session: Session,
+    store: Store,
+    comment: NewComment,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let content = match check_profanity(comment.content).await {
+        Ok(res) => res,
+        Err(e) => return Err(warp::reject::custom(e)),
+    };
+
+    match store.add_comment(target, content, session.account_id).await {
+        Ok(res) => Ok(warp::reply::json(&res)),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+pub async fn update_comment(
+    id: i32,
+    session: Session,
+    store: Store,
+    comment: NewComment,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let account_id = session.account_id;
+    store
+        .comment_ownership(id, &account_id)
+        .await?
+        .require_owner()?;
+
+    let content = match check_profanity(comment.content).await {
+        Ok(res) => res,
+        Err(e) => return Err(warp::reject::custom(e)),
+    };
+
+    match store.update_comment(content, id, account_id).await {
+        Ok(res) => Ok(warp::reply::json(&res)),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+pub async fn delete_comment(
+    id: i32,
+    session: Session,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let account_id = session.account_id;
+    store
+        .comment_ownership(id, &account_id)
+        .await?
+        .require_owner()?;
+
+    match store.delete_comment(id, account_id).await {
+        Ok(true) => Ok(warp::reply::with_status(
+            format!("Comment {} deleted", id),
+            StatusCode::OK,
+        )),
+        // Deleted by a concurrent request after the ownership check
+        Ok(false) => Err(warp::reject::custom(handle_errors::Error::NotFound)),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
@@ -1,3 +1,11 @@
+pub mod account;
 pub mod answer;
+pub mod api_key;
 pub mod authentication;
+pub mod comment;
+pub mod moderation;
 pub mod question;
+pub mod revision;
+pub mod session;
+pub mod tag;
+pub mod vote;
@@ -0,0 +1,47 @@
+use std::collections::HashMap;
+
+use crate::store::Store;
+use crate::types::account::{Access, AccountId, Session};
+use crate::types::moderation::{ModerationAction, ModerationTarget, NewModerationLogEntry};
+use crate::types::pagination::{extract_pagination, Pagination};
+
+/// The log entry of a change moderators make to posts of other
+/// accounts. Owners changing their own posts aren't logged
+pub fn moderation_entry(
+    access: Access,
+    moderator_id: &AccountId,
+    action: ModerationAction,
+    target: ModerationTarget,
+    reason: Option<String>,
+) -> Option<NewModerationLogEntry> {
+    if access == Access::Owner {
+        return None;
+    }
+
+    Some(NewModerationLogEntry {
+        moderator_id: moderator_id.clone(),
+        action,
+        target,
+        reason,
+    })
+}
+
+pub async fn get_moderation_log(
+    params: HashMap<String, String>,
+    _session: Session,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let mut pagination = Pagination::default();
+
+    if !params.is_empty() {
+        pagination = extract_pagination(params)?;
+    }
+
+    match store
+        .get_moderation_log(Some(pagination.page_size()), pagination.offset)
+        .await
+    {
+        Ok(res) => Ok(warp::reply::json(&res)),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
@@ -1,29 +1,202 @@
 use std::collections::HashMap;
 
+use serde::Serialize;
 use tracing::{event, instrument, Level};
-use warp::http::StatusCode;
+use warp::http::{
+    header::{HeaderValue, LINK},
+    StatusCode,
+};
+use warp::Reply;
 
 use crate::profanity::check_profanity;
+use crate::routes::moderation::moderation_entry;
 use crate::store::Store;
 use crate::types::account::Session;
-use crate::types::pagination::{extract_pagination, Pagination};
-use crate::types::question::{NewQuestion, Question};
+use crate::types::moderation::{CloseQuestion, ModerationAction, ModerationTarget};
+use crate::types::pagination::{extract_pagination, Cursor, Page, Pagination};
+use crate::types::question::{
+    AcceptedAnswer, NewQuestion, QuestionId, QuestionSort, RELEVANCE_SORT,
+};
+use crate::types::tag::extract_tag_filter;
 
 #[instrument]
 pub async fn get_questions(
-    params: HashMap<String, String>,
+    query: Vec<(String, String)>,
     store: Store,
 ) -> Result<impl warp::Reply, warp::Rejection> {
     event!(target: "practical_rust_book", Level::INFO, "querying questions");
     let mut pagination = Pagination::default();
+    let tags = extract_tag_filter(&query)?;
+
+    // Repeated keys like `tag` are handled above, the rest is unique
+    let mut params: HashMap<String, String> = query
+        .iter()
+        .filter(|(key, _)| key != "tag" && key != "match")
+        .cloned()
+        .collect();
+    let search = params.remove("q").filter(|q| !q.trim().is_empty());
+    let sort = params
+        .remove("sort")
+        .map(|sort| sort.parse::<QuestionSort>())
+        .transpose()?;
+
+    if search.is_some() && sort.is_some() {
+        return Err(warp::reject::custom(
+            handle_errors::Error::InvalidParameter(
+                "search results are sorted by relevance, sort can't be used with q".to_string(),
+            ),
+        ));
+    }
 
     if !params.is_empty() {
         event!(Level::INFO, pagination = true);
         pagination = extract_pagination(params)?;
     }
 
+    let listing_sort = match (&search, sort) {
+        (Some(_), _) => RELEVANCE_SORT,
+        (None, sort) => sort.unwrap_or_default().as_str(),
+    };
+    if let Some(cursor) = &pagination.cursor {
+        cursor.check_sort(listing_sort)?;
+    }
+
+    let page_size = pagination.page_size();
+    let total = if pagination.include_total {
+        let unanswered = sort == Some(QuestionSort::Unanswered);
+        Some(
+            store
+                .clone()
+                .count_questions(search.clone(), unanswered, &tags)
+                .await?,
+        )
+    } else {
+        None
+    };
+
+    let page = if let Some(search) = search {
+        event!(Level::INFO, search = true);
+        // Results are ordered by relevance, so the cursor carries an offset
+        let offset = pagination
+            .cursor
+            .and_then(|cursor| cursor.offset)
+            .unwrap_or(pagination.offset);
+        // Crafted cursors may carry any offset
+        let next_offset = offset.checked_add(page_size).ok_or_else(|| {
+            handle_errors::Error::InvalidParameter("offset is too large".to_string())
+        })?;
+        let mut items = store
+            .search_questions(search, Some(page_size + 1), offset, &tags)
+            .await?;
+
+        let next_cursor = if items.len() > page_size as usize {
+            items.truncate(page_size as usize);
+            Some(Cursor {
+                sort: Some(RELEVANCE_SORT.to_string()),
+                offset: Some(next_offset),
+                ..Default::default()
+            })
+        } else {
+            None
+        };
+
+        page_reply(
+            Page {
+                items,
+                next_cursor: next_cursor.map(|cursor| cursor.encode()),
+                total,
+            },
+            &query,
+        )
+    } else {
+        let sort = sort.unwrap_or_default();
+        let mut items = store
+            .get_questions(
+                Some(page_size + 1),
+                pagination.offset,
+                pagination.cursor,
+                sort,
+                &tags,
+            )
+            .await?;
+
+        let next_cursor = if items.len() > page_size as usize {
+            items.truncate(page_size as usize);
+            items.last().map(|question| sort.cursor(question))
+        } else {
+            None
+        };
+
+        page_reply(
+            Page {
+                items,
+                next_cursor: next_cursor.map(|cursor| cursor.encode()),
+                total,
+            },
+            &query,
+        )
+    };
+
+    Ok(page)
+}
+
+/// Serializes a page of questions and adds the `Link` header
+/// if there is a next page
+fn page_reply<T: Serialize>(page: Page<T>, query: &[(String, String)]) -> warp::reply::Response {
+    let link = page.link_header("/questions", query);
+    let mut res = warp::reply::json(&page).into_response();
+
+    if let Some(value) = link.and_then(|link| HeaderValue::from_str(&link).ok()) {
+        res.headers_mut().insert(LINK, value);
+    }
+
+    res
+}
+
+pub async fn get_question(id: i32, store: Store) -> Result<impl warp::Reply, warp::Rejection> {
+    match store.get_question(id).await {
+        Ok(res) => Ok(warp::reply::json(&res)),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+pub async fn update_question(
+    id: i32,
+    session: Session,
+    store: Store,
+    question: NewQuestion,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let account_id = session.account_id;
+    let access = store
+        .question_ownership(id, &account_id)
+        .await?
+        .require_owner_or_moderator(session.role)?;
+
+    let title = check_profanity(question.title);
+    let content = check_profanity(question.content);
+
+    let (title, content) = tokio::join!(title, content);
+
+    let question = match (title, content) {
+        (Ok(title), Ok(content)) => NewQuestion {
+            title,
+            content,
+            tags: question.tags,
+        },
+        (Err(e), _) | (_, Err(e)) => return Err(warp::reject::custom(e)),
+    };
+
+    let log = moderation_entry(
+        access,
+        &account_id,
+        ModerationAction::EditQuestion,
+        ModerationTarget::Question(QuestionId(id)),
+        None,
+    );
+
     match store
-        .get_questions(pagination.limit, pagination.offset)
+        .clone()
+        .update_question(question, id, account_id.clone(), log)
         .await
     {
         Ok(res) => Ok(warp::reply::json(&res)),
@@ -31,56 +204,143 @@ pub async fn get_questions(
     }
 }
 
-pub async fn update_question(
+pub async fn delete_question(
     id: i32,
     session: Session,
     store: Store,
-    question: Question,
 ) -> Result<impl warp::Reply, warp::Rejection> {
     let account_id = session.account_id;
-    if store.is_question_owner(id, &account_id).await? {
-        let title = check_profanity(question.title);
-        let content = check_profanity(question.content);
-
-        let (title, content) = tokio::join!(title, content);
-
-        if title.is_ok() && content.is_ok() {
-            let question = Question {
-                id: question.id,
-                title: title.unwrap(),
-                content: content.unwrap(),
-                tags: question.tags,
-            };
-            match store.update_question(question, id, account_id).await {
-                Ok(res) => Ok(warp::reply::json(&res)),
-                Err(e) => Err(warp::reject::custom(e)),
-            }
-        } else {
-            Err(warp::reject::custom(
-                title.expect_err("Expected API call to have failed here"),
-            ))
-        }
-    } else {
-        Err(warp::reject::custom(handle_errors::Error::Unauthorized))
+    let access = store
+        .question_ownership(id, &account_id)
+        .await?
+        .require_owner_or_moderator(session.role)?;
+
+    let log = moderation_entry(
+        access,
+        &account_id,
+        ModerationAction::DeleteQuestion,
+        ModerationTarget::Question(QuestionId(id)),
+        None,
+    );
+
+    match store
+        .clone()
+        .delete_question(id, account_id.clone(), log)
+        .await
+    {
+        Ok(_) => Ok(warp::reply::with_status(
+            format!("Question {} deleted", id),
+            StatusCode::OK,
+        )),
+        Err(e) => Err(warp::reject::custom(e)),
     }
 }
 
-pub async fn delete_question(
+pub async fn restore_question(
     id: i32,
     session: Session,
     store: Store,
 ) -> Result<impl warp::Reply, warp::Rejection> {
     let account_id = session.account_id;
-    if store.is_question_owner(id, &account_id).await? {
-        match store.delete_question(id, account_id).await {
-            Ok(_) => Ok(warp::reply::with_status(
-                format!("Question {} deleted", id),
-                StatusCode::OK,
-            )),
-            Err(e) => Err(warp::reject::custom(e)),
-        }
-    } else {
-        Err(warp::reject::custom(handle_errors::Error::Unauthorized))
+    let access = store
+        .deleted_question_ownership(id, &account_id)
+        .await?
+        .require_owner_or_moderator(session.role)?;
+
+    let log = moderation_entry(
+        access,
+        &account_id,
+        ModerationAction::RestoreQuestion,
+        ModerationTarget::Question(QuestionId(id)),
+        None,
+    );
+
+    match store.clone().restore_question(id, log).await {
+        Ok(res) => Ok(warp::reply::json(&res)),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+/// Closed questions stay visible, but take no new answers
+pub async fn close_question(
+    id: i32,
+    session: Session,
+    store: Store,
+    close: CloseQuestion,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let account_id = session.account_id;
+    let access = store
+        .question_ownership(id, &account_id)
+        .await?
+        .require_owner_or_moderator(session.role)?;
+
+    let log = moderation_entry(
+        access,
+        &account_id,
+        ModerationAction::CloseQuestion,
+        ModerationTarget::Question(QuestionId(id)),
+        Some(close.reason.clone()),
+    );
+
+    match store
+        .clone()
+        .close_question(id, close.reason.clone(), account_id.clone(), log)
+        .await
+    {
+        Ok(_) => Ok(warp::reply::with_status(
+            format!("Question {} closed", id),
+            StatusCode::OK,
+        )),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+pub async fn reopen_question(
+    id: i32,
+    session: Session,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let account_id = session.account_id;
+    let access = store
+        .question_ownership(id, &account_id)
+        .await?
+        .require_owner_or_moderator(session.role)?;
+
+    let log = moderation_entry(
+        access,
+        &account_id,
+        ModerationAction::ReopenQuestion,
+        ModerationTarget::Question(QuestionId(id)),
+        None,
+    );
+
+    match store.clone().reopen_question(id, log).await {
+        Ok(_) => Ok(warp::reply::with_status(
+            format!("Question {} reopened", id),
+            StatusCode::OK,
+        )),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+pub async fn accept_answer(
+    id: i32,
+    session: Session,
+    store: Store,
+    accepted: AcceptedAnswer,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let account_id = session.account_id;
+    store
+        .question_ownership(id, &account_id)
+        .await?
+        .require_owner()?;
+
+    match store
+        .set_accepted_answer(id, accepted.answer_id, account_id)
+        .await
+    {
+        Ok(res) => Ok(warp::reply::json(&res)),
+        Err(e) => Err(warp::reject::custom(e)),
     }
 }
 
@@ -0,0 +1,78 @@
+use std::collections::HashMap;
+
+use tracing::{event, instrument, Level};
+
+use crate::routes::moderation::moderation_entry;
+use crate::store::Store;
+use crate::types::account::Session;
+use crate::types::moderation::{ModerationAction, ModerationTarget};
+use crate::types::question::QuestionId;
+use crate::types::revision::RevisionDiff;
+
+#[instrument]
+pub async fn get_revisions(id: i32, store: Store) -> Result<impl warp::Reply, warp::Rejection> {
+    event!(Level::INFO, "querying revisions of question {}", id);
+    match store.get_revisions(id).await {
+        Ok(res) => Ok(warp::reply::json(&res)),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+/// Compares two revisions of a question
+/// # Example query
+/// `/questions/1/revisions/diff?from=1&to=3`
+#[instrument]
+pub async fn get_revision_diff(
+    id: i32,
+    params: HashMap<String, String>,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let (from, to) = match (params.get("from"), params.get("to")) {
+        (Some(from), Some(to)) => (
+            from.parse::<i32>()
+                .map_err(handle_errors::Error::ParseError)?,
+            to.parse::<i32>()
+                .map_err(handle_errors::Error::ParseError)?,
+        ),
+        _ => {
+            return Err(warp::reject::custom(
+                handle_errors::Error::MissingParameters,
+            ))
+        }
+    };
+
+    let from = store.clone().get_revision(id, from).await?;
+    let to = store.get_revision(id, to).await?;
+
+    Ok(warp::reply::json(&RevisionDiff::new(&from, &to)))
+}
+
+pub async fn rollback_question(
+    id: i32,
+    revision: i32,
+    session: Session,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let account_id = session.account_id;
+    let access = store
+        .question_ownership(id, &account_id)
+        .await?
+        .require_owner_or_moderator(session.role)?;
+
+    let log = moderation_entry(
+        access,
+        &account_id,
+        ModerationAction::RollbackQuestion,
+        ModerationTarget::Question(QuestionId(id)),
+        Some(format!("Rolled back to revision {}", revision)),
+    );
+
+    match store
+        .clone()
+        .rollback_question(id, revision, account_id.clone(), log)
+        .await
+    {
+        Ok(res) => Ok(warp::reply::json(&res)),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
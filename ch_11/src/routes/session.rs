@@ -0,0 +1,35 @@
+use warp::http::StatusCode;
+
+use crate::store::Store;
+use crate::types::account::{Session, TokenId};
+
+pub async fn get_sessions(
+    session: Session,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    match store
+        .get_sessions(&session.account_id, &session.token_id)
+        .await
+    {
+        Ok(res) => Ok(warp::reply::json(&res)),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+/// Ends one of the sessions of the account, e.g. the login on a lost device
+pub async fn revoke_session(
+    id: i32,
+    session: Session,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    match store
+        .revoke_session(&TokenId(id), &session.account_id)
+        .await
+    {
+        Ok(_) => Ok(warp::reply::with_status(
+            format!("Session {} revoked", id),
+            StatusCode::OK,
+        )),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
@@ -0,0 +1,28 @@
+use std::collections::HashMap;
+
+use tracing::{event, instrument, Level};
+
+use crate::store::Store;
+use crate::types::tag::extract_tag_limit;
+
+/// Lists the tags in use, most used first. `prefix` narrows the list down
+/// for autocompletion, `limit` caps the number of returned tags,
+/// which are 20 by default and 100 at most
+#[instrument]
+pub async fn get_tags(
+    params: HashMap<String, String>,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    event!(Level::INFO, "querying tags");
+    let prefix = params
+        .get("prefix")
+        .map(|prefix| prefix.trim().to_string())
+        .filter(|prefix| !prefix.is_empty());
+
+    let limit = extract_tag_limit(&params)?;
+
+    match store.get_tags(prefix, limit).await {
+        Ok(res) => Ok(warp::reply::json(&res)),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
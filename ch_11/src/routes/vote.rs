@@ -0,0 +1,61 @@
+use crate::store::Store;
+use crate::types::account::Session;
+use crate::types::answer::AnswerId;
+use crate::types::question::QuestionId;
+use crate::types::vote::{NewVote, VoteTarget};
+
+pub async fn vote_question(
+    id: i32,
+    session: Session,
+    store: Store,
+    vote: NewVote,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let target = VoteTarget::Question(QuestionId(id));
+    match store
+        .add_vote(target, vote.direction, session.account_id)
+        .await
+    {
+        Ok(res) => Ok(warp::reply::json(&res)),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+pub async fn retract_question_vote(
+    id: i32,
+    session: Session,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let target = VoteTarget::Question(QuestionId(id));
+    match store.delete_vote(target, session.account_id).await {
+        Ok(res) => Ok(warp::reply::json(&res)),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+pub async fn vote_answer(
+    id: i32,
+    session: Session,
+    store: Store,
+    vote: NewVote,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let target = VoteTarget::Answer(AnswerId(id));
+    match store
+        .add_vote(target, vote.direction, session.account_id)
+        .await
+    {
+        Ok(res) => Ok(warp::reply::json(&res)),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+pub async fn retract_answer_vote(
+    id: i32,
+    session: Session,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let target = VoteTarget::Answer(AnswerId(id));
+    match store.delete_vote(target, session.account_id).await {
+        Ok(res) => Ok(warp::reply::json(&res)),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
@@ -1,14 +1,28 @@
+use chrono::{NaiveDateTime, Utc};
 use sqlx::{
-    postgres::{PgPool, PgPoolOptions, PgRow},
-    Row,
+    postgres::{PgPool, PgPoolOptions, PgRow, Postgres},
+    Row, Transaction,
 };
 
 use handle_errors::Error;
 
 use crate::types::{
-    account::{Account, AccountId},
-    answer::Answer,
-    question::{NewQuestion, Question, QuestionId},
+    account::{
+        Account, AccountExport, AccountId, AccountInfo, EmailVerification, LoginAttempt, LoginKey,
+        Ownership, PasswordReset, Profile, RefreshSession, Role, SessionInfo, TokenId,
+        UpdateProfile, DELETED_ACCOUNT,
+    },
+    answer::{Answer, AnswerId, NewAnswer},
+    api_key::{ApiKeyId, ApiKeyInfo, Scope, StoredApiKey},
+    comment::{Comment, CommentId, CommentTarget},
+    moderation::{ModerationLogEntry, ModerationTarget, NewModerationLogEntry},
+    pagination::Cursor,
+    question::{
+        NewQuestion, Question, QuestionDetail, QuestionId, QuestionSearchResult, QuestionSort,
+    },
+    revision::Revision,
+    tag::{Tag, TagFilter},
+    vote::{Score, VoteDirection, VoteTarget},
 };
 
 #[derive(Debug, Clone)]
@@ -16,6 +30,112 @@ pub struct Store {
     pub connection: PgPool,
 }
 
+/// SQLSTATE of a unique constraint violation
+const UNIQUE_VIOLATION: &str = "23505";
+/// SQLSTATE of a foreign key constraint violation
+const FOREIGN_KEY_VIOLATION: &str = "23503";
+
+/// Role of an account row, unknown values fall back to the least privileged
+fn role(row: &PgRow) -> Role {
+    row.get::<String, _>("role").parse().unwrap_or_default()
+}
+
+/// Account of a row with the `id`, `email`, `role` and `email_verified_at` columns
+fn account_info(row: PgRow) -> AccountInfo {
+    AccountInfo {
+        id: AccountId(row.get("id")),
+        email: row.get("email"),
+        role: role(&row),
+        email_verified: row
+            .get::<Option<NaiveDateTime>, _>("email_verified_at")
+            .is_some(),
+    }
+}
+
+/// Profile of a row with the columns of `account_info()`, `display_name` and `bio`
+fn profile(row: PgRow) -> Profile {
+    Profile {
+        id: AccountId(row.get("id")),
+        email: row.get("email"),
+        role: role(&row),
+        email_verified: row
+            .get::<Option<NaiveDateTime>, _>("email_verified_at")
+            .is_some(),
+        display_name: row.get("display_name"),
+        bio: row.get("bio"),
+    }
+}
+
+/// Question of a row with the `questions` columns, `score` and `answer_count`
+fn question(row: &PgRow) -> Question {
+    Question {
+        id: QuestionId(row.get("id")),
+        title: row.get("title"),
+        content: row.get("content"),
+        tags: row.get("tags"),
+        accepted_answer: row.get::<Option<i32>, _>("accepted_answer").map(AnswerId),
+        score: row.get("score"),
+        created_on: row.get("created_on"),
+        answer_count: row.get("answer_count"),
+    }
+}
+
+/// Answer of a row with the `answers` columns and `score`
+fn answer(row: &PgRow) -> Answer {
+    Answer {
+        id: AnswerId(row.get("id")),
+        content: row.get("content"),
+        question_id: QuestionId(row.get("corresponding_question")),
+        account_id: AccountId(row.get("account_id")),
+        created_on: row.get("created_on"),
+        score: row.get("score"),
+    }
+}
+
+/// Comment of a row with the `comments` columns
+fn comment(row: &PgRow) -> Comment {
+    Comment {
+        id: CommentId(row.get("id")),
+        content: row.get("content"),
+        question_id: row.get::<Option<i32>, _>("question_id").map(QuestionId),
+        answer_id: row.get::<Option<i32>, _>("answer_id").map(AnswerId),
+        account_id: AccountId(row.get("account_id")),
+        created_on: row.get("created_on"),
+        updated_on: row.get("updated_on"),
+    }
+}
+
+/// Scopes of an API key row, unknown values are dropped
+fn scopes(row: &PgRow) -> Vec<Scope> {
+    row.get::<Vec<String>, _>("scopes")
+        .iter()
+        .filter_map(|scope| scope.parse().ok())
+        .collect()
+}
+
+/// Turns a failed query into the error the client gets to see. Every
+/// query of the store goes through here, so constraint violations
+/// and connection problems are classified the same way everywhere
+fn database_error(error: sqlx::Error) -> Error {
+    let (code, constraint) = match error.as_database_error() {
+        Some(db_error) => (
+            db_error.code().map(|code| code.into_owned()),
+            db_error.constraint().unwrap_or_default().to_string(),
+        ),
+        None => (None, String::new()),
+    };
+
+    match (code.as_deref(), error) {
+        (Some(UNIQUE_VIOLATION), _) => Error::Conflict(constraint),
+        (Some(FOREIGN_KEY_VIOLATION), _) => Error::ForeignKeyViolation(constraint),
+        (_, sqlx::Error::RowNotFound) => Error::NotFound,
+        (_, sqlx::Error::PoolTimedOut | sqlx::Error::PoolClosed | sqlx::Error::Io(_)) => {
+            Error::Unavailable
+        }
+        (_, error) => Error::DatabaseQueryError(error),
+    }
+}
+
 impl Store {
     pub async fn new(db_url: &str) -> Result<Self, sqlx::Error> {
         tracing::warn!("{}", db_url);
@@ -33,42 +153,293 @@ impl Store {
         self,
         limit: Option<i32>,
         offset: i32,
+        after: Option<Cursor>,
+        sort: QuestionSort,
+        tags: &TagFilter,
     ) -> Result<Vec<Question>, Error> {
-        match sqlx::query("SELECT * from questions LIMIT $1 OFFSET $2")
+        // $3 is the id of the last question of the previous page,
+        // $6 and $7 its date or numeric sort key
+        let (filter, order) = match sort {
+            QuestionSort::Id => ("($3::int IS NULL OR id > $3)", "id"),
+            QuestionSort::Newest => (
+                "($3::int IS NULL OR (created_on, id) < ($6, $3))",
+                "created_on DESC, id DESC",
+            ),
+            QuestionSort::Oldest => (
+                "($3::int IS NULL OR (created_on, id) > ($6, $3))",
+                "created_on, id",
+            ),
+            QuestionSort::MostAnswered => (
+                "($3::int IS NULL OR (answer_count, id) < ($7, $3))",
+                "answer_count DESC, id DESC",
+            ),
+            QuestionSort::Unanswered => (
+                "answer_count = 0 AND ($3::int IS NULL OR (created_on, id) < ($6, $3))",
+                "created_on DESC, id DESC",
+            ),
+            QuestionSort::Score => (
+                "($3::int IS NULL OR (score, id) < ($7, $3))",
+                "score DESC, id DESC",
+            ),
+        };
+        let query = format!(
+            "SELECT * FROM questions
+            WHERE deleted_at IS NULL
+            AND {}
+            AND ($4::text[] IS NULL OR tags && $4)
+            AND ($5::text[] IS NULL OR tags @> $5)
+            ORDER BY {}
+            LIMIT $1 OFFSET $2",
+            filter, order
+        );
+        let after = after.unwrap_or_default();
+
+        match sqlx::query(&query)
             .bind(limit)
             .bind(offset)
-            .map(|row: PgRow| Question {
-                id: QuestionId(row.get("id")),
-                title: row.get("title"),
-                content: row.get("content"),
-                tags: row.get("tags"),
-            })
+            .bind(after.id)
+            .bind(tags.any_of())
+            .bind(tags.all_of())
+            .bind(after.created_on)
+            .bind(after.value)
+            .map(|row: PgRow| question(&row))
             .fetch_all(&self.connection)
             .await
         {
             Ok(questions) => Ok(questions),
             Err(e) => {
                 tracing::event!(tracing::Level::ERROR, "{:?}", e);
-                Err(Error::DatabaseQueryError(e))
+                Err(database_error(e))
+            }
+        }
+    }
+
+    pub async fn search_questions(
+        self,
+        search: String,
+        limit: Option<i32>,
+        offset: i32,
+        tags: &TagFilter,
+    ) -> Result<Vec<QuestionSearchResult>, Error> {
+        match sqlx::query(
+            "SELECT questions.*,
+            ts_rank(search_vector, search) AS rank,
+            ts_headline('english', content, search,
+                'StartSel=<mark>, StopSel=</mark>, MaxFragments=2') AS snippet
+            FROM questions, websearch_to_tsquery('english', $1) search
+            WHERE search_vector @@ search
+            AND deleted_at IS NULL
+            AND ($4::text[] IS NULL OR tags && $4)
+            AND ($5::text[] IS NULL OR tags @> $5)
+            ORDER BY rank DESC, id
+            LIMIT $2 OFFSET $3",
+        )
+        .bind(search)
+        .bind(limit)
+        .bind(offset)
+        .bind(tags.any_of())
+        .bind(tags.all_of())
+        .map(|row: PgRow| QuestionSearchResult {
+            question: question(&row),
+            rank: row.get("rank"),
+            snippet: row.get("snippet"),
+        })
+        .fetch_all(&self.connection)
+        .await
+        {
+            Ok(questions) => Ok(questions),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(database_error(e))
+            }
+        }
+    }
+
+    pub async fn count_questions(
+        self,
+        search: Option<String>,
+        unanswered: bool,
+        tags: &TagFilter,
+    ) -> Result<i64, Error> {
+        match sqlx::query(
+            "SELECT COUNT(*) AS total FROM questions
+            WHERE deleted_at IS NULL
+            AND ($1::text IS NULL OR search_vector @@ websearch_to_tsquery('english', $1))
+            AND ($2::text[] IS NULL OR tags && $2)
+            AND ($3::text[] IS NULL OR tags @> $3)
+            AND (NOT $4 OR answer_count = 0)",
+        )
+        .bind(search)
+        .bind(tags.any_of())
+        .bind(tags.all_of())
+        .bind(unanswered)
+        .map(|row: PgRow| row.get("total"))
+        .fetch_one(&self.connection)
+        .await
+        {
+            Ok(total) => Ok(total),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(database_error(e))
+            }
+        }
+    }
+
+    pub async fn get_tags(self, prefix: Option<String>, limit: i32) -> Result<Vec<Tag>, Error> {
+        match sqlx::query(
+            "SELECT tag, COUNT(*) AS questions
+            FROM questions, unnest(tags) AS tag
+            WHERE deleted_at IS NULL
+            AND ($1::text IS NULL OR starts_with(lower(tag), lower($1)))
+            GROUP BY tag
+            ORDER BY questions DESC, tag
+            LIMIT $2",
+        )
+        .bind(prefix)
+        .bind(limit)
+        .map(|row: PgRow| Tag {
+            name: row.get("tag"),
+            questions: row.get("questions"),
+        })
+        .fetch_all(&self.connection)
+        .await
+        {
+            Ok(tags) => Ok(tags),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(database_error(e))
+            }
+        }
+    }
+
+    pub async fn get_question(self, id: i32) -> Result<QuestionDetail, Error> {
+        let question =
+            match sqlx::query("SELECT * FROM questions WHERE id = $1 AND deleted_at IS NULL")
+                .bind(id)
+                .map(|row: PgRow| QuestionDetail {
+                    id: QuestionId(row.get("id")),
+                    title: row.get("title"),
+                    content: row.get("content"),
+                    tags: row.get("tags"),
+                    account_id: AccountId(row.get("account_id")),
+                    created_on: row.get("created_on"),
+                    accepted_answer: row.get::<Option<i32>, _>("accepted_answer").map(AnswerId),
+                    score: row.get("score"),
+                    closed_on: row.get("closed_at"),
+                    close_reason: row.get("close_reason"),
+                    answers: Vec::new(),
+                })
+                .fetch_optional(&self.connection)
+                .await
+            {
+                Ok(Some(question)) => question,
+                Ok(None) => return Err(Error::NotFound),
+                Err(e) => {
+                    tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                    return Err(database_error(e));
+                }
+            };
+
+        match sqlx::query(
+            "SELECT a.*,
+            COALESCE((SELECT SUM(value) FROM votes WHERE votes.answer_id = a.id), 0) AS score
+            FROM answers a
+            JOIN questions q ON q.id = a.corresponding_question
+            WHERE a.corresponding_question = $1
+            AND q.deleted_at IS NULL
+            ORDER BY (a.id = q.accepted_answer) IS TRUE DESC, a.created_on, a.id",
+        )
+        .bind(id)
+        .map(|row: PgRow| answer(&row))
+        .fetch_all(&self.connection)
+        .await
+        {
+            Ok(answers) => Ok(QuestionDetail {
+                answers,
+                ..question
+            }),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(database_error(e))
             }
         }
     }
 
-    pub async fn is_question_owner(
+    /// Whether the question exists and belongs to the account.
+    /// Deleted questions count as missing
+    pub async fn question_ownership(
         &self,
         question_id: i32,
         account_id: &AccountId,
-    ) -> Result<bool, Error> {
-        match sqlx::query("SELECT * from questions where id = $1 and account_id = $2")
+    ) -> Result<Ownership, Error> {
+        match sqlx::query("SELECT account_id from questions where id = $1 AND deleted_at IS NULL")
             .bind(question_id)
-            .bind(account_id.0)
+            .map(|row: PgRow| AccountId(row.get("account_id")))
+            .fetch_optional(&self.connection)
+            .await
+        {
+            Ok(owner) => Ok(Ownership::new(owner, account_id)),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(database_error(e))
+            }
+        }
+    }
+
+    /// Whether the question was deleted and belongs to the account
+    pub async fn deleted_question_ownership(
+        &self,
+        question_id: i32,
+        account_id: &AccountId,
+    ) -> Result<Ownership, Error> {
+        match sqlx::query(
+            "SELECT account_id from questions where id = $1 AND deleted_at IS NOT NULL",
+        )
+        .bind(question_id)
+        .map(|row: PgRow| AccountId(row.get("account_id")))
+        .fetch_optional(&self.connection)
+        .await
+        {
+            Ok(owner) => Ok(Ownership::new(owner, account_id)),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(database_error(e))
+            }
+        }
+    }
+
+    /// Answers 404 for a question which doesn't exist or was deleted,
+    /// so nothing below a deleted question can be read or changed
+    async fn require_question(&self, id: i32) -> Result<(), Error> {
+        match sqlx::query("SELECT 1 FROM questions WHERE id = $1 AND deleted_at IS NULL")
+            .bind(id)
             .fetch_optional(&self.connection)
             .await
         {
-            Ok(question) => Ok(question.is_some()),
+            Ok(Some(_)) => Ok(()),
+            Ok(None) => Err(Error::NotFound),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(database_error(e))
+            }
+        }
+    }
+
+    /// Like `require_question()`, answers of deleted questions count as missing
+    async fn require_answer(&self, id: i32) -> Result<(), Error> {
+        match sqlx::query(
+            "SELECT 1 FROM answers a JOIN questions q ON q.id = a.corresponding_question
+            WHERE a.id = $1 AND q.deleted_at IS NULL",
+        )
+        .bind(id)
+        .fetch_optional(&self.connection)
+        .await
+        {
+            Ok(Some(_)) => Ok(()),
+            Ok(None) => Err(Error::NotFound),
             Err(e) => {
                 tracing::event!(tracing::Level::ERROR, "{:?}", e);
-                Err(Error::DatabaseQueryError(e))
+                Err(database_error(e))
             }
         }
     }
@@ -78,145 +449,1920 @@ impl Store {
         new_question: NewQuestion,
         account_id: AccountId,
     ) -> Result<Question, Error> {
-        match sqlx::query("INSERT INTO questions (title, content, tags, account_id) VALUES ($1, $2, $3, $4) RETURNING id, title, content, tags")
-            .bind(new_question.title)
-            .bind(new_question.content)
-            .bind(new_question.tags)
-            .bind(account_id.0)
-            .map(|row: PgRow| Question {
-			    id: QuestionId(row.get("id")),
-                title: row.get("title"),
-			    content: row.get("content"),
-                tags: row.get("tags"),
-		    })
-            .fetch_one(&self.connection)
-            .await {
-                Ok(question) => Ok(question),
-                Err(error) => {
-                    tracing::event!(tracing::Level::ERROR, "{:?}", error);
-                    Err(Error::DatabaseQueryError(error))
-                },
+        let mut tx = self.connection.begin().await.map_err(database_error)?;
+
+        let question = match sqlx::query(
+            "INSERT INTO questions (title, content, tags, account_id) VALUES ($1, $2, $3, $4)
+            RETURNING id, title, content, tags, accepted_answer, created_on, answer_count, score",
+        )
+        .bind(new_question.title)
+        .bind(new_question.content)
+        .bind(new_question.tags)
+        .bind(account_id.0)
+        .map(|row: PgRow| question(&row))
+        .fetch_one(&mut tx)
+        .await
+        {
+            Ok(question) => question,
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                return Err(database_error(error));
             }
+        };
+
+        Store::add_revision(&mut tx, &question, &account_id).await?;
+        tx.commit().await.map_err(database_error)?;
+
+        Ok(question)
     }
 
     pub async fn update_question(
         self,
-        question: Question,
+        new_question: NewQuestion,
         id: i32,
         account_id: AccountId,
+        log: Option<NewModerationLogEntry>,
     ) -> Result<Question, Error> {
-        match sqlx::query(
+        let mut tx = self.connection.begin().await.map_err(database_error)?;
+
+        let question = match sqlx::query(
             "UPDATE questions SET title = $1, content = $2, tags = $3
-        WHERE id = $4 AND account_id = $5
-        RETURNING id, title, content, tags",
+        WHERE id = $4 AND deleted_at IS NULL
+        RETURNING id, title, content, tags, accepted_answer, created_on, answer_count, score",
         )
-        .bind(question.title)
-        .bind(question.content)
-        .bind(question.tags)
+        .bind(new_question.title)
+        .bind(new_question.content)
+        .bind(new_question.tags)
         .bind(id)
+        .map(|row: PgRow| question(&row))
+        .fetch_optional(&mut tx)
+        .await
+        {
+            Ok(Some(question)) => question,
+            Ok(None) => return Err(Error::NotFound),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                return Err(database_error(error));
+            }
+        };
+
+        Store::add_revision(&mut tx, &question, &account_id).await?;
+        Store::add_moderation_log(&mut tx, log).await?;
+        tx.commit().await.map_err(database_error)?;
+
+        Ok(question)
+    }
+
+    /// Saves the current state of a question as its next revision,
+    /// inside the transaction which changed the question. The question
+    /// row stays locked until the transaction ends, so concurrent edits
+    /// can't pick the same revision number
+    async fn add_revision(
+        tx: &mut Transaction<'_, Postgres>,
+        question: &Question,
+        account_id: &AccountId,
+    ) -> Result<(), Error> {
+        if let Err(error) = sqlx::query("SELECT 1 FROM questions WHERE id = $1 FOR UPDATE")
+            .bind(question.id.0)
+            .execute(&mut *tx)
+            .await
+        {
+            tracing::event!(tracing::Level::ERROR, "{:?}", error);
+            return Err(database_error(error));
+        }
+
+        match sqlx::query(
+            "INSERT INTO question_revisions (question_id, revision, title, content, tags, account_id)
+            SELECT $1, COALESCE(MAX(revision), 0) + 1, $2, $3, $4, $5
+            FROM question_revisions WHERE question_id = $1",
+        )
+        .bind(question.id.0)
+        .bind(&question.title)
+        .bind(&question.content)
+        .bind(&question.tags)
         .bind(account_id.0)
-        .map(|row: PgRow| Question {
-            id: QuestionId(row.get("id")),
+        .execute(&mut *tx)
+        .await
+        {
+            Ok(_) => Ok(()),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(database_error(error))
+            }
+        }
+    }
+
+    pub async fn get_revisions(self, question_id: i32) -> Result<Vec<Revision>, Error> {
+        self.require_question(question_id).await?;
+
+        match sqlx::query(
+            "SELECT * from question_revisions WHERE question_id = $1 ORDER BY revision",
+        )
+        .bind(question_id)
+        .map(|row: PgRow| Revision {
+            question_id: QuestionId(row.get("question_id")),
+            revision: row.get("revision"),
             title: row.get("title"),
             content: row.get("content"),
             tags: row.get("tags"),
+            account_id: AccountId(row.get("account_id")),
+            created_on: row.get("created_on"),
         })
-        .fetch_one(&self.connection)
+        .fetch_all(&self.connection)
         .await
         {
-            Ok(question) => Ok(question),
-            Err(error) => {
-                tracing::event!(tracing::Level::ERROR, "{:?}", error);
-                Err(Error::DatabaseQueryError(error))
+            Ok(revisions) => Ok(revisions),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(database_error(e))
             }
         }
     }
 
-    pub async fn delete_question(self, id: i32, account_id: AccountId) -> Result<bool, Error> {
-        match sqlx::query("DELETE FROM questions WHERE id = $1 AND account_id = $2")
-            .bind(id)
-            .bind(account_id.0)
-            .execute(&self.connection)
-            .await
+    pub async fn get_revision(self, question_id: i32, revision: i32) -> Result<Revision, Error> {
+        match sqlx::query(
+            "SELECT r.* from question_revisions r
+            JOIN questions q ON q.id = r.question_id
+            WHERE r.question_id = $1 AND r.revision = $2 AND q.deleted_at IS NULL",
+        )
+        .bind(question_id)
+        .bind(revision)
+        .map(|row: PgRow| Revision {
+            question_id: QuestionId(row.get("question_id")),
+            revision: row.get("revision"),
+            title: row.get("title"),
+            content: row.get("content"),
+            tags: row.get("tags"),
+            account_id: AccountId(row.get("account_id")),
+            created_on: row.get("created_on"),
+        })
+        .fetch_optional(&self.connection)
+        .await
         {
-            Ok(_) => Ok(true),
+            Ok(Some(revision)) => Ok(revision),
+            Ok(None) => Err(Error::NotFound),
             Err(e) => {
                 tracing::event!(tracing::Level::ERROR, "{:?}", e);
-                Err(Error::DatabaseQueryError(e))
+                Err(database_error(e))
             }
         }
     }
 
-    pub async fn add_answer(self, answer: Answer, account_id: AccountId) -> Result<bool, Error> {
+    /// Restores title, content and tags of an earlier revision.
+    /// The rollback itself is saved as a new revision
+    pub async fn rollback_question(
+        self,
+        question_id: i32,
+        revision: i32,
+        account_id: AccountId,
+        log: Option<NewModerationLogEntry>,
+    ) -> Result<Question, Error> {
+        let revision = self.clone().get_revision(question_id, revision).await?;
+
+        self.update_question(
+            NewQuestion {
+                title: revision.title,
+                content: revision.content,
+                tags: revision.tags,
+            },
+            question_id,
+            account_id,
+            log,
+        )
+        .await
+    }
+
+    /// Questions are never removed from the database, deleting one only
+    /// sets its `deleted_at` tombstone. It disappears from all listings
+    /// together with its answers, which stay untouched so restoring the
+    /// question brings them back as well
+    pub async fn delete_question(
+        self,
+        id: i32,
+        account_id: AccountId,
+        log: Option<NewModerationLogEntry>,
+    ) -> Result<bool, Error> {
+        let mut tx = self.connection.begin().await.map_err(database_error)?;
+
         match sqlx::query(
-            "INSERT INTO answers (content, corresponding_question, account_id) VALUES ($1, $2, $3)",
+            "UPDATE questions SET deleted_at = NOW(), deleted_by = $2
+            WHERE id = $1 AND deleted_at IS NULL",
         )
-        .bind(answer.content)
-        .bind(answer.question_id)
+        .bind(id)
         .bind(account_id.0)
-        .execute(&self.connection)
+        .execute(&mut tx)
         .await
         {
-            Ok(_) => Ok(true),
+            Ok(res) if res.rows_affected() == 0 => return Err(Error::NotFound),
+            Ok(_) => (),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                return Err(database_error(e));
+            }
+        }
+
+        Store::add_moderation_log(&mut tx, log).await?;
+        tx.commit().await.map_err(database_error)?;
+
+        Ok(true)
+    }
+
+    pub async fn restore_question(
+        self,
+        id: i32,
+        log: Option<NewModerationLogEntry>,
+    ) -> Result<Question, Error> {
+        let mut tx = self.connection.begin().await.map_err(database_error)?;
+
+        let question = match sqlx::query(
+            "UPDATE questions SET deleted_at = NULL, deleted_by = NULL
+        WHERE id = $1 AND deleted_at IS NOT NULL
+        RETURNING id, title, content, tags, accepted_answer, created_on, answer_count, score",
+        )
+        .bind(id)
+        .map(|row: PgRow| question(&row))
+        .fetch_optional(&mut tx)
+        .await
+        {
+            Ok(Some(question)) => question,
+            // The question isn't deleted
+            Ok(None) => return Err(Error::NotFound),
             Err(error) => {
-                tracing::event!(
-                    tracing::Level::ERROR,
-                    code = error
-                        .as_database_error()
-                        .unwrap()
-                        .code()
-                        .unwrap()
-                        .parse::<i32>()
-                        .unwrap(),
-                    db_message = error.as_database_error().unwrap().message(),
-                    constraint = error.as_database_error().unwrap().constraint().unwrap()
-                );
-                Err(Error::DatabaseQueryError(error))
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                return Err(database_error(error));
+            }
+        };
+
+        Store::add_moderation_log(&mut tx, log).await?;
+        tx.commit().await.map_err(database_error)?;
+
+        Ok(question)
+    }
+
+    /// Deleted questions count as missing rather than closed
+    async fn is_question_closed(&self, id: i32) -> Result<bool, Error> {
+        match sqlx::query(
+            "SELECT closed_at IS NOT NULL AS closed FROM questions
+            WHERE id = $1 AND deleted_at IS NULL",
+        )
+        .bind(id)
+        .map(|row: PgRow| row.get("closed"))
+        .fetch_optional(&self.connection)
+        .await
+        {
+            Ok(closed) => Ok(closed.unwrap_or(false)),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(database_error(error))
             }
         }
     }
 
-    pub async fn add_account(self, account: Account) -> Result<bool, Error> {
-        match sqlx::query("INSERT INTO accounts (email, password) VALUES ($1, $2)")
-            .bind(account.email)
-            .bind(account.password)
-            .execute(&self.connection)
-            .await
+    /// Closes the question for new answers
+    pub async fn close_question(
+        self,
+        id: i32,
+        reason: String,
+        account_id: AccountId,
+        log: Option<NewModerationLogEntry>,
+    ) -> Result<bool, Error> {
+        let mut tx = self.connection.begin().await.map_err(database_error)?;
+
+        match sqlx::query(
+            "UPDATE questions SET closed_at = NOW(), closed_by = $3, close_reason = $2
+            WHERE id = $1 AND deleted_at IS NULL AND closed_at IS NULL",
+        )
+        .bind(id)
+        .bind(reason)
+        .bind(account_id.0)
+        .execute(&mut tx)
+        .await
         {
-            Ok(_) => Ok(true),
+            // The question doesn't exist or is closed already
+            Ok(res) if res.rows_affected() == 0 => return Err(Error::NotFound),
+            Ok(_) => (),
             Err(error) => {
-                tracing::event!(
-                    tracing::Level::ERROR,
-                    code = error
-                        .as_database_error()
-                        .unwrap()
-                        .code()
-                        .unwrap()
-                        .parse::<i32>()
-                        .unwrap(),
-                    db_message = error.as_database_error().unwrap().message(),
-                    constraint = error.as_database_error().unwrap().constraint().unwrap()
-                );
-                Err(Error::DatabaseQueryError(error))
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                return Err(database_error(error));
             }
         }
+
+        Store::add_moderation_log(&mut tx, log).await?;
+        tx.commit().await.map_err(database_error)?;
+
+        Ok(true)
     }
 
-    pub async fn get_account(self, email: String) -> Result<Account, Error> {
-        match sqlx::query("SELECT * from accounts where email = $1")
-            .bind(email)
-            .map(|row: PgRow| Account {
-                id: Some(AccountId(row.get("id"))),
-                email: row.get("email"),
-                password: row.get("password"),
-            })
-            .fetch_one(&self.connection)
-            .await
+    pub async fn reopen_question(
+        self,
+        id: i32,
+        log: Option<NewModerationLogEntry>,
+    ) -> Result<bool, Error> {
+        let mut tx = self.connection.begin().await.map_err(database_error)?;
+
+        match sqlx::query(
+            "UPDATE questions SET closed_at = NULL, closed_by = NULL, close_reason = NULL
+            WHERE id = $1 AND deleted_at IS NULL AND closed_at IS NOT NULL",
+        )
+        .bind(id)
+        .execute(&mut tx)
+        .await
         {
-            Ok(account) => Ok(account),
+            // The question doesn't exist or isn't closed
+            Ok(res) if res.rows_affected() == 0 => return Err(Error::NotFound),
+            Ok(_) => (),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                return Err(database_error(error));
+            }
+        }
+
+        Store::add_moderation_log(&mut tx, log).await?;
+        tx.commit().await.map_err(database_error)?;
+
+        Ok(true)
+    }
+
+    pub async fn set_accepted_answer(
+        self,
+        question_id: i32,
+        answer_id: Option<AnswerId>,
+        account_id: AccountId,
+    ) -> Result<Question, Error> {
+        match sqlx::query(
+            "UPDATE questions SET accepted_answer = $1
+        WHERE id = $2 AND account_id = $3 AND deleted_at IS NULL
+        AND ($1 IS NULL OR EXISTS (
+            SELECT 1 FROM answers WHERE id = $1 AND corresponding_question = $2
+        ))
+        RETURNING id, title, content, tags, accepted_answer, created_on, answer_count, score",
+        )
+        .bind(answer_id.map(|id| id.0))
+        .bind(question_id)
+        .bind(account_id.0)
+        .map(|row: PgRow| question(&row))
+        .fetch_optional(&self.connection)
+        .await
+        {
+            Ok(Some(question)) => Ok(question),
+            // The answer does not belong to this question
+            Ok(None) => Err(Error::NotFound),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(database_error(error))
+            }
+        }
+    }
+
+    pub async fn add_answer(self, answer: NewAnswer, account_id: AccountId) -> Result<bool, Error> {
+        match sqlx::query(
+            "INSERT INTO answers (content, corresponding_question, account_id)
+            SELECT $1, $2, $3 WHERE EXISTS (
+                SELECT 1 FROM questions
+                WHERE id = $2 AND deleted_at IS NULL AND closed_at IS NULL
+            )",
+        )
+        .bind(answer.content)
+        .bind(answer.question_id.0)
+        .bind(account_id.0)
+        .execute(&self.connection)
+        .await
+        {
+            // The question doesn't exist, was deleted or is closed
+            Ok(res) if res.rows_affected() == 0 => {
+                match self.is_question_closed(answer.question_id.0).await? {
+                    true => Err(Error::QuestionClosed),
+                    false => Err(Error::NotFound),
+                }
+            }
+            Ok(_) => Ok(true),
+            Err(error) => {
+                tracing::event!(
+                    tracing::Level::ERROR,
+                    code = ?error.as_database_error().and_then(|e| e.code()),
+                    constraint = ?error.as_database_error().and_then(|e| e.constraint()),
+                    "{:?}",
+                    error
+                );
+                Err(database_error(error))
+            }
+        }
+    }
+
+    pub async fn get_answers(
+        self,
+        question_id: i32,
+        limit: Option<i32>,
+        offset: i32,
+    ) -> Result<Vec<Answer>, Error> {
+        self.require_question(question_id).await?;
+
+        match sqlx::query(
+            "SELECT a.*,
+            COALESCE((SELECT SUM(value) FROM votes WHERE votes.answer_id = a.id), 0) AS score
+            FROM answers a
+            JOIN questions q ON q.id = a.corresponding_question
+            WHERE a.corresponding_question = $1
+            AND q.deleted_at IS NULL
+            ORDER BY (a.id = q.accepted_answer) IS TRUE DESC, a.created_on, a.id
+            LIMIT $2 OFFSET $3",
+        )
+        .bind(question_id)
+        .bind(limit)
+        .bind(offset)
+        .map(|row: PgRow| answer(&row))
+        .fetch_all(&self.connection)
+        .await
+        {
+            Ok(answers) => Ok(answers),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(database_error(e))
+            }
+        }
+    }
+
+    /// Whether the answer exists and belongs to the account.
+    /// Answers of deleted questions count as missing
+    pub async fn answer_ownership(
+        &self,
+        answer_id: i32,
+        account_id: &AccountId,
+    ) -> Result<Ownership, Error> {
+        match sqlx::query(
+            "SELECT a.account_id from answers a
+            JOIN questions q ON q.id = a.corresponding_question
+            WHERE a.id = $1 AND q.deleted_at IS NULL",
+        )
+        .bind(answer_id)
+        .map(|row: PgRow| AccountId(row.get("account_id")))
+        .fetch_optional(&self.connection)
+        .await
+        {
+            Ok(owner) => Ok(Ownership::new(owner, account_id)),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(database_error(e))
+            }
+        }
+    }
+
+    pub async fn update_answer(
+        self,
+        content: String,
+        id: i32,
+        log: Option<NewModerationLogEntry>,
+    ) -> Result<Answer, Error> {
+        let mut tx = self.connection.begin().await.map_err(database_error)?;
+
+        let answer = match sqlx::query(
+            "UPDATE answers SET content = $1
+        WHERE id = $2
+        RETURNING id, content, corresponding_question, account_id, created_on,
+        COALESCE((SELECT SUM(value) FROM votes WHERE votes.answer_id = answers.id), 0) AS score",
+        )
+        .bind(content)
+        .bind(id)
+        .map(|row: PgRow| answer(&row))
+        .fetch_one(&mut tx)
+        .await
+        {
+            Ok(answer) => answer,
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                return Err(database_error(error));
+            }
+        };
+
+        Store::add_moderation_log(&mut tx, log).await?;
+        tx.commit().await.map_err(database_error)?;
+
+        Ok(answer)
+    }
+
+    pub async fn delete_answer(
+        self,
+        id: i32,
+        log: Option<NewModerationLogEntry>,
+    ) -> Result<bool, Error> {
+        let mut tx = self.connection.begin().await.map_err(database_error)?;
+
+        match sqlx::query("DELETE FROM answers WHERE id = $1")
+            .bind(id)
+            .execute(&mut tx)
+            .await
+        {
+            Ok(res) if res.rows_affected() == 0 => return Err(Error::NotFound),
+            Ok(_) => (),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                return Err(database_error(e));
+            }
+        }
+
+        Store::add_moderation_log(&mut tx, log).await?;
+        tx.commit().await.map_err(database_error)?;
+
+        Ok(true)
+    }
+
+    pub async fn get_comments(
+        self,
+        target: CommentTarget,
+        limit: Option<i32>,
+        offset: i32,
+    ) -> Result<Vec<Comment>, Error> {
+        self.require_comment_target(&target).await?;
+
+        let (query, id) = match target {
+            CommentTarget::Question(id) => (
+                "SELECT * from comments WHERE question_id = $1
+                ORDER BY created_on, id
+                LIMIT $2 OFFSET $3",
+                id.0,
+            ),
+            CommentTarget::Answer(id) => (
+                "SELECT * from comments WHERE answer_id = $1
+                ORDER BY created_on, id
+                LIMIT $2 OFFSET $3",
+                id.0,
+            ),
+        };
+
+        match sqlx::query(query)
+            .bind(id)
+            .bind(limit)
+            .bind(offset)
+            .map(|row: PgRow| comment(&row))
+            .fetch_all(&self.connection)
+            .await
+        {
+            Ok(comments) => Ok(comments),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(database_error(e))
+            }
+        }
+    }
+
+    pub async fn add_comment(
+        self,
+        target: CommentTarget,
+        content: String,
+        account_id: AccountId,
+    ) -> Result<Comment, Error> {
+        self.require_comment_target(&target).await?;
+
+        let (question_id, answer_id) = match target {
+            CommentTarget::Question(id) => (Some(id.0), None),
+            CommentTarget::Answer(id) => (None, Some(id.0)),
+        };
+
+        match sqlx::query(
+            "INSERT INTO comments (content, question_id, answer_id, account_id)
+            VALUES ($1, $2, $3, $4)
+            RETURNING *",
+        )
+        .bind(content)
+        .bind(question_id)
+        .bind(answer_id)
+        .bind(account_id.0)
+        .map(|row: PgRow| comment(&row))
+        .fetch_one(&self.connection)
+        .await
+        {
+            Ok(comment) => Ok(comment),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(database_error(error))
+            }
+        }
+    }
+
+    async fn require_comment_target(&self, target: &CommentTarget) -> Result<(), Error> {
+        match target {
+            CommentTarget::Question(id) => self.require_question(id.0).await,
+            CommentTarget::Answer(id) => self.require_answer(id.0).await,
+        }
+    }
+
+    /// Whether the comment exists and belongs to the account.
+    /// Comments below deleted questions count as missing
+    pub async fn comment_ownership(
+        &self,
+        comment_id: i32,
+        account_id: &AccountId,
+    ) -> Result<Ownership, Error> {
+        match sqlx::query(
+            "SELECT c.account_id from comments c
+            LEFT JOIN answers a ON a.id = c.answer_id
+            JOIN questions q ON q.id = COALESCE(c.question_id, a.corresponding_question)
+            WHERE c.id = $1 AND q.deleted_at IS NULL",
+        )
+        .bind(comment_id)
+        .map(|row: PgRow| AccountId(row.get("account_id")))
+        .fetch_optional(&self.connection)
+        .await
+        {
+            Ok(owner) => Ok(Ownership::new(owner, account_id)),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(database_error(e))
+            }
+        }
+    }
+
+    pub async fn update_comment(
+        self,
+        content: String,
+        id: i32,
+        account_id: AccountId,
+    ) -> Result<Comment, Error> {
+        match sqlx::query(
+            "UPDATE comments SET content = $1, updated_on = NOW()
+        WHERE id = $2 AND account_id = $3
+        RETURNING *",
+        )
+        .bind(content)
+        .bind(id)
+        .bind(account_id.0)
+        .map(|row: PgRow| comment(&row))
+        .fetch_one(&self.connection)
+        .await
+        {
+            Ok(comment) => Ok(comment),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(database_error(error))
+            }
+        }
+    }
+
+    pub async fn delete_comment(self, id: i32, account_id: AccountId) -> Result<bool, Error> {
+        match sqlx::query("DELETE FROM comments WHERE id = $1 AND account_id = $2")
+            .bind(id)
+            .bind(account_id.0)
+            .execute(&self.connection)
+            .await
+        {
+            Ok(res) => Ok(res.rows_affected() > 0),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(database_error(e))
+            }
+        }
+    }
+
+    pub async fn add_vote(
+        self,
+        target: VoteTarget,
+        direction: VoteDirection,
+        account_id: AccountId,
+    ) -> Result<Score, Error> {
+        self.require_vote_target(&target).await?;
+
+        let (query, id) = match &target {
+            VoteTarget::Question(id) => (
+                "INSERT INTO votes (question_id, account_id, value) VALUES ($1, $2, $3)
+                ON CONFLICT (question_id, account_id) WHERE question_id IS NOT NULL
+                DO UPDATE SET value = EXCLUDED.value",
+                id.0,
+            ),
+            VoteTarget::Answer(id) => (
+                "INSERT INTO votes (answer_id, account_id, value) VALUES ($1, $2, $3)
+                ON CONFLICT (answer_id, account_id) WHERE answer_id IS NOT NULL
+                DO UPDATE SET value = EXCLUDED.value",
+                id.0,
+            ),
+        };
+
+        match sqlx::query(query)
+            .bind(id)
+            .bind(account_id.0)
+            .bind(direction.value())
+            .execute(&self.connection)
+            .await
+        {
+            Ok(_) => self.get_score(target).await,
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(database_error(error))
+            }
+        }
+    }
+
+    pub async fn delete_vote(
+        self,
+        target: VoteTarget,
+        account_id: AccountId,
+    ) -> Result<Score, Error> {
+        self.require_vote_target(&target).await?;
+
+        let (query, id) = match &target {
+            VoteTarget::Question(id) => (
+                "DELETE FROM votes WHERE question_id = $1 AND account_id = $2",
+                id.0,
+            ),
+            VoteTarget::Answer(id) => (
+                "DELETE FROM votes WHERE answer_id = $1 AND account_id = $2",
+                id.0,
+            ),
+        };
+
+        match sqlx::query(query)
+            .bind(id)
+            .bind(account_id.0)
+            .execute(&self.connection)
+            .await
+        {
+            Ok(_) => self.get_score(target).await,
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(database_error(error))
+            }
+        }
+    }
+
+    async fn require_vote_target(&self, target: &VoteTarget) -> Result<(), Error> {
+        match target {
+            VoteTarget::Question(id) => self.require_question(id.0).await,
+            VoteTarget::Answer(id) => self.require_answer(id.0).await,
+        }
+    }
+
+    async fn get_score(&self, target: VoteTarget) -> Result<Score, Error> {
+        let (query, id) = match target {
+            VoteTarget::Question(id) => ("SELECT score FROM questions WHERE id = $1", id.0),
+            VoteTarget::Answer(id) => (
+                "SELECT COALESCE(SUM(value), 0) AS score FROM votes WHERE answer_id = $1",
+                id.0,
+            ),
+        };
+
+        match sqlx::query(query)
+            .bind(id)
+            .map(|row: PgRow| Score {
+                score: row.get("score"),
+            })
+            .fetch_one(&self.connection)
+            .await
+        {
+            Ok(score) => Ok(score),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(database_error(error))
+            }
+        }
+    }
+
+    /// Adds an unverified account. Emails are unique regardless of their case
+    pub async fn add_account(self, account: Account) -> Result<AccountId, Error> {
+        match sqlx::query("INSERT INTO accounts (email, password) VALUES ($1, $2) RETURNING id")
+            .bind(account.email)
+            .bind(account.password)
+            .map(|row: PgRow| AccountId(row.get("id")))
+            .fetch_one(&self.connection)
+            .await
+        {
+            Ok(account_id) => Ok(account_id),
+            Err(error) => {
+                tracing::event!(
+                    tracing::Level::ERROR,
+                    code = ?error.as_database_error().and_then(|e| e.code()),
+                    constraint = ?error.as_database_error().and_then(|e| e.constraint()),
+                    "{:?}",
+                    error
+                );
+                Err(database_error(error))
+            }
+        }
+    }
+
+    pub async fn get_account(self, email: String) -> Result<Account, Error> {
+        match sqlx::query(
+            "SELECT * from accounts where LOWER(email) = LOWER($1) AND deleted_at IS NULL",
+        )
+        .bind(email)
+        .map(|row: PgRow| Account {
+            id: Some(AccountId(row.get("id"))),
+            email: row.get("email"),
+            password: row.get("password"),
+            role: role(&row),
+        })
+        .fetch_one(&self.connection)
+        .await
+        {
+            Ok(account) => Ok(account),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(database_error(error))
+            }
+        }
+    }
+
+    pub async fn add_session(
+        &self,
+        account_id: &AccountId,
+        refresh_token: String,
+        expires_at: NaiveDateTime,
+    ) -> Result<TokenId, Error> {
+        match sqlx::query(
+            "INSERT INTO sessions (account_id, refresh_token, expires_at)
+            VALUES ($1, $2, $3)
+            RETURNING id",
+        )
+        .bind(account_id.0)
+        .bind(refresh_token)
+        .bind(expires_at)
+        .map(|row: PgRow| TokenId(row.get("id")))
+        .fetch_one(&self.connection)
+        .await
+        {
+            Ok(token_id) => Ok(token_id),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(database_error(error))
+            }
+        }
+    }
+
+    /// A session which is neither revoked nor expired
+    pub async fn get_session(&self, token_id: &TokenId) -> Result<RefreshSession, Error> {
+        match sqlx::query(
+            "SELECT s.account_id, s.refresh_token, s.previous_refresh_token, a.role
+            FROM sessions s JOIN accounts a ON a.id = s.account_id
+            WHERE s.id = $1 AND s.revoked_at IS NULL AND s.expires_at > NOW()",
+        )
+        .bind(token_id.0)
+        .map(|row: PgRow| RefreshSession {
+            account_id: AccountId(row.get("account_id")),
+            role: role(&row),
+            refresh_token: row.get("refresh_token"),
+            previous_refresh_token: row.get("previous_refresh_token"),
+        })
+        .fetch_optional(&self.connection)
+        .await
+        {
+            Ok(Some(session)) => Ok(session),
+            Ok(None) => Err(Error::NotFound),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(database_error(error))
+            }
+        }
+    }
+
+    pub async fn is_session_active(
+        &self,
+        token_id: &TokenId,
+        account_id: &AccountId,
+    ) -> Result<bool, Error> {
+        match sqlx::query(
+            "SELECT 1 FROM sessions
+            WHERE id = $1 AND account_id = $2 AND revoked_at IS NULL AND expires_at > NOW()",
+        )
+        .bind(token_id.0)
+        .bind(account_id.0)
+        .fetch_optional(&self.connection)
+        .await
+        {
+            Ok(session) => Ok(session.is_some()),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(database_error(error))
+            }
+        }
+    }
+
+    /// Replaces the hashed refresh token of a session. Only succeeds if the
+    /// session still has the old hash, so a refresh token can be used just once
+    pub async fn rotate_session(
+        &self,
+        token_id: &TokenId,
+        old_hash: &str,
+        new_hash: String,
+        expires_at: NaiveDateTime,
+    ) -> Result<bool, Error> {
+        match sqlx::query(
+            "UPDATE sessions
+            SET refresh_token = $3, previous_refresh_token = $2,
+                expires_at = $4, last_used_on = NOW()
+            WHERE id = $1 AND refresh_token = $2 AND revoked_at IS NULL",
+        )
+        .bind(token_id.0)
+        .bind(old_hash)
+        .bind(new_hash)
+        .bind(expires_at)
+        .execute(&self.connection)
+        .await
+        {
+            Ok(res) if res.rows_affected() == 0 => Err(Error::NotFound),
+            Ok(_) => Ok(true),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(database_error(error))
+            }
+        }
+    }
+
+    pub async fn revoke_session(
+        &self,
+        token_id: &TokenId,
+        account_id: &AccountId,
+    ) -> Result<bool, Error> {
+        match sqlx::query(
+            "UPDATE sessions SET revoked_at = NOW()
+            WHERE id = $1 AND account_id = $2 AND revoked_at IS NULL",
+        )
+        .bind(token_id.0)
+        .bind(account_id.0)
+        .execute(&self.connection)
+        .await
+        {
+            Ok(res) if res.rows_affected() == 0 => Err(Error::NotFound),
+            Ok(_) => Ok(true),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(database_error(error))
+            }
+        }
+    }
+
+    /// Sessions of the account which are neither revoked nor expired
+    pub async fn get_sessions(
+        &self,
+        account_id: &AccountId,
+        current: &TokenId,
+    ) -> Result<Vec<SessionInfo>, Error> {
+        match sqlx::query(
+            "SELECT id, created_on, last_used_on, expires_at FROM sessions
+            WHERE account_id = $1 AND revoked_at IS NULL AND expires_at > NOW()
+            ORDER BY last_used_on DESC",
+        )
+        .bind(account_id.0)
+        .map(|row: PgRow| {
+            let id = TokenId(row.get("id"));
+            SessionInfo {
+                current: &id == current,
+                id,
+                created_on: row.get("created_on"),
+                last_used_on: row.get("last_used_on"),
+                expires_at: row.get("expires_at"),
+            }
+        })
+        .fetch_all(&self.connection)
+        .await
+        {
+            Ok(sessions) => Ok(sessions),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(database_error(error))
+            }
+        }
+    }
+
+    pub async fn get_account_info(&self, account_id: &AccountId) -> Result<AccountInfo, Error> {
+        match sqlx::query("SELECT id, email, role, email_verified_at FROM accounts WHERE id = $1")
+            .bind(account_id.0)
+            .map(account_info)
+            .fetch_one(&self.connection)
+            .await
+        {
+            Ok(account) => Ok(account),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(database_error(error))
+            }
+        }
+    }
+
+    pub async fn get_profile(&self, account_id: &AccountId) -> Result<Profile, Error> {
+        match sqlx::query(
+            "SELECT id, email, role, email_verified_at, display_name, bio
+            FROM accounts WHERE id = $1 AND deleted_at IS NULL",
+        )
+        .bind(account_id.0)
+        .map(profile)
+        .fetch_one(&self.connection)
+        .await
+        {
+            Ok(profile) => Ok(profile),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(database_error(error))
+            }
+        }
+    }
+
+    pub async fn update_profile(
+        self,
+        account_id: &AccountId,
+        update: UpdateProfile,
+    ) -> Result<Profile, Error> {
+        match sqlx::query(
+            "UPDATE accounts SET display_name = $2, bio = $3
+            WHERE id = $1 AND deleted_at IS NULL
+            RETURNING id, email, role, email_verified_at, display_name, bio",
+        )
+        .bind(account_id.0)
+        .bind(update.display_name)
+        .bind(update.bio)
+        .map(profile)
+        .fetch_one(&self.connection)
+        .await
+        {
+            Ok(profile) => Ok(profile),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(database_error(error))
+            }
+        }
+    }
+
+    /// The password hash of the account
+    pub async fn get_password(&self, account_id: &AccountId) -> Result<String, Error> {
+        match sqlx::query("SELECT password FROM accounts WHERE id = $1 AND deleted_at IS NULL")
+            .bind(account_id.0)
+            .map(|row: PgRow| row.get("password"))
+            .fetch_one(&self.connection)
+            .await
+        {
+            Ok(password) => Ok(password),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(database_error(error))
+            }
+        }
+    }
+
+    /// Sets the new password hash, ends all other sessions of the account and
+    /// revokes its API keys. The session the password was changed with stays logged in
+    pub async fn change_password(
+        self,
+        account_id: &AccountId,
+        password: String,
+        current_session: &TokenId,
+    ) -> Result<u64, Error> {
+        let mut tx = self.connection.begin().await.map_err(database_error)?;
+
+        if let Err(error) = sqlx::query("UPDATE accounts SET password = $2 WHERE id = $1")
+            .bind(account_id.0)
+            .bind(password)
+            .execute(&mut tx)
+            .await
+        {
+            tracing::event!(tracing::Level::ERROR, "{:?}", error);
+            return Err(database_error(error));
+        }
+
+        let revoked = match sqlx::query(
+            "UPDATE sessions SET revoked_at = NOW()
+            WHERE account_id = $1 AND id <> $2 AND revoked_at IS NULL",
+        )
+        .bind(account_id.0)
+        .bind(current_session.0)
+        .execute(&mut tx)
+        .await
+        {
+            Ok(res) => res.rows_affected(),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                return Err(database_error(error));
+            }
+        };
+        // A password change is the way out after a leak, keys have to go as well
+        Store::revoke_all_api_keys(&mut tx, account_id).await?;
+
+        tx.commit().await.map_err(database_error)?;
+
+        Ok(revoked)
+    }
+
+    /// Removes everything personal from the account and ends its sessions.
+    /// Its questions, answers, comments and edits go to `DELETED_ACCOUNT`,
+    /// so they can't be told apart from the posts of other deleted accounts
+    pub async fn delete_account(self, account_id: &AccountId) -> Result<bool, Error> {
+        let mut tx = self.connection.begin().await.map_err(database_error)?;
+
+        match sqlx::query(
+            "UPDATE accounts
+            SET email = 'deleted-' || id || '@invalid', password = '', role = 'user',
+            display_name = NULL, bio = NULL, deleted_at = NOW()
+            WHERE id = $1 AND deleted_at IS NULL",
+        )
+        .bind(account_id.0)
+        .execute(&mut tx)
+        .await
+        {
+            Ok(res) if res.rows_affected() == 1 => (),
+            Ok(_) => return Err(Error::NotFound),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                return Err(database_error(error));
+            }
+        }
+
+        for query in [
+            "UPDATE password_resets SET used_at = NOW() WHERE account_id = $1 AND used_at IS NULL",
+            "UPDATE email_verifications SET used_at = NOW() WHERE account_id = $1 AND used_at IS NULL",
+        ] {
+            if let Err(error) = sqlx::query(query)
+                .bind(account_id.0)
+                .execute(&mut tx)
+                .await
+            {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                return Err(database_error(error));
+            }
+        }
+
+        for query in [
+            "UPDATE questions SET account_id = $2 WHERE account_id = $1",
+            "UPDATE answers SET account_id = $2 WHERE account_id = $1",
+            "UPDATE comments SET account_id = $2 WHERE account_id = $1",
+            "UPDATE question_revisions SET account_id = $2 WHERE account_id = $1",
+        ] {
+            if let Err(error) = sqlx::query(query)
+                .bind(account_id.0)
+                .bind(DELETED_ACCOUNT.0)
+                .execute(&mut tx)
+                .await
+            {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                return Err(database_error(error));
+            }
+        }
+
+        Store::revoke_all_sessions(&mut tx, account_id).await?;
+        Store::revoke_all_api_keys(&mut tx, account_id).await?;
+        tx.commit().await.map_err(database_error)?;
+
+        Ok(true)
+    }
+
+    /// Everything the account wrote, deleted questions included
+    pub async fn get_account_export(self, account_id: &AccountId) -> Result<AccountExport, Error> {
+        let account = self.get_profile(account_id).await?;
+
+        let questions = sqlx::query("SELECT * FROM questions WHERE account_id = $1 ORDER BY id")
+            .bind(account_id.0)
+            .map(|row: PgRow| question(&row))
+            .fetch_all(&self.connection);
+
+        let answers = sqlx::query(
+            "SELECT *,
+            COALESCE((SELECT SUM(value) FROM votes WHERE votes.answer_id = answers.id), 0) AS score
+            FROM answers WHERE account_id = $1 ORDER BY id",
+        )
+        .bind(account_id.0)
+        .map(|row: PgRow| answer(&row))
+        .fetch_all(&self.connection);
+
+        let comments = sqlx::query("SELECT * FROM comments WHERE account_id = $1 ORDER BY id")
+            .bind(account_id.0)
+            .map(|row: PgRow| comment(&row))
+            .fetch_all(&self.connection);
+
+        let revisions = sqlx::query(
+            "SELECT * FROM question_revisions WHERE account_id = $1
+            ORDER BY question_id, revision",
+        )
+        .bind(account_id.0)
+        .map(|row: PgRow| Revision {
+            question_id: QuestionId(row.get("question_id")),
+            revision: row.get("revision"),
+            title: row.get("title"),
+            content: row.get("content"),
+            tags: row.get("tags"),
+            account_id: AccountId(row.get("account_id")),
+            created_on: row.get("created_on"),
+        })
+        .fetch_all(&self.connection);
+
+        match tokio::try_join!(questions, answers, comments, revisions) {
+            Ok((questions, answers, comments, revisions)) => Ok(AccountExport {
+                exported_on: Utc::now().naive_utc(),
+                account,
+                questions,
+                answers,
+                comments,
+                revisions,
+            }),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(database_error(error))
+            }
+        }
+    }
+
+    pub async fn get_accounts(
+        self,
+        limit: Option<i32>,
+        offset: i32,
+    ) -> Result<Vec<AccountInfo>, Error> {
+        match sqlx::query(
+            "SELECT id, email, role, email_verified_at FROM accounts
+            ORDER BY id LIMIT $1 OFFSET $2",
+        )
+        .bind(limit)
+        .bind(offset)
+        .map(account_info)
+        .fetch_all(&self.connection)
+        .await
+        {
+            Ok(accounts) => Ok(accounts),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(database_error(error))
+            }
+        }
+    }
+
+    /// Changes the role of an account and ends its sessions, so the
+    /// new role applies right away and not only after the next refresh
+    pub async fn set_role(
+        self,
+        account_id: &AccountId,
+        new_role: Role,
+        log: NewModerationLogEntry,
+    ) -> Result<AccountInfo, Error> {
+        let mut tx = self.connection.begin().await.map_err(database_error)?;
+
+        let account = match sqlx::query(
+            "UPDATE accounts SET role = $2 WHERE id = $1
+            RETURNING id, email, role, email_verified_at",
+        )
+        .bind(account_id.0)
+        .bind(new_role.as_str())
+        .map(account_info)
+        .fetch_optional(&mut tx)
+        .await
+        {
+            Ok(Some(account)) => account,
+            Ok(None) => return Err(Error::NotFound),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                return Err(database_error(error));
+            }
+        };
+
+        Store::revoke_all_sessions(&mut tx, account_id).await?;
+        Store::add_moderation_log(&mut tx, Some(log)).await?;
+        tx.commit().await.map_err(database_error)?;
+
+        Ok(account)
+    }
+
+    /// Ends every session of the account, e.g. on a lost
+    /// device or after the account changed
+    pub async fn revoke_sessions(
+        self,
+        account_id: &AccountId,
+        log: NewModerationLogEntry,
+    ) -> Result<u64, Error> {
+        let mut tx = self.connection.begin().await.map_err(database_error)?;
+        let revoked = Store::revoke_all_sessions(&mut tx, account_id).await?;
+        Store::add_moderation_log(&mut tx, Some(log)).await?;
+        tx.commit().await.map_err(database_error)?;
+
+        Ok(revoked)
+    }
+
+    async fn revoke_all_sessions(
+        tx: &mut Transaction<'_, Postgres>,
+        account_id: &AccountId,
+    ) -> Result<u64, Error> {
+        match sqlx::query(
+            "UPDATE sessions SET revoked_at = NOW()
+            WHERE account_id = $1 AND revoked_at IS NULL",
+        )
+        .bind(account_id.0)
+        .execute(&mut *tx)
+        .await
+        {
+            Ok(res) => Ok(res.rows_affected()),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(database_error(error))
+            }
+        }
+    }
+
+    pub async fn add_api_key(
+        &self,
+        account_id: &AccountId,
+        name: String,
+        key: String,
+        requested_scopes: &[Scope],
+        expires_at: NaiveDateTime,
+    ) -> Result<ApiKeyInfo, Error> {
+        let values: Vec<&str> = requested_scopes
+            .iter()
+            .map(|scope| scope.as_str())
+            .collect();
+
+        match sqlx::query(
+            "INSERT INTO api_keys (account_id, name, key, scopes, expires_at)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING id, name, scopes, created_on, last_used_on, expires_at",
+        )
+        .bind(account_id.0)
+        .bind(name)
+        .bind(key)
+        .bind(values)
+        .bind(expires_at)
+        .map(|row: PgRow| ApiKeyInfo {
+            id: ApiKeyId(row.get("id")),
+            name: row.get("name"),
+            scopes: scopes(&row),
+            created_on: row.get("created_on"),
+            last_used_on: row.get("last_used_on"),
+            expires_at: row.get("expires_at"),
+        })
+        .fetch_one(&self.connection)
+        .await
+        {
+            Ok(key) => Ok(key),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(database_error(error))
+            }
+        }
+    }
+
+    /// A key which is neither revoked nor expired, of an account which wasn't deleted
+    pub async fn get_api_key(&self, id: &ApiKeyId) -> Result<StoredApiKey, Error> {
+        match sqlx::query(
+            "SELECT k.account_id, k.key, k.scopes, k.expires_at
+            FROM api_keys k JOIN accounts a ON a.id = k.account_id
+            WHERE k.id = $1 AND k.revoked_at IS NULL AND k.expires_at > NOW()
+            AND a.deleted_at IS NULL",
+        )
+        .bind(id.0)
+        .map(|row: PgRow| StoredApiKey {
+            account_id: AccountId(row.get("account_id")),
+            key: row.get("key"),
+            scopes: scopes(&row),
+            expires_at: row.get("expires_at"),
+        })
+        .fetch_optional(&self.connection)
+        .await
+        {
+            Ok(Some(key)) => Ok(key),
+            Ok(None) => Err(Error::NotFound),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(database_error(error))
+            }
+        }
+    }
+
+    /// Records the use of a key. Busy keys write at most once a minute
+    pub async fn touch_api_key(&self, id: &ApiKeyId) -> Result<(), Error> {
+        match sqlx::query(
+            "UPDATE api_keys SET last_used_on = NOW()
+            WHERE id = $1
+            AND (last_used_on IS NULL OR last_used_on < NOW() - INTERVAL '1 minute')",
+        )
+        .bind(id.0)
+        .execute(&self.connection)
+        .await
+        {
+            Ok(_) => Ok(()),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(database_error(error))
+            }
+        }
+    }
+
+    /// Keys of the account which are neither revoked nor expired
+    pub async fn get_api_keys(&self, account_id: &AccountId) -> Result<Vec<ApiKeyInfo>, Error> {
+        match sqlx::query(
+            "SELECT id, name, scopes, created_on, last_used_on, expires_at FROM api_keys
+            WHERE account_id = $1 AND revoked_at IS NULL AND expires_at > NOW()
+            ORDER BY created_on DESC",
+        )
+        .bind(account_id.0)
+        .map(|row: PgRow| ApiKeyInfo {
+            id: ApiKeyId(row.get("id")),
+            name: row.get("name"),
+            scopes: scopes(&row),
+            created_on: row.get("created_on"),
+            last_used_on: row.get("last_used_on"),
+            expires_at: row.get("expires_at"),
+        })
+        .fetch_all(&self.connection)
+        .await
+        {
+            Ok(keys) => Ok(keys),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(database_error(error))
+            }
+        }
+    }
+
+    pub async fn revoke_api_key(
+        &self,
+        id: &ApiKeyId,
+        account_id: &AccountId,
+    ) -> Result<bool, Error> {
+        match sqlx::query(
+            "UPDATE api_keys SET revoked_at = NOW()
+            WHERE id = $1 AND account_id = $2 AND revoked_at IS NULL",
+        )
+        .bind(id.0)
+        .bind(account_id.0)
+        .execute(&self.connection)
+        .await
+        {
+            Ok(res) if res.rows_affected() == 0 => Err(Error::NotFound),
+            Ok(_) => Ok(true),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(database_error(error))
+            }
+        }
+    }
+
+    async fn revoke_all_api_keys(
+        tx: &mut Transaction<'_, Postgres>,
+        account_id: &AccountId,
+    ) -> Result<u64, Error> {
+        match sqlx::query(
+            "UPDATE api_keys SET revoked_at = NOW()
+            WHERE account_id = $1 AND revoked_at IS NULL",
+        )
+        .bind(account_id.0)
+        .execute(&mut *tx)
+        .await
+        {
+            Ok(res) => Ok(res.rows_affected()),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(database_error(error))
+            }
+        }
+    }
+
+    pub async fn add_password_reset(
+        &self,
+        account_id: &AccountId,
+        token: String,
+        expires_at: NaiveDateTime,
+    ) -> Result<i32, Error> {
+        match sqlx::query(
+            "INSERT INTO password_resets (account_id, token, expires_at)
+            VALUES ($1, $2, $3)
+            RETURNING id",
+        )
+        .bind(account_id.0)
+        .bind(token)
+        .bind(expires_at)
+        .map(|row: PgRow| row.get("id"))
+        .fetch_one(&self.connection)
+        .await
+        {
+            Ok(id) => Ok(id),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(database_error(error))
+            }
+        }
+    }
+
+    /// A password reset which is neither used nor expired
+    pub async fn get_password_reset(&self, id: i32) -> Result<PasswordReset, Error> {
+        match sqlx::query(
+            "SELECT account_id, token FROM password_resets
+            WHERE id = $1 AND used_at IS NULL AND expires_at > NOW()",
+        )
+        .bind(id)
+        .map(|row: PgRow| PasswordReset {
+            account_id: AccountId(row.get("account_id")),
+            token: row.get("token"),
+        })
+        .fetch_optional(&self.connection)
+        .await
+        {
+            Ok(Some(reset)) => Ok(reset),
+            Ok(None) => Err(Error::NotFound),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(database_error(error))
+            }
+        }
+    }
+
+    /// Uses up the password reset, sets the new password hash and ends all
+    /// sessions and API keys of the account. Other open resets of the account are used
+    /// up as well. Fails with `NotFound` if the reset was used concurrently
+    pub async fn reset_password(
+        self,
+        id: i32,
+        account_id: &AccountId,
+        password: String,
+    ) -> Result<u64, Error> {
+        let mut tx = self.connection.begin().await.map_err(database_error)?;
+
+        match sqlx::query(
+            "UPDATE password_resets SET used_at = NOW()
+            WHERE account_id = $1 AND used_at IS NULL
+            RETURNING id",
+        )
+        .bind(account_id.0)
+        .map(|row: PgRow| row.get::<i32, _>("id"))
+        .fetch_all(&mut tx)
+        .await
+        {
+            Ok(used) if used.contains(&id) => (),
+            Ok(_) => return Err(Error::NotFound),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                return Err(database_error(error));
+            }
+        }
+
+        // The reset token came by mail, which proves the address as well
+        if let Err(error) = sqlx::query(
+            "UPDATE accounts
+            SET password = $2, email_verified_at = COALESCE(email_verified_at, NOW())
+            WHERE id = $1",
+        )
+        .bind(account_id.0)
+        .bind(password)
+        .execute(&mut tx)
+        .await
+        {
+            tracing::event!(tracing::Level::ERROR, "{:?}", error);
+            return Err(database_error(error));
+        }
+
+        let revoked = Store::revoke_all_sessions(&mut tx, account_id).await?;
+        // Whoever took over the account may have created keys as well
+        Store::revoke_all_api_keys(&mut tx, account_id).await?;
+        tx.commit().await.map_err(database_error)?;
+
+        Ok(revoked)
+    }
+
+    /// End of the longest running lockout of the keys, if any of them is locked
+    pub async fn login_locked_until(
+        &self,
+        keys: &[LoginKey],
+    ) -> Result<Option<NaiveDateTime>, Error> {
+        let kinds: Vec<&str> = keys.iter().map(|key| key.kind()).collect();
+        let values: Vec<String> = keys.iter().map(|key| key.value()).collect();
+
+        match sqlx::query(
+            "SELECT MAX(locked_until) AS locked_until FROM login_throttles
+            WHERE (kind, value) IN (SELECT * FROM UNNEST($1::text[], $2::text[]))
+            AND locked_until > NOW()",
+        )
+        .bind(kinds)
+        .bind(values)
+        .map(|row: PgRow| row.get("locked_until"))
+        .fetch_one(&self.connection)
+        .await
+        {
+            Ok(locked_until) => Ok(locked_until),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(database_error(error))
+            }
+        }
+    }
+
+    /// Counts a login attempt as failed before its password is verified, so
+    /// concurrent attempts each see their own count. Attempts on a locked key
+    /// aren't counted, failures older than a day are forgotten
+    pub async fn add_login_attempt(&self, key: &LoginKey) -> Result<LoginAttempt, Error> {
+        match sqlx::query(
+            "INSERT INTO login_throttles (kind, value, failures, last_failure_at)
+            VALUES ($1, $2, 1, NOW())
+            ON CONFLICT (kind, value) DO UPDATE SET
+            failures = CASE
+                WHEN login_throttles.locked_until > NOW() THEN login_throttles.failures
+                WHEN login_throttles.last_failure_at < NOW() - INTERVAL '1 day' THEN 1
+                ELSE login_throttles.failures + 1
+            END,
+            last_failure_at = CASE
+                WHEN login_throttles.locked_until > NOW() THEN login_throttles.last_failure_at
+                ELSE NOW()
+            END
+            RETURNING failures,
+            CASE WHEN locked_until > NOW() THEN locked_until END AS locked_until",
+        )
+        .bind(key.kind())
+        .bind(key.value())
+        .map(|row: PgRow| LoginAttempt {
+            failures: row.get("failures"),
+            locked_until: row.get("locked_until"),
+        })
+        .fetch_one(&self.connection)
+        .await
+        {
+            Ok(attempt) => Ok(attempt),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(database_error(error))
+            }
+        }
+    }
+
+    /// Locks the key, unless a concurrent attempt locked it first.
+    /// Returns whether this call took the lock
+    pub async fn lock_login(&self, key: &LoginKey, until: NaiveDateTime) -> Result<bool, Error> {
+        match sqlx::query(
+            "UPDATE login_throttles SET locked_until = $3
+            WHERE kind = $1 AND value = $2
+            AND (locked_until IS NULL OR locked_until <= NOW())",
+        )
+        .bind(key.kind())
+        .bind(key.value())
+        .bind(until)
+        .execute(&self.connection)
+        .await
+        {
+            Ok(res) => Ok(res.rows_affected() == 1),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(database_error(error))
+            }
+        }
+    }
+
+    /// Forgets the failures and lockout of the key
+    pub async fn clear_login_failures(&self, key: &LoginKey) -> Result<bool, Error> {
+        match sqlx::query("DELETE FROM login_throttles WHERE kind = $1 AND value = $2")
+            .bind(key.kind())
+            .bind(key.value())
+            .execute(&self.connection)
+            .await
+        {
+            Ok(_) => Ok(true),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(database_error(error))
+            }
+        }
+    }
+
+    /// Takes back an attempt which turned out to be a successful login,
+    /// together with the lockout it took while its password was verified
+    pub async fn release_login_attempt(
+        &self,
+        key: &LoginKey,
+        took_lock: bool,
+    ) -> Result<bool, Error> {
+        match sqlx::query(
+            "UPDATE login_throttles SET failures = GREATEST(failures - 1, 0),
+            locked_until = CASE WHEN $3 THEN NULL ELSE locked_until END
+            WHERE kind = $1 AND value = $2",
+        )
+        .bind(key.kind())
+        .bind(key.value())
+        .bind(took_lock)
+        .execute(&self.connection)
+        .await
+        {
+            Ok(_) => Ok(true),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(database_error(error))
+            }
+        }
+    }
+
+    /// Deletes throttles whose failures are forgotten and which aren't locked
+    pub async fn expire_login_throttles(&self) -> Result<u64, Error> {
+        match sqlx::query(
+            "DELETE FROM login_throttles
+            WHERE last_failure_at < NOW() - INTERVAL '1 day'
+            AND (locked_until IS NULL OR locked_until <= NOW())",
+        )
+        .execute(&self.connection)
+        .await
+        {
+            Ok(res) => Ok(res.rows_affected()),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(database_error(error))
+            }
+        }
+    }
+
+    /// Lifts the lockout of the email of the account
+    pub async fn unlock_login(
+        self,
+        account_id: &AccountId,
+        log: NewModerationLogEntry,
+    ) -> Result<bool, Error> {
+        let account = self.get_account_info(account_id).await?;
+        let key = LoginKey::email(&account.email);
+        let mut tx = self.connection.begin().await.map_err(database_error)?;
+
+        if let Err(error) =
+            sqlx::query("DELETE FROM login_throttles WHERE kind = $1 AND value = $2")
+                .bind(key.kind())
+                .bind(key.value())
+                .execute(&mut tx)
+                .await
+        {
+            tracing::event!(tracing::Level::ERROR, "{:?}", error);
+            return Err(database_error(error));
+        }
+
+        Store::add_moderation_log(&mut tx, Some(log)).await?;
+        tx.commit().await.map_err(database_error)?;
+
+        Ok(true)
+    }
+
+    /// Adds a verification token. Tokens sent earlier are used up,
+    /// only the latest mail verifies the account
+    pub async fn add_email_verification(
+        &self,
+        account_id: &AccountId,
+        token: String,
+        expires_at: NaiveDateTime,
+    ) -> Result<i32, Error> {
+        let mut tx = self.connection.begin().await.map_err(database_error)?;
+
+        if let Err(error) = sqlx::query(
+            "UPDATE email_verifications SET used_at = NOW()
+            WHERE account_id = $1 AND used_at IS NULL",
+        )
+        .bind(account_id.0)
+        .execute(&mut tx)
+        .await
+        {
+            tracing::event!(tracing::Level::ERROR, "{:?}", error);
+            return Err(database_error(error));
+        }
+
+        let id = match sqlx::query(
+            "INSERT INTO email_verifications (account_id, token, expires_at)
+            VALUES ($1, $2, $3)
+            RETURNING id",
+        )
+        .bind(account_id.0)
+        .bind(token)
+        .bind(expires_at)
+        .map(|row: PgRow| row.get("id"))
+        .fetch_one(&mut tx)
+        .await
+        {
+            Ok(id) => id,
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                return Err(database_error(error));
+            }
+        };
+
+        tx.commit().await.map_err(database_error)?;
+
+        Ok(id)
+    }
+
+    /// A verification token which is neither used nor expired
+    pub async fn get_email_verification(&self, id: i32) -> Result<EmailVerification, Error> {
+        match sqlx::query(
+            "SELECT account_id, token FROM email_verifications
+            WHERE id = $1 AND used_at IS NULL AND expires_at > NOW()",
+        )
+        .bind(id)
+        .map(|row: PgRow| EmailVerification {
+            account_id: AccountId(row.get("account_id")),
+            token: row.get("token"),
+        })
+        .fetch_optional(&self.connection)
+        .await
+        {
+            Ok(Some(verification)) => Ok(verification),
+            Ok(None) => Err(Error::NotFound),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(database_error(error))
+            }
+        }
+    }
+
+    /// Uses up the verification token and marks the email of the account
+    /// as verified. Fails with `NotFound` if the token was used concurrently
+    pub async fn verify_email(self, id: i32, account_id: &AccountId) -> Result<bool, Error> {
+        let mut tx = self.connection.begin().await.map_err(database_error)?;
+
+        match sqlx::query(
+            "UPDATE email_verifications SET used_at = NOW()
+            WHERE id = $1 AND used_at IS NULL",
+        )
+        .bind(id)
+        .execute(&mut tx)
+        .await
+        {
+            Ok(res) if res.rows_affected() == 1 => (),
+            Ok(_) => return Err(Error::NotFound),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                return Err(database_error(error));
+            }
+        }
+
+        if let Err(error) = sqlx::query(
+            "UPDATE accounts SET email_verified_at = NOW()
+            WHERE id = $1 AND email_verified_at IS NULL",
+        )
+        .bind(account_id.0)
+        .execute(&mut tx)
+        .await
+        {
+            tracing::event!(tracing::Level::ERROR, "{:?}", error);
+            return Err(database_error(error));
+        }
+
+        tx.commit().await.map_err(database_error)?;
+
+        Ok(true)
+    }
+
+    /// Logs a moderation action inside the transaction which makes it,
+    /// so there is no action without an entry and no entry without action
+    async fn add_moderation_log(
+        tx: &mut Transaction<'_, Postgres>,
+        entry: Option<NewModerationLogEntry>,
+    ) -> Result<(), Error> {
+        // Owners changing their own posts aren't logged
+        let entry = match entry {
+            Some(entry) => entry,
+            None => return Ok(()),
+        };
+        let (question_id, answer_id, account_id) = match &entry.target {
+            ModerationTarget::Question(id) => (Some(id.0), None, None),
+            ModerationTarget::Answer(id) => (None, Some(id.0), None),
+            ModerationTarget::Account(id) => (None, None, Some(id.0)),
+        };
+
+        match sqlx::query(
+            "INSERT INTO moderation_log (moderator_id, action, question_id, answer_id, account_id, reason)
+            VALUES ($1, $2, $3, $4, $5, $6)",
+        )
+        .bind(entry.moderator_id.0)
+        .bind(entry.action.as_str())
+        .bind(question_id)
+        .bind(answer_id)
+        .bind(account_id)
+        .bind(entry.reason)
+        .execute(&mut *tx)
+        .await
+        {
+            Ok(_) => {
+                tracing::event!(
+                    tracing::Level::INFO,
+                    moderator_id = entry.moderator_id.0,
+                    action = entry.action.as_str(),
+                    target = ?entry.target,
+                    "moderation action"
+                );
+                Ok(())
+            }
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(database_error(error))
+            }
+        }
+    }
+
+    pub async fn get_moderation_log(
+        self,
+        limit: Option<i32>,
+        offset: i32,
+    ) -> Result<Vec<ModerationLogEntry>, Error> {
+        match sqlx::query(
+            "SELECT * FROM moderation_log
+            ORDER BY created_on DESC, id DESC
+            LIMIT $1 OFFSET $2",
+        )
+        .bind(limit)
+        .bind(offset)
+        .map(|row: PgRow| ModerationLogEntry {
+            id: row.get("id"),
+            moderator_id: AccountId(row.get("moderator_id")),
+            action: row.get("action"),
+            question_id: row.get::<Option<i32>, _>("question_id").map(QuestionId),
+            answer_id: row.get::<Option<i32>, _>("answer_id").map(AnswerId),
+            account_id: row.get::<Option<i32>, _>("account_id").map(AccountId),
+            reason: row.get("reason"),
+            created_on: row.get("created_on"),
+        })
+        .fetch_all(&self.connection)
+        .await
+        {
+            Ok(entries) => Ok(entries),
             Err(error) => {
                 tracing::event!(tracing::Level::ERROR, "{:?}", error);
-                Err(Error::DatabaseQueryError(error))
+                Err(database_error(error))
             }
         }
     }
@@ -0,0 +1,352 @@
+use std::env;
+use std::fmt;
+use std::sync::Arc;
+
+use paseto::tokens::{validate_local_token, validate_public_token, PasetoPublicKey, TimeBackend};
+use rand::Rng;
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use handle_errors::Error;
+
+/// Id of the key from `PASETO_KEY`. Tokens without a footer were
+/// issued before keys had ids, they are checked with this one
+pub const DEFAULT_KEY_ID: &str = "default";
+
+/// Footer of every token, names the key which verifies it
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct Footer {
+    kid: String,
+}
+
+/// A key of the access tokens
+#[derive(Clone)]
+pub enum TokenKey {
+    /// Encrypts and decrypts v2.local tokens
+    Local(Vec<u8>),
+    /// Signs and verifies v2.public tokens
+    Signing(Arc<Ed25519KeyPair>),
+    /// Only verifies v2.public tokens, e.g. those of a retired signing key
+    Verifying(Vec<u8>),
+}
+
+impl TokenKey {
+    /// Keys are 32 bytes, base64url encoded. Kinds are `local`, `public`
+    /// for the seed of an Ed25519 key pair and `verify` for its public key
+    fn parse(kind: &str, key: &str) -> Result<Self, String> {
+        let key = base64::decode_config(key, base64::URL_SAFE_NO_PAD)
+            .map_err(|_| "key is not base64url encoded".to_string())?;
+        if key.len() != 32 {
+            return Err("key must be 32 bytes long".to_string());
+        }
+
+        match kind {
+            "local" => Ok(TokenKey::Local(key)),
+            "public" => Ed25519KeyPair::from_seed_unchecked(&key)
+                .map(|key_pair| TokenKey::Signing(Arc::new(key_pair)))
+                .map_err(|e| e.to_string()),
+            "verify" => Ok(TokenKey::Verifying(key)),
+            _ => Err(format!("{} is not one of local, public or verify", kind)),
+        }
+    }
+
+    /// The public half of a v2.public key
+    fn public_key(&self) -> Option<&[u8]> {
+        match self {
+            TokenKey::Local(_) => None,
+            TokenKey::Signing(key_pair) => Some(key_pair.public_key().as_ref()),
+            TokenKey::Verifying(public_key) => Some(public_key),
+        }
+    }
+}
+
+impl PartialEq for TokenKey {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (TokenKey::Local(a), TokenKey::Local(b)) => a == b,
+            (TokenKey::Signing(_), TokenKey::Signing(_))
+            | (TokenKey::Verifying(_), TokenKey::Verifying(_)) => {
+                self.public_key() == other.public_key()
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Leaves out the key material, the auth settings end up in logs
+impl fmt::Debug for TokenKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TokenKey::Local(_) => write!(f, "Local(..)"),
+            TokenKey::Signing(_) => write!(f, "Signing(..)"),
+            TokenKey::Verifying(_) => write!(f, "Verifying(..)"),
+        }
+    }
+}
+
+/// A key other services verify v2.public tokens with, see `GET /token-keys`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PublicTokenKey {
+    pub kid: String,
+    pub version: String,
+    /// The Ed25519 public key, base64url encoded
+    pub key: String,
+}
+
+/// The keys of the access tokens. New tokens are issued with the active
+/// key, all keys verify. A rotated key keeps verifying the tokens it
+/// issued until they expired, so nobody gets logged out
+#[derive(Debug, Clone, PartialEq)]
+pub struct TokenKeys {
+    active: String,
+    keys: Arc<Vec<(String, TokenKey)>>,
+}
+
+impl TokenKeys {
+    /// Reads `PASETO_KEYS`, a comma separated list of `<key id>:<kind>:<key>`,
+    /// see `TokenKey::parse()`. The first key is the active one. A `PASETO_KEY`
+    /// of 32 characters is added as the local key `default`
+    pub fn from_env() -> Result<Self, String> {
+        TokenKeys::parse(
+            env::var("PASETO_KEYS").ok().as_deref(),
+            env::var("PASETO_KEY").ok().as_deref(),
+        )
+    }
+
+    fn parse(keys: Option<&str>, default_key: Option<&str>) -> Result<Self, String> {
+        let mut entries: Vec<(String, TokenKey)> = Vec::new();
+
+        let list = keys.unwrap_or_default().split(',').map(str::trim);
+        for entry in list.filter(|entry| !entry.is_empty()) {
+            // Only the key id may end up in the message, never the key
+            let (kid, kind, key) = match entry.splitn(3, ':').collect::<Vec<&str>>()[..] {
+                [kid, kind, key] if !kid.is_empty() => (kid, kind, key),
+                [kid, ..] => return Err(format!("PASETO_KEYS entry {} has no kind or key", kid)),
+                [] => unreachable!("split always returns an item"),
+            };
+            let key = TokenKey::parse(kind, key)
+                .map_err(|e| format!("PASETO_KEYS key {}: {}", kid, e))?;
+            entries.push((kid.to_string(), key));
+        }
+
+        if let Some(key) = default_key {
+            if key.len() != 32 {
+                return Err("PASETO_KEY must be 32 bytes long".to_string());
+            }
+            entries.push((
+                DEFAULT_KEY_ID.to_string(),
+                TokenKey::Local(key.as_bytes().to_vec()),
+            ));
+        }
+
+        for (i, (kid, _)) in entries.iter().enumerate() {
+            if entries[..i].iter().any(|(other, _)| other == kid) {
+                return Err(format!("Key id {} is used twice", kid));
+            }
+        }
+
+        match entries.first() {
+            None => Err("Neither PASETO_KEYS nor PASETO_KEY is set".to_string()),
+            Some((kid, TokenKey::Verifying(_))) => Err(format!(
+                "Key {} can only verify, the first key has to sign",
+                kid
+            )),
+            Some((kid, _)) => Ok(TokenKeys {
+                active: kid.clone(),
+                keys: Arc::new(entries),
+            }),
+        }
+    }
+
+    /// A random local key, for tests. Its tokens don't outlive the process
+    pub fn generate() -> Self {
+        let key = rand::thread_rng().gen::<[u8; 32]>();
+        TokenKeys {
+            active: DEFAULT_KEY_ID.to_string(),
+            keys: Arc::new(vec![(
+                DEFAULT_KEY_ID.to_string(),
+                TokenKey::Local(key.to_vec()),
+            )]),
+        }
+    }
+
+    fn get(&self, kid: &str) -> Option<&TokenKey> {
+        self.keys
+            .iter()
+            .find(|(id, _)| id == kid)
+            .map(|(_, key)| key)
+    }
+
+    /// A token with the claims, encrypted or signed by the active key
+    pub fn issue<T: Serialize>(&self, claims: &T) -> String {
+        let message = serde_json::to_string(claims).expect("Claims are always serializable");
+        let footer = serde_json::to_string(&Footer {
+            kid: self.active.clone(),
+        })
+        .expect("Footer is always serializable");
+
+        match self.get(&self.active) {
+            Some(TokenKey::Local(key)) => paseto::v2::local_paseto(&message, Some(&footer), key),
+            Some(TokenKey::Signing(key_pair)) => {
+                paseto::v2::public_paseto(&message, Some(&footer), key_pair)
+            }
+            _ => unreachable!("The active key is checked by parse()"),
+        }
+        .expect("Failed to construct paseto token")
+    }
+
+    /// Checks the token with the key of its footer and returns its claims
+    pub fn verify<T: DeserializeOwned>(&self, token: &str) -> Result<T, Error> {
+        let parts: Vec<&str> = token.split('.').collect();
+        let footer = match parts.get(3) {
+            Some(footer) => Some(
+                base64::decode_config(footer, base64::URL_SAFE_NO_PAD)
+                    .ok()
+                    .and_then(|footer| String::from_utf8(footer).ok())
+                    .ok_or(Error::CannotDecryptToken)?,
+            ),
+            None => None,
+        };
+        let kid = match &footer {
+            Some(footer) => {
+                serde_json::from_str::<Footer>(footer)
+                    .map_err(|_| Error::CannotDecryptToken)?
+                    .kid
+            }
+            None => DEFAULT_KEY_ID.to_string(),
+        };
+
+        // The paseto crate panics on bodies shorter than the nonce or signature
+        let body_len = parts
+            .get(2)
+            .and_then(|body| base64::decode_config(body, base64::URL_SAFE_NO_PAD).ok())
+            .map_or(0, |body| body.len());
+
+        let claims = match (parts.get(..2), self.get(&kid)) {
+            (Some(["v2", "local"]), Some(TokenKey::Local(key))) if body_len > 24 => {
+                validate_local_token(token, footer.as_deref(), key, &TimeBackend::Chrono)
+            }
+            (Some(["v2", "public"]), Some(key)) if body_len > 64 => match key.public_key() {
+                Some(public_key) => validate_public_token(
+                    token,
+                    footer.as_deref(),
+                    &PasetoPublicKey::ED25519PublicKey(public_key),
+                    &TimeBackend::Chrono,
+                ),
+                None => return Err(Error::CannotDecryptToken),
+            },
+            _ => return Err(Error::CannotDecryptToken),
+        }
+        .map_err(|_| Error::CannotDecryptToken)?;
+
+        serde_json::from_value(claims).map_err(|_| Error::CannotDecryptToken)
+    }
+
+    /// The keys which verify v2.public tokens
+    pub fn public_keys(&self) -> Vec<PublicTokenKey> {
+        self.keys
+            .iter()
+            .filter_map(|(kid, key)| {
+                key.public_key().map(|public_key| PublicTokenKey {
+                    kid: kid.clone(),
+                    version: "v2.public".to_string(),
+                    key: base64::encode_config(public_key, base64::URL_SAFE_NO_PAD),
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod token_keys_tests {
+    use super::*;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Claims {
+        exp: chrono::DateTime<chrono::Utc>,
+        sub: String,
+    }
+
+    fn claims() -> Claims {
+        Claims {
+            exp: chrono::Utc::now() + chrono::Duration::minutes(15),
+            sub: "3".to_string(),
+        }
+    }
+
+    fn key(byte: u8) -> String {
+        base64::encode_config([byte; 32], base64::URL_SAFE_NO_PAD)
+    }
+
+    #[test]
+    fn rotated_keys_keep_verifying() {
+        let old = TokenKeys::parse(Some(&format!("2022-06:local:{}", key(1))), None).unwrap();
+        let token = old.issue(&claims());
+
+        let rotated = TokenKeys::parse(
+            Some(&format!(
+                "2022-07:local:{},2022-06:local:{}",
+                key(2),
+                key(1)
+            )),
+            None,
+        )
+        .unwrap();
+        assert_eq!(rotated.verify::<Claims>(&token).unwrap().sub, "3");
+        assert!(rotated.issue(&claims()).ends_with(&base64::encode_config(
+            r#"{"kid":"2022-07"}"#,
+            base64::URL_SAFE_NO_PAD
+        )));
+
+        let dropped = TokenKeys::parse(Some(&format!("2022-07:local:{}", key(2))), None).unwrap();
+        assert!(dropped.verify::<Claims>(&token).is_err());
+    }
+
+    #[test]
+    fn public_tokens_verify_with_the_public_key() {
+        let signer = TokenKeys::parse(Some(&format!("2022-07:public:{}", key(1))), None).unwrap();
+        let token = signer.issue(&claims());
+        assert!(token.starts_with("v2.public."));
+
+        let public_key = &signer.public_keys()[0];
+        let verifier = TokenKeys::parse(
+            Some(&format!(
+                "other:local:{},2022-07:verify:{}",
+                key(2),
+                public_key.key
+            )),
+            None,
+        )
+        .unwrap();
+        assert_eq!(verifier.verify::<Claims>(&token).unwrap().sub, "3");
+
+        // A token of one key type doesn't verify with a key of the other
+        let tampered = token.replacen("v2.public.", "v2.local.", 1);
+        assert!(verifier.verify::<Claims>(&tampered).is_err());
+        assert!(verifier.verify::<Claims>("v2.public.AAAA").is_err());
+    }
+
+    #[test]
+    fn tokens_without_footer_use_the_default_key() {
+        let keys = TokenKeys::parse(None, Some("RANDOM WORDS WINTER MACINTOSH PC")).unwrap();
+        let message = serde_json::to_string(&claims()).unwrap();
+        let token =
+            paseto::v2::local_paseto(&message, None, b"RANDOM WORDS WINTER MACINTOSH PC").unwrap();
+
+        assert_eq!(keys.verify::<Claims>(&token).unwrap().sub, "3");
+    }
+
+    #[test]
+    fn invalid_key_lists() {
+        assert!(TokenKeys::parse(None, None).is_err());
+        assert!(TokenKeys::parse(None, Some("too short")).is_err());
+        assert!(TokenKeys::parse(Some("a:local"), None).is_err());
+        assert!(TokenKeys::parse(Some(&format!("a:other:{}", key(1))), None).is_err());
+        assert!(TokenKeys::parse(Some(&format!("a:verify:{}", key(1))), None).is_err());
+        assert!(TokenKeys::parse(
+            Some(&format!("a:local:{},a:local:{}", key(1), key(2))),
+            None
+        )
+        .is_err());
+    }
+}
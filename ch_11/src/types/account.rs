@@ -1,10 +1,201 @@
+use std::net::IpAddr;
+use std::str::FromStr;
+
 use chrono::prelude::*;
 use serde::{Deserialize, Serialize};
 
+use handle_errors::Error;
+
+use crate::types::api_key::{ApiKeyGrant, Scope};
+use crate::types::{answer::Answer, comment::Comment, question::Question, revision::Revision};
+use crate::validation::{Validate, Validator};
+
+/// Claims of an access token
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Session {
     pub exp: DateTime<Utc>,
     pub account_id: AccountId,
+    /// The server side session the token was issued for,
+    /// revoking it invalidates the token
+    pub token_id: TokenId,
+    #[serde(default)]
+    pub role: Role,
+    /// Set if the request came with an API key instead of an access
+    /// token. API keys have no session, their `token_id` is 0
+    #[serde(skip)]
+    pub api_key: Option<ApiKeyGrant>,
+}
+
+impl Session {
+    /// Logins may do anything, API keys only what their scopes allow
+    pub fn require_scope(&self, scope: Scope) -> Result<(), Error> {
+        match &self.api_key {
+            Some(grant) if !grant.scopes.contains(&scope) => {
+                Err(Error::InsufficientScope(scope.as_str().to_string()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// For the routes which manage the account, they need a login
+    pub fn require_login(&self) -> Result<(), Error> {
+        match self.api_key {
+            Some(_) => Err(Error::ApiKeyNotAllowed),
+            None => Ok(()),
+        }
+    }
+}
+
+/// What an account may do besides changing its own posts. Roles are
+/// ordered, every role has the permissions of the ones below it
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    #[default]
+    User,
+    /// Edits, closes and deletes any question or answer
+    Moderator,
+    /// Manages accounts
+    Admin,
+}
+
+impl Role {
+    /// The value which gets stored in the `accounts` table
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::User => "user",
+            Role::Moderator => "moderator",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+impl FromStr for Role {
+    type Err = Error;
+
+    fn from_str(role: &str) -> Result<Self, Self::Err> {
+        match role {
+            "user" => Ok(Role::User),
+            "moderator" => Ok(Role::Moderator),
+            "admin" => Ok(Role::Admin),
+            _ => Err(Error::InvalidParameter(format!("{} is not a role", role))),
+        }
+    }
+}
+
+/// Body of the request which changes the role of an account
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NewRole {
+    pub role: Role,
+}
+
+impl Validate for NewRole {
+    fn validate(&self) -> Result<(), Error> {
+        // The role is checked while deserializing
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TokenId(pub i32);
+
+/// Tokens handed out at login and on every refresh
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TokenPair {
+    pub access_token: String,
+    pub token_type: String,
+    /// Seconds until the access token expires
+    pub expires_in: i64,
+    /// Single use, every refresh returns a new one
+    pub refresh_token: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+impl Validate for RefreshRequest {
+    fn validate(&self) -> Result<(), Error> {
+        Validator::new()
+            .length("refresh_token", &self.refresh_token, 1, 255)
+            .finish()
+    }
+}
+
+/// Body of `POST /introspect`, a form as in RFC 7662
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct IntrospectionRequest {
+    pub token: String,
+}
+
+/// Answer of `POST /introspect`. Invalid, expired and revoked
+/// tokens get nothing but `"active": false`
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct TokenIntrospection {
+    pub active: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub account_id: Option<AccountId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub role: Option<Role>,
+    /// Seconds since the epoch
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exp: Option<i64>,
+    /// `access_token` or `api_key`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token_type: Option<String>,
+    /// Space separated scopes of an API key
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+}
+
+impl From<&Session> for TokenIntrospection {
+    fn from(session: &Session) -> Self {
+        let scopes = session.api_key.as_ref().map(|grant| {
+            grant
+                .scopes
+                .iter()
+                .map(|scope| scope.as_str())
+                .collect::<Vec<&str>>()
+                .join(" ")
+        });
+
+        TokenIntrospection {
+            active: true,
+            account_id: Some(session.account_id.clone()),
+            role: Some(session.role),
+            exp: Some(session.exp.timestamp()),
+            token_type: Some(
+                match scopes {
+                    Some(_) => "api_key",
+                    None => "access_token",
+                }
+                .to_string(),
+            ),
+            scope: scopes,
+        }
+    }
+}
+
+/// Server side state of a session, the refresh tokens are hashed
+#[derive(Debug, Clone)]
+pub struct RefreshSession {
+    pub account_id: AccountId,
+    pub role: Role,
+    pub refresh_token: String,
+    /// The token which was replaced by the last refresh
+    pub previous_refresh_token: Option<String>,
+}
+
+/// A login of an account, as listed by `GET /sessions`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SessionInfo {
+    pub id: TokenId,
+    pub created_on: NaiveDateTime,
+    pub last_used_on: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+    /// If this is the session of the requesting token
+    pub current: bool,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -12,7 +203,340 @@ pub struct Account {
     pub id: Option<AccountId>,
     pub email: String,
     pub password: String,
+    /// Only ever changed by admins, never taken from a request body
+    #[serde(default, skip_deserializing)]
+    pub role: Role,
+}
+
+/// An account as listed to admins
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AccountInfo {
+    pub id: AccountId,
+    pub email: String,
+    pub role: Role,
+    pub email_verified: bool,
+}
+
+/// The own account, as shown by `GET /accounts/me`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Profile {
+    pub id: AccountId,
+    pub email: String,
+    pub role: Role,
+    pub email_verified: bool,
+    pub display_name: Option<String>,
+    pub bio: Option<String>,
+}
+
+/// Replaces the profile, missing fields are cleared
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UpdateProfile {
+    pub display_name: Option<String>,
+    pub bio: Option<String>,
+}
+
+impl Validate for UpdateProfile {
+    fn validate(&self) -> Result<(), Error> {
+        let mut validator = Validator::new();
+        if let Some(display_name) = &self.display_name {
+            validator.length("display_name", display_name, 1, 100);
+        }
+        if let Some(bio) = &self.bio {
+            validator.length("bio", bio, 0, 2000);
+        }
+        validator.finish()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PasswordChange {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+impl Validate for PasswordChange {
+    fn validate(&self) -> Result<(), Error> {
+        Validator::new()
+            .length("current_password", &self.current_password, 1, 128)
+            .length("new_password", &self.new_password, MIN_PASSWORD_LENGTH, 128)
+            .finish()
+    }
+}
+
+/// Deleting the account needs the password, a stolen access token isn't enough
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AccountDeletion {
+    pub password: String,
+}
+
+impl Validate for AccountDeletion {
+    fn validate(&self) -> Result<(), Error> {
+        Validator::new()
+            .length("password", &self.password, 1, 128)
+            .finish()
+    }
+}
+
+/// Everything an account wrote, for `GET /accounts/me/export`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AccountExport {
+    pub exported_on: NaiveDateTime,
+    pub account: Profile,
+    /// Including the deleted ones
+    pub questions: Vec<Question>,
+    pub answers: Vec<Answer>,
+    pub comments: Vec<Comment>,
+    /// Edits of questions, also of questions of other accounts
+    pub revisions: Vec<Revision>,
+}
+
+/// What failed logins are counted by
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LoginKey {
+    Email(String),
+    Ip(IpAddr),
+}
+
+impl LoginKey {
+    /// Emails are case-insensitive, so are their failures
+    pub fn email(email: &str) -> Self {
+        LoginKey::Email(email.to_lowercase())
+    }
+
+    /// The values which get stored in the `login_throttles` table
+    pub fn kind(&self) -> &'static str {
+        match self {
+            LoginKey::Email(_) => "email",
+            LoginKey::Ip(_) => "ip",
+        }
+    }
+
+    pub fn value(&self) -> String {
+        match self {
+            LoginKey::Email(email) => email.clone(),
+            LoginKey::Ip(ip) => ip.to_string(),
+        }
+    }
+}
+
+/// A throttle right after a login attempt was counted against it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoginAttempt {
+    /// Failures so far, counting the current attempt
+    pub failures: i32,
+    /// Set if the key is locked out, the attempt isn't counted then
+    pub locked_until: Option<NaiveDateTime>,
+}
+
+/// Minimum password length for new accounts
+pub const MIN_PASSWORD_LENGTH: usize = 8;
+
+impl Validate for Account {
+    fn validate(&self) -> Result<(), Error> {
+        Validator::new()
+            .email("email", &self.email)
+            .length("password", &self.password, 1, 128)
+            .finish()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PasswordResetRequest {
+    pub email: String,
+}
+
+impl Validate for PasswordResetRequest {
+    fn validate(&self) -> Result<(), Error> {
+        Validator::new().email("email", &self.email).finish()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PasswordResetConfirm {
+    /// The token of the reset mail
+    pub token: String,
+    pub password: String,
+}
+
+impl Validate for PasswordResetConfirm {
+    fn validate(&self) -> Result<(), Error> {
+        Validator::new()
+            .length("token", &self.token, 1, 255)
+            .length("password", &self.password, MIN_PASSWORD_LENGTH, 128)
+            .finish()
+    }
+}
+
+/// Server side state of a password reset, the token is hashed
+#[derive(Debug, Clone)]
+pub struct PasswordReset {
+    pub account_id: AccountId,
+    pub token: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VerifyEmail {
+    /// The token of the verification mail
+    pub token: String,
+}
+
+impl Validate for VerifyEmail {
+    fn validate(&self) -> Result<(), Error> {
+        Validator::new()
+            .length("token", &self.token, 1, 255)
+            .finish()
+    }
+}
+
+/// Server side state of an email verification, the token is hashed
+#[derive(Debug, Clone)]
+pub struct EmailVerification {
+    pub account_id: AccountId,
+    pub token: String,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Hash)]
 pub struct AccountId(pub i32);
+
+/// Author of the posts of deleted accounts. It can't log in
+pub const DELETED_ACCOUNT: AccountId = AccountId(0);
+
+/// How an account relates to a question, answer or comment it wants to change
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ownership {
+    /// There is no such post
+    NotFound,
+    /// The post belongs to another account
+    Forbidden,
+    Owner,
+}
+
+impl Ownership {
+    pub fn new(owner: Option<AccountId>, account_id: &AccountId) -> Self {
+        match owner {
+            None => Ownership::NotFound,
+            Some(owner) if &owner == account_id => Ownership::Owner,
+            Some(_) => Ownership::Forbidden,
+        }
+    }
+
+    /// Missing posts are answered with a 404, posts of other accounts with a 403
+    pub fn require_owner(self) -> Result<(), Error> {
+        match self {
+            Ownership::NotFound => Err(Error::NotFound),
+            Ownership::Forbidden => Err(Error::Forbidden),
+            Ownership::Owner => Ok(()),
+        }
+    }
+
+    /// Like `require_owner`, but moderators may change posts of other accounts
+    pub fn require_owner_or_moderator(self, role: Role) -> Result<Access, Error> {
+        match self {
+            Ownership::Owner => Ok(Access::Owner),
+            Ownership::Forbidden if role >= Role::Moderator => Ok(Access::Moderator),
+            _ => self.require_owner().map(|_| Access::Owner),
+        }
+    }
+}
+
+/// Why an account may change a post. Changes of moderators get logged
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Owner,
+    Moderator,
+}
+
+#[cfg(test)]
+mod account_tests {
+    use super::{Access, AccountId, Ownership, Role, Session, TokenId, UpdateProfile};
+    use crate::types::api_key::{ApiKeyGrant, ApiKeyId, Scope};
+    use crate::validation::Validate;
+
+    #[test]
+    fn ownership() {
+        let account_id = AccountId(1);
+
+        assert_eq!(Ownership::new(None, &account_id), Ownership::NotFound);
+        assert_eq!(
+            Ownership::new(Some(AccountId(2)), &account_id),
+            Ownership::Forbidden
+        );
+        assert_eq!(
+            Ownership::new(Some(AccountId(1)), &account_id),
+            Ownership::Owner
+        );
+        assert!(Ownership::Forbidden.require_owner().is_err());
+        assert!(Ownership::Owner.require_owner().is_ok());
+    }
+
+    #[test]
+    fn moderators_change_any_post() {
+        assert_eq!(
+            Ownership::Forbidden
+                .require_owner_or_moderator(Role::Moderator)
+                .unwrap(),
+            Access::Moderator
+        );
+        assert_eq!(
+            Ownership::Forbidden
+                .require_owner_or_moderator(Role::Admin)
+                .unwrap(),
+            Access::Moderator
+        );
+        assert_eq!(
+            Ownership::Owner
+                .require_owner_or_moderator(Role::Moderator)
+                .unwrap(),
+            Access::Owner
+        );
+        assert!(Ownership::Forbidden
+            .require_owner_or_moderator(Role::User)
+            .is_err());
+        assert!(Ownership::NotFound
+            .require_owner_or_moderator(Role::Admin)
+            .is_err());
+    }
+
+    #[test]
+    fn roles() {
+        assert!(Role::Admin > Role::Moderator && Role::Moderator > Role::User);
+        assert_eq!("moderator".parse::<Role>().unwrap(), Role::Moderator);
+        assert!("root".parse::<Role>().is_err());
+    }
+
+    #[test]
+    fn profile_fields_are_optional() {
+        let cleared = UpdateProfile {
+            display_name: None,
+            bio: None,
+        };
+        assert!(cleared.validate().is_ok());
+
+        let blank = UpdateProfile {
+            display_name: Some("  ".to_string()),
+            bio: Some(String::new()),
+        };
+        assert!(blank.validate().is_err());
+    }
+
+    #[test]
+    fn api_keys_are_limited_to_their_scopes() {
+        let mut session = Session {
+            exp: chrono::Utc::now(),
+            account_id: AccountId(1),
+            token_id: TokenId(1),
+            role: Role::User,
+            api_key: None,
+        };
+        assert!(session.require_scope(Scope::VotesWrite).is_ok());
+        assert!(session.require_login().is_ok());
+
+        session.api_key = Some(ApiKeyGrant {
+            id: ApiKeyId(1),
+            scopes: vec![Scope::AnswersWrite],
+        });
+        assert!(session.require_scope(Scope::AnswersWrite).is_ok());
+        assert!(session.require_scope(Scope::VotesWrite).is_err());
+        assert!(session.require_login().is_err());
+    }
+}
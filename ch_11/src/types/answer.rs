@@ -1,7 +1,67 @@
+use std::collections::HashMap;
+
+use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
 
+use handle_errors::Error;
+
+use crate::types::{account::AccountId, question::QuestionId};
+use crate::validation::{Validate, Validator};
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Answer {
+    pub id: AnswerId,
+    pub content: String,
+    pub question_id: QuestionId,
+    pub account_id: AccountId,
+    pub created_on: NaiveDateTime,
+    pub score: i64,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AnswerId(pub i32);
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NewAnswer {
+    pub content: String,
+    pub question_id: QuestionId,
+}
+
+impl NewAnswer {
+    /// Answers are posted as a form, so the fields are
+    /// read and validated by hand
+    pub fn from_form(params: &HashMap<String, String>) -> Result<NewAnswer, Error> {
+        let content = params.get("content").cloned().unwrap_or_default();
+        let question_id = params
+            .get("questionId")
+            .and_then(|id| id.parse::<i32>().ok())
+            .filter(|id| *id > 0);
+
+        Validator::new()
+            .length("content", &content, 1, 30_000)
+            .check(
+                question_id.is_some(),
+                "questionId",
+                "must be a positive number",
+            )
+            .finish()?;
+
+        Ok(NewAnswer {
+            content,
+            question_id: QuestionId(question_id.unwrap_or_default()),
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UpdateAnswer {
     pub content: String,
-    pub question_id: i32,
+}
+
+impl Validate for UpdateAnswer {
+    fn validate(&self) -> Result<(), Error> {
+        Validator::new()
+            .length("content", &self.content, 1, 30_000)
+            .finish()
+    }
 }
@@ -0,0 +1,166 @@
+use std::str::FromStr;
+
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use handle_errors::Error;
+
+use crate::types::account::AccountId;
+use crate::validation::{Validate, Validator};
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ApiKeyId(pub i32);
+
+/// What an API key may do. Keys only work on routes which need one of
+/// these scopes, everything else about the account needs a login
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Scope {
+    /// Add, edit, close and delete questions, accept answers
+    #[serde(rename = "questions:write")]
+    QuestionsWrite,
+    #[serde(rename = "answers:write")]
+    AnswersWrite,
+    #[serde(rename = "comments:write")]
+    CommentsWrite,
+    #[serde(rename = "votes:write")]
+    VotesWrite,
+    /// Ask `/introspect` about the tokens of others, for services
+    /// which take tokens of this API
+    #[serde(rename = "introspect")]
+    Introspect,
+}
+
+impl Scope {
+    /// The value which gets stored in the `api_keys` table
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::QuestionsWrite => "questions:write",
+            Scope::AnswersWrite => "answers:write",
+            Scope::CommentsWrite => "comments:write",
+            Scope::VotesWrite => "votes:write",
+            Scope::Introspect => "introspect",
+        }
+    }
+}
+
+impl FromStr for Scope {
+    type Err = Error;
+
+    fn from_str(scope: &str) -> Result<Self, Self::Err> {
+        match scope {
+            "questions:write" => Ok(Scope::QuestionsWrite),
+            "answers:write" => Ok(Scope::AnswersWrite),
+            "comments:write" => Ok(Scope::CommentsWrite),
+            "votes:write" => Ok(Scope::VotesWrite),
+            "introspect" => Ok(Scope::Introspect),
+            _ => Err(Error::InvalidParameter(format!("{} is not a scope", scope))),
+        }
+    }
+}
+
+/// Longest lifetime of a key, and the one keys get without `expires_in_days`
+pub const MAX_API_KEY_DAYS: u32 = 365;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NewApiKey {
+    /// Tells the keys of an account apart, e.g. the name of the bot
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    pub expires_in_days: Option<u32>,
+}
+
+impl Validate for NewApiKey {
+    fn validate(&self) -> Result<(), Error> {
+        let mut validator = Validator::new();
+        validator.length("name", &self.name, 1, 100).check(
+            !self.scopes.is_empty(),
+            "scopes",
+            "must not be empty",
+        );
+        if let Some(days) = self.expires_in_days {
+            validator.check(
+                (1..=MAX_API_KEY_DAYS).contains(&days),
+                "expires_in_days",
+                &format!("must be between 1 and {}", MAX_API_KEY_DAYS),
+            );
+        }
+        validator.finish()
+    }
+}
+
+/// A key of the account, as listed by `GET /api-keys`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ApiKeyInfo {
+    pub id: ApiKeyId,
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    pub created_on: NaiveDateTime,
+    /// Updated at most once a minute
+    pub last_used_on: Option<NaiveDateTime>,
+    pub expires_at: NaiveDateTime,
+}
+
+/// Answer to `POST /api-keys`, the only time the key is shown
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CreatedApiKey {
+    pub key: String,
+    #[serde(flatten)]
+    pub info: ApiKeyInfo,
+}
+
+/// Server side state of a key, the secret is hashed
+#[derive(Debug, Clone)]
+pub struct StoredApiKey {
+    pub account_id: AccountId,
+    pub key: String,
+    pub scopes: Vec<Scope>,
+    pub expires_at: NaiveDateTime,
+}
+
+/// The key a request was authenticated with
+#[derive(Debug, Clone, PartialEq)]
+pub struct ApiKeyGrant {
+    pub id: ApiKeyId,
+    pub scopes: Vec<Scope>,
+}
+
+#[cfg(test)]
+mod api_key_tests {
+    use super::{NewApiKey, Scope};
+    use crate::validation::Validate;
+
+    #[test]
+    fn scopes_round_trip() {
+        for scope in [
+            Scope::QuestionsWrite,
+            Scope::AnswersWrite,
+            Scope::CommentsWrite,
+            Scope::VotesWrite,
+            Scope::Introspect,
+        ] {
+            assert_eq!(scope.as_str().parse::<Scope>().unwrap(), scope);
+            assert_eq!(
+                serde_json::to_value(scope).unwrap(),
+                serde_json::json!(scope.as_str())
+            );
+        }
+        assert!("questions:read".parse::<Scope>().is_err());
+    }
+
+    #[test]
+    fn new_key_needs_name_and_scopes() {
+        let key = NewApiKey {
+            name: " ".to_string(),
+            scopes: vec![],
+            expires_in_days: Some(0),
+        };
+
+        match key.validate() {
+            Err(handle_errors::Error::ValidationError(errors)) => {
+                let fields: Vec<&str> = errors.iter().map(|e| e.field.as_str()).collect();
+                assert_eq!(fields, vec!["name", "scopes", "expires_in_days"]);
+            }
+            other => panic!("expected validation error, got {:?}", other),
+        }
+    }
+}
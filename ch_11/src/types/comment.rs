@@ -0,0 +1,42 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+use handle_errors::Error;
+
+use crate::types::{account::AccountId, answer::AnswerId, question::QuestionId};
+use crate::validation::{Validate, Validator};
+
+/// Short remark attached to either a question or an answer
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Comment {
+    pub id: CommentId,
+    pub content: String,
+    pub question_id: Option<QuestionId>,
+    pub answer_id: Option<AnswerId>,
+    pub account_id: AccountId,
+    pub created_on: NaiveDateTime,
+    pub updated_on: Option<NaiveDateTime>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CommentId(pub i32);
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NewComment {
+    pub content: String,
+}
+
+impl Validate for NewComment {
+    fn validate(&self) -> Result<(), Error> {
+        Validator::new()
+            .length("content", &self.content, 1, 600)
+            .finish()
+    }
+}
+
+/// The post a comment belongs to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommentTarget {
+    Question(QuestionId),
+    Answer(AnswerId),
+}
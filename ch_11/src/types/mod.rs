@@ -1,4 +1,10 @@
 pub mod account;
 pub mod answer;
+pub mod api_key;
+pub mod comment;
+pub mod moderation;
 pub mod pagination;
 pub mod question;
+pub mod revision;
+pub mod tag;
+pub mod vote;
@@ -0,0 +1,87 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+use handle_errors::Error;
+
+use crate::types::{account::AccountId, answer::AnswerId, question::QuestionId};
+use crate::validation::{Validate, Validator};
+
+/// Changes moderators and admins make to content or accounts of others
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ModerationAction {
+    EditQuestion,
+    RollbackQuestion,
+    DeleteQuestion,
+    RestoreQuestion,
+    CloseQuestion,
+    ReopenQuestion,
+    EditAnswer,
+    DeleteAnswer,
+    ChangeRole,
+    RevokeSessions,
+    UnlockLogin,
+}
+
+impl ModerationAction {
+    /// The value which gets stored in the `moderation_log` table
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ModerationAction::EditQuestion => "edit_question",
+            ModerationAction::RollbackQuestion => "rollback_question",
+            ModerationAction::DeleteQuestion => "delete_question",
+            ModerationAction::RestoreQuestion => "restore_question",
+            ModerationAction::CloseQuestion => "close_question",
+            ModerationAction::ReopenQuestion => "reopen_question",
+            ModerationAction::EditAnswer => "edit_answer",
+            ModerationAction::DeleteAnswer => "delete_answer",
+            ModerationAction::ChangeRole => "change_role",
+            ModerationAction::RevokeSessions => "revoke_sessions",
+            ModerationAction::UnlockLogin => "unlock_login",
+        }
+    }
+}
+
+/// What a moderation action was applied to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ModerationTarget {
+    Question(QuestionId),
+    Answer(AnswerId),
+    Account(AccountId),
+}
+
+/// An action about to be logged, written in the transaction which makes it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NewModerationLogEntry {
+    pub moderator_id: AccountId,
+    pub action: ModerationAction,
+    pub target: ModerationTarget,
+    pub reason: Option<String>,
+}
+
+/// One entry of the moderation log
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ModerationLogEntry {
+    pub id: i32,
+    pub moderator_id: AccountId,
+    pub action: String,
+    pub question_id: Option<QuestionId>,
+    pub answer_id: Option<AnswerId>,
+    pub account_id: Option<AccountId>,
+    pub reason: Option<String>,
+    pub created_on: NaiveDateTime,
+}
+
+/// Body of the request which closes a question for new answers
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CloseQuestion {
+    pub reason: String,
+}
+
+impl Validate for CloseQuestion {
+    fn validate(&self) -> Result<(), Error> {
+        Validator::new()
+            .length("reason", &self.reason, 1, 600)
+            .finish()
+    }
+}
@@ -1,7 +1,15 @@
 use std::collections::HashMap;
 
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
 use handle_errors::Error;
 
+/// Number of items returned by paginated listings if no limit is given
+pub const DEFAULT_PAGE_SIZE: i32 = 20;
+/// Upper bound for the limit of paginated listings
+pub const MAX_PAGE_SIZE: i32 = 100;
+
 /// Pagination struct which is getting extract
 /// from query params
 #[derive(Default, Debug, PartialEq)]
@@ -11,6 +19,96 @@ pub struct Pagination {
     /// The index of the first item which has to be returned
     //TODO: Why i32?
     pub offset: i32,
+    /// Position after which the next page starts
+    pub cursor: Option<Cursor>,
+    /// If the total number of items should be counted as well
+    pub include_total: bool,
+}
+
+impl Pagination {
+    /// The limit clamped to the allowed page sizes, for listings
+    /// which never return everything at once
+    pub fn page_size(&self) -> i32 {
+        self.limit
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE)
+    }
+}
+
+/// Opaque position in a listing. Clients get it as `next_cursor`
+/// and hand it back unchanged to receive the following page
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+pub struct Cursor {
+    /// Order of the listing the cursor was created for
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sort: Option<String>,
+    /// Id of the last item of the previous page
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<i32>,
+    /// Creation date of the last item, if the listing is sorted by date
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_on: Option<NaiveDateTime>,
+    /// Sort key of the last item, if the listing is sorted by a number
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<i64>,
+    /// Offset of the next page, for listings which can't be keyset paginated
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offset: Option<i32>,
+}
+
+impl Cursor {
+    pub fn encode(&self) -> String {
+        let json = serde_json::to_vec(self).expect("Cursor can always be serialized");
+        base64::encode_config(json, base64::URL_SAFE_NO_PAD)
+    }
+
+    /// Positions only make sense in the order they were taken from,
+    /// so a cursor can't be reused with another sort
+    pub fn check_sort(&self, sort: &str) -> Result<(), Error> {
+        if self.sort.as_deref() == Some(sort) {
+            Ok(())
+        } else {
+            Err(Error::InvalidParameter(format!(
+                "cursor doesn't belong to the {} sort",
+                sort
+            )))
+        }
+    }
+
+    pub fn decode(cursor: &str) -> Result<Cursor, Error> {
+        base64::decode_config(cursor, base64::URL_SAFE_NO_PAD)
+            .ok()
+            .and_then(|json| serde_json::from_slice::<Cursor>(&json).ok())
+            // Cursors are opaque, but nothing stops clients from crafting one
+            .filter(|cursor| cursor.offset.is_none_or(|offset| offset >= 0))
+            .ok_or_else(|| Error::InvalidParameter("cursor is not valid".to_string()))
+    }
+}
+
+/// Envelope for paginated listings
+#[derive(Serialize, Debug)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next_cursor: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total: Option<i64>,
+}
+
+impl<T> Page<T> {
+    /// RFC 8288 `Link` header pointing to the next page. The original query
+    /// is kept, so filters and the page size carry over
+    pub fn link_header(&self, path: &str, query: &[(String, String)]) -> Option<String> {
+        let cursor = self.next_cursor.as_ref()?;
+        let mut params: Vec<(&str, &str)> = query
+            .iter()
+            .filter(|(key, _)| key != "cursor" && key != "offset")
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+            .collect();
+        params.push(("cursor", cursor));
+
+        let query = serde_urlencoded::to_string(params).ok()?;
+        Some(format!("<{}?{}>; rel=\"next\"", path, query))
+    }
 }
 
 /// Extract query parameters from the `/questions` route
@@ -22,7 +120,7 @@ pub struct Pagination {
 /// ```rust
 /// use std::collections::HashMap;
 /// use rust_web_dev::types::pagination::extract_pagination;
-/// 
+///
 /// let mut query = HashMap::new();
 /// query.insert("limit".to_string(), "1".to_string());
 /// query.insert("offset".to_string(), "10".to_string());
@@ -31,32 +129,50 @@ pub struct Pagination {
 /// assert_eq!(p.offset, 10);
 /// ```
 pub fn extract_pagination(params: HashMap<String, String>) -> Result<Pagination, Error> {
-    // Could be improved in the future
-    if params.contains_key("limit") && params.contains_key("offset") {
-        return Ok(Pagination {
-            // Takes the "limit" parameter in the query and tries to convert it to a number
-            limit: Some(
-                params
-                    .get("limit")
-                    .unwrap()
-                    .parse()
-                    .map_err(Error::ParseError)?,
-            ),
-            // Takes the "offset" parameter in the query and tries to convert it to a number
-            offset: params
-                .get("offset")
-                .unwrap()
-                .parse()
-                .map_err(Error::ParseError)?,
-        });
-    }
-
-    Err(Error::MissingParameters)
+    if params.contains_key("cursor") && params.contains_key("offset") {
+        return Err(Error::InvalidParameter(
+            "cursor and offset can't be combined".to_string(),
+        ));
+    }
+
+    // Takes the "limit" parameter in the query and tries to convert it to a number
+    let limit = params
+        .get("limit")
+        .map(|limit| limit.parse().map_err(Error::ParseError))
+        .transpose()?;
+    // Takes the "offset" parameter in the query and tries to convert it to a number
+    let offset = params
+        .get("offset")
+        .map(|offset| offset.parse().map_err(Error::ParseError))
+        .transpose()?
+        .unwrap_or(0);
+
+    // Postgres refuses negative limits and offsets, the client has to hear why
+    if limit.is_some_and(|limit: i32| limit < 0) {
+        return Err(Error::InvalidParameter(
+            "limit must not be negative".to_string(),
+        ));
+    }
+    if offset < 0 {
+        return Err(Error::InvalidParameter(
+            "offset must not be negative".to_string(),
+        ));
+    }
+
+    Ok(Pagination {
+        limit,
+        offset,
+        cursor: params
+            .get("cursor")
+            .map(|cursor| Cursor::decode(cursor))
+            .transpose()?,
+        include_total: params.get("count").is_some_and(|count| count == "true"),
+    })
 }
 
 #[cfg(test)]
 mod pagination_tests {
-    use super::{extract_pagination, Error, HashMap, Pagination};
+    use super::{extract_pagination, Cursor, Error, HashMap, Page, Pagination, MAX_PAGE_SIZE};
 
     #[test]
     fn valid_pagination() {
@@ -67,6 +183,7 @@ mod pagination_tests {
         let expected = Pagination {
             limit: Some(1),
             offset: 1,
+            ..Default::default()
         };
         assert_eq!(pagination_result.unwrap(), expected);
     }
@@ -76,10 +193,14 @@ mod pagination_tests {
         let mut params = HashMap::new();
         params.insert(String::from("limit"), String::from("1"));
 
-        let pagination_result = format!("{}", extract_pagination(params).unwrap_err());
-        let expected = format!("{}", Error::MissingParameters);
+        let pagination_result = extract_pagination(params);
+        let expected = Pagination {
+            limit: Some(1),
+            offset: 0,
+            ..Default::default()
+        };
 
-        assert_eq!(pagination_result, expected);
+        assert_eq!(pagination_result.unwrap(), expected);
     }
 
     #[test]
@@ -87,10 +208,10 @@ mod pagination_tests {
         let mut params = HashMap::new();
         params.insert(String::from("offset"), String::from("1"));
 
-        let pagination_result = format!("{}", extract_pagination(params).unwrap_err());
-        let expected = format!("{}", Error::MissingParameters);
+        let pagination_result = extract_pagination(params).unwrap();
 
-        assert_eq!(pagination_result, expected);
+        assert_eq!(pagination_result.limit, None);
+        assert_eq!(pagination_result.offset, 1);
     }
 
     #[test]
@@ -116,4 +237,114 @@ mod pagination_tests {
 
         assert_eq!(pagination_result, expected);
     }
+
+    #[test]
+    fn negative_offset() {
+        let mut params = HashMap::new();
+        params.insert(String::from("limit"), String::from("1"));
+        params.insert(String::from("offset"), String::from("-1"));
+        let pagination_result = format!("{}", extract_pagination(params).unwrap_err());
+
+        let expected = format!(
+            "{}",
+            Error::InvalidParameter("offset must not be negative".to_string())
+        );
+
+        assert_eq!(pagination_result, expected);
+    }
+
+    #[test]
+    fn negative_limit() {
+        let mut params = HashMap::new();
+        params.insert(String::from("limit"), String::from("-1"));
+        params.insert(String::from("offset"), String::from("1"));
+        let pagination_result = format!("{}", extract_pagination(params).unwrap_err());
+
+        let expected = format!(
+            "{}",
+            Error::InvalidParameter("limit must not be negative".to_string())
+        );
+
+        assert_eq!(pagination_result, expected);
+    }
+
+    #[test]
+    fn page_size_is_clamped() {
+        let mut pagination = Pagination::default();
+        assert_eq!(pagination.page_size(), super::DEFAULT_PAGE_SIZE);
+
+        pagination.limit = Some(10_000);
+        assert_eq!(pagination.page_size(), MAX_PAGE_SIZE);
+    }
+
+    #[test]
+    fn cursor_round_trip() {
+        let cursor = Cursor {
+            id: Some(42),
+            ..Default::default()
+        };
+
+        let mut params = HashMap::new();
+        params.insert(String::from("cursor"), cursor.encode());
+        let pagination_result = extract_pagination(params).unwrap();
+
+        assert_eq!(pagination_result.cursor, Some(cursor));
+    }
+
+    #[test]
+    fn invalid_cursor() {
+        let mut params = HashMap::new();
+        params.insert(String::from("cursor"), String::from("NOT_A_CURSOR"));
+        let pagination_result = format!("{}", extract_pagination(params).unwrap_err());
+
+        let expected = format!(
+            "{}",
+            Error::InvalidParameter("cursor is not valid".to_string())
+        );
+
+        assert_eq!(pagination_result, expected);
+    }
+
+    #[test]
+    fn negative_cursor_offset() {
+        let cursor = Cursor {
+            offset: Some(-20),
+            ..Default::default()
+        };
+
+        let mut params = HashMap::new();
+        params.insert(String::from("cursor"), cursor.encode());
+
+        match extract_pagination(params) {
+            Err(Error::InvalidParameter(_)) => {}
+            other => panic!("expected invalid parameter, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn cursor_and_offset() {
+        let mut params = HashMap::new();
+        params.insert(String::from("cursor"), Cursor::default().encode());
+        params.insert(String::from("offset"), String::from("1"));
+
+        assert!(extract_pagination(params).is_err());
+    }
+
+    #[test]
+    fn link_header_keeps_query() {
+        let page: Page<i32> = Page {
+            items: vec![1, 2],
+            next_cursor: Some("abc".to_string()),
+            total: None,
+        };
+        let query = vec![
+            (String::from("tag"), String::from("rust")),
+            (String::from("cursor"), String::from("old")),
+        ];
+
+        assert_eq!(
+            page.link_header("/questions", &query),
+            Some("</questions?tag=rust&cursor=abc>; rel=\"next\"".to_string())
+        );
+    }
 }
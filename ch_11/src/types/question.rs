@@ -1,11 +1,27 @@
+use std::str::FromStr;
+
+use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
 
+use handle_errors::Error;
+
+use crate::types::{
+    account::AccountId,
+    answer::{Answer, AnswerId},
+    pagination::Cursor,
+};
+use crate::validation::{Validate, Validator};
+
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct Question {
     pub id: QuestionId,
     pub title: String,
     pub content: String,
     pub tags: Option<Vec<String>>,
+    pub accepted_answer: Option<AnswerId>,
+    pub score: i64,
+    pub created_on: NaiveDateTime,
+    pub answer_count: i32,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Hash)]
@@ -17,3 +33,201 @@ pub struct NewQuestion {
     pub content: String,
     pub tags: Option<Vec<String>>,
 }
+
+impl Validate for NewQuestion {
+    fn validate(&self) -> Result<(), Error> {
+        let mut validator = Validator::new();
+        validator
+            .length("title", &self.title, 1, 255)
+            .length("content", &self.content, 1, 30_000);
+
+        if let Some(tags) = &self.tags {
+            validator.check(
+                tags.len() <= 10,
+                "tags",
+                "must not contain more than 10 tags",
+            );
+            for tag in tags {
+                validator.length("tags", tag, 1, 50);
+            }
+        }
+
+        validator.finish()
+    }
+}
+
+/// A single question together with its author, creation date
+/// and all the answers given to it
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct QuestionDetail {
+    pub id: QuestionId,
+    pub title: String,
+    pub content: String,
+    pub tags: Option<Vec<String>>,
+    pub account_id: AccountId,
+    pub created_on: NaiveDateTime,
+    pub accepted_answer: Option<AnswerId>,
+    pub score: i64,
+    /// Closed questions take no new answers
+    pub closed_on: Option<NaiveDateTime>,
+    pub close_reason: Option<String>,
+    pub answers: Vec<Answer>,
+}
+
+/// Body of the request which marks an answer as the accepted solution,
+/// `null` clears the current choice
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct AcceptedAnswer {
+    pub answer_id: Option<AnswerId>,
+}
+
+impl Validate for AcceptedAnswer {
+    fn validate(&self) -> Result<(), Error> {
+        Validator::new()
+            .check(
+                self.answer_id.as_ref().is_none_or(|id| id.0 > 0),
+                "answer_id",
+                "must be a positive number",
+            )
+            .finish()
+    }
+}
+
+/// A question matching a full-text search, together with its
+/// relevance and a snippet of the content with the matches highlighted
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct QuestionSearchResult {
+    #[serde(flatten)]
+    pub question: Question,
+    pub rank: f32,
+    pub snippet: String,
+}
+
+/// Order of the `/questions` listing, picked with the `sort` parameter
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuestionSort {
+    /// In order of creation, used if no `sort` is given
+    #[default]
+    Id,
+    Newest,
+    Oldest,
+    MostAnswered,
+    /// Questions without answers, newest first
+    Unanswered,
+    Score,
+}
+
+/// Sort of search results, which come ordered by relevance
+pub const RELEVANCE_SORT: &str = "relevance";
+
+impl QuestionSort {
+    /// The value of the `sort` parameter, `id` for the default order
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            QuestionSort::Id => "id",
+            QuestionSort::Newest => "newest",
+            QuestionSort::Oldest => "oldest",
+            QuestionSort::MostAnswered => "most_answered",
+            QuestionSort::Unanswered => "unanswered",
+            QuestionSort::Score => "score",
+        }
+    }
+
+    /// Cursor pointing right after the given question. It is only
+    /// meaningful for the sort it was created with, which it records
+    pub fn cursor(&self, question: &Question) -> Cursor {
+        Cursor {
+            sort: Some(self.as_str().to_string()),
+            id: Some(question.id.0),
+            created_on: match self {
+                QuestionSort::Newest | QuestionSort::Oldest | QuestionSort::Unanswered => {
+                    Some(question.created_on)
+                }
+                _ => None,
+            },
+            value: match self {
+                QuestionSort::MostAnswered => Some(question.answer_count as i64),
+                QuestionSort::Score => Some(question.score),
+                _ => None,
+            },
+            ..Default::default()
+        }
+    }
+}
+
+impl FromStr for QuestionSort {
+    type Err = Error;
+
+    fn from_str(sort: &str) -> Result<Self, Self::Err> {
+        match sort {
+            "newest" => Ok(QuestionSort::Newest),
+            "oldest" => Ok(QuestionSort::Oldest),
+            "most_answered" => Ok(QuestionSort::MostAnswered),
+            "unanswered" => Ok(QuestionSort::Unanswered),
+            "score" => Ok(QuestionSort::Score),
+            other => Err(Error::InvalidParameter(format!(
+                "sort must be newest, oldest, most_answered, unanswered or score, got {}",
+                other
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod question_tests {
+    use super::{Question, QuestionId, QuestionSort};
+
+    fn question() -> Question {
+        Question {
+            id: QuestionId(7),
+            title: "Title".to_string(),
+            content: "Content".to_string(),
+            tags: None,
+            accepted_answer: None,
+            score: -2,
+            created_on: chrono::NaiveDate::from_ymd_opt(2022, 6, 1)
+                .and_then(|date| date.and_hms_opt(12, 0, 0))
+                .unwrap(),
+            answer_count: 3,
+        }
+    }
+
+    #[test]
+    fn parse_sort() {
+        assert_eq!(
+            "newest".parse::<QuestionSort>().unwrap(),
+            QuestionSort::Newest
+        );
+        assert_eq!(
+            "most_answered".parse::<QuestionSort>().unwrap(),
+            QuestionSort::MostAnswered
+        );
+        assert!("random".parse::<QuestionSort>().is_err());
+    }
+
+    #[test]
+    fn cursor_carries_sort_key() {
+        let question = question();
+
+        let cursor = QuestionSort::Newest.cursor(&question);
+        assert_eq!(cursor.id, Some(7));
+        assert_eq!(cursor.created_on, Some(question.created_on));
+        assert_eq!(cursor.value, None);
+
+        let cursor = QuestionSort::MostAnswered.cursor(&question);
+        assert_eq!(cursor.created_on, None);
+        assert_eq!(cursor.value, Some(3));
+
+        let cursor = QuestionSort::Score.cursor(&question);
+        assert_eq!(cursor.value, Some(-2));
+    }
+
+    #[test]
+    fn cursor_only_fits_its_sort() {
+        let cursor = QuestionSort::Newest.cursor(&question());
+
+        assert!(cursor.check_sort(QuestionSort::Newest.as_str()).is_ok());
+        assert!(cursor.check_sort(QuestionSort::Oldest.as_str()).is_err());
+        assert!(cursor.check_sort(super::RELEVANCE_SORT).is_err());
+    }
+}
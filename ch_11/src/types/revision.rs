@@ -0,0 +1,228 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+use crate::types::{account::AccountId, question::QuestionId};
+
+/// State of a question after one of its edits. Revision 1 is
+/// the question as it was posted
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Revision {
+    pub question_id: QuestionId,
+    pub revision: i32,
+    pub title: String,
+    pub content: String,
+    pub tags: Option<Vec<String>>,
+    /// The account which made the edit
+    pub account_id: AccountId,
+    pub created_on: NaiveDateTime,
+}
+
+/// One line of the content diff between two revisions
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "op", content = "line", rename_all = "lowercase")]
+pub enum DiffLine {
+    Equal(String),
+    Insert(String),
+    Delete(String),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TitleChange {
+    pub from: String,
+    pub to: String,
+}
+
+/// Changes made between the revisions `from` and `to`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RevisionDiff {
+    pub question_id: QuestionId,
+    pub from: i32,
+    pub to: i32,
+    pub title: Option<TitleChange>,
+    pub tags_added: Vec<String>,
+    pub tags_removed: Vec<String>,
+    pub content: Vec<DiffLine>,
+}
+
+impl RevisionDiff {
+    pub fn new(from: &Revision, to: &Revision) -> Self {
+        let from_tags = from.tags.clone().unwrap_or_default();
+        let to_tags = to.tags.clone().unwrap_or_default();
+
+        RevisionDiff {
+            question_id: to.question_id.clone(),
+            from: from.revision,
+            to: to.revision,
+            title: (from.title != to.title).then(|| TitleChange {
+                from: from.title.clone(),
+                to: to.title.clone(),
+            }),
+            tags_added: to_tags
+                .iter()
+                .filter(|tag| !from_tags.contains(tag))
+                .cloned()
+                .collect(),
+            tags_removed: from_tags
+                .iter()
+                .filter(|tag| !to_tags.contains(tag))
+                .cloned()
+                .collect(),
+            content: diff_lines(&from.content, &to.content),
+        }
+    }
+}
+
+/// Largest LCS table `diff_lines()` builds. Beyond that the changed
+/// lines are shown as replaced as a whole, so anyone requesting a diff
+/// of two huge revisions can't tie up the server
+const MAX_DIFF_CELLS: usize = 1_000_000;
+
+/// Line based diff of two texts, using the longest common subsequence
+/// of the lines between their common prefix and suffix
+pub fn diff_lines(from: &str, to: &str) -> Vec<DiffLine> {
+    let from: Vec<&str> = from.lines().collect();
+    let to: Vec<&str> = to.lines().collect();
+
+    let prefix = from
+        .iter()
+        .zip(&to)
+        .take_while(|(from, to)| from == to)
+        .count();
+    let suffix = from[prefix..]
+        .iter()
+        .rev()
+        .zip(to[prefix..].iter().rev())
+        .take_while(|(from, to)| from == to)
+        .count();
+    let changed_from = &from[prefix..from.len() - suffix];
+    let changed_to = &to[prefix..to.len() - suffix];
+
+    let mut diff: Vec<DiffLine> = from[..prefix]
+        .iter()
+        .map(|line| DiffLine::Equal(line.to_string()))
+        .collect();
+    if changed_from.len().saturating_mul(changed_to.len()) <= MAX_DIFF_CELLS {
+        diff.extend(lcs_diff(changed_from, changed_to));
+    } else {
+        diff.extend(
+            changed_from
+                .iter()
+                .map(|line| DiffLine::Delete(line.to_string())),
+        );
+        diff.extend(
+            changed_to
+                .iter()
+                .map(|line| DiffLine::Insert(line.to_string())),
+        );
+    }
+    diff.extend(
+        from[from.len() - suffix..]
+            .iter()
+            .map(|line| DiffLine::Equal(line.to_string())),
+    );
+
+    diff
+}
+
+fn lcs_diff(from: &[&str], to: &[&str]) -> Vec<DiffLine> {
+    // lcs[i][j] is the length of the longest common subsequence
+    // of from[i..] and to[j..]
+    let mut lcs = vec![vec![0usize; to.len() + 1]; from.len() + 1];
+    for i in (0..from.len()).rev() {
+        for j in (0..to.len()).rev() {
+            lcs[i][j] = if from[i] == to[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut diff = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < from.len() && j < to.len() {
+        if from[i] == to[j] {
+            diff.push(DiffLine::Equal(from[i].to_string()));
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            diff.push(DiffLine::Delete(from[i].to_string()));
+            i += 1;
+        } else {
+            diff.push(DiffLine::Insert(to[j].to_string()));
+            j += 1;
+        }
+    }
+    diff.extend(
+        from[i..]
+            .iter()
+            .map(|line| DiffLine::Delete(line.to_string())),
+    );
+    diff.extend(
+        to[j..]
+            .iter()
+            .map(|line| DiffLine::Insert(line.to_string())),
+    );
+
+    diff
+}
+
+#[cfg(test)]
+mod revision_tests {
+    use super::{diff_lines, DiffLine};
+
+    #[test]
+    fn identical_content() {
+        let diff = diff_lines("a\nb", "a\nb");
+        assert_eq!(
+            diff,
+            vec![
+                DiffLine::Equal("a".to_string()),
+                DiffLine::Equal("b".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn changed_line() {
+        let diff = diff_lines("a\nb\nc", "a\nx\nc\nd");
+        assert_eq!(
+            diff,
+            vec![
+                DiffLine::Equal("a".to_string()),
+                DiffLine::Delete("b".to_string()),
+                DiffLine::Insert("x".to_string()),
+                DiffLine::Equal("c".to_string()),
+                DiffLine::Insert("d".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn empty_content() {
+        assert_eq!(
+            diff_lines("", "new"),
+            vec![DiffLine::Insert("new".to_string())]
+        );
+        assert_eq!(
+            diff_lines("old", ""),
+            vec![DiffLine::Delete("old".to_string())]
+        );
+    }
+
+    #[test]
+    fn large_changes_are_replaced_as_a_whole() {
+        let from: Vec<String> = (0..2_000).map(|i| format!("old {}", i)).collect();
+        let to: Vec<String> = (0..2_000).map(|i| format!("new {}", i)).collect();
+        let from = format!("same\n{}\nend", from.join("\n"));
+        let to = format!("same\n{}\nend", to.join("\n"));
+
+        let diff = diff_lines(&from, &to);
+
+        assert_eq!(diff.len(), 4_002);
+        assert_eq!(diff[0], DiffLine::Equal("same".to_string()));
+        assert_eq!(diff[1], DiffLine::Delete("old 0".to_string()));
+        assert_eq!(diff[2_001], DiffLine::Insert("new 0".to_string()));
+        assert_eq!(diff[4_001], DiffLine::Equal("end".to_string()));
+    }
+}
@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use handle_errors::Error;
+
+use crate::types::pagination::{DEFAULT_PAGE_SIZE, MAX_PAGE_SIZE};
+
+/// Entry of the tag catalog
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Tag {
+    pub name: String,
+    /// Number of questions using this tag
+    pub questions: i64,
+}
+
+/// How the requested tags have to match the tags of a question
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TagMatch {
+    /// The question has at least one of the tags
+    #[default]
+    Any,
+    /// The question has every single one of the tags
+    All,
+}
+
+/// Tag filter which is getting extracted
+/// from the query params
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct TagFilter {
+    pub tags: Vec<String>,
+    pub mode: TagMatch,
+}
+
+impl TagFilter {
+    /// Tags of which a question needs at least one, if filtering in `any` mode
+    pub fn any_of(&self) -> Option<Vec<String>> {
+        match self.mode {
+            TagMatch::Any if !self.tags.is_empty() => Some(self.tags.clone()),
+            _ => None,
+        }
+    }
+
+    /// Tags a question needs all of, if filtering in `all` mode
+    pub fn all_of(&self) -> Option<Vec<String>> {
+        match self.mode {
+            TagMatch::All if !self.tags.is_empty() => Some(self.tags.clone()),
+            _ => None,
+        }
+    }
+}
+
+/// Extract the tag filter from the `/questions` route
+/// # Example query
+/// Every `tag` parameter adds a tag, `match` decides if a question needs
+/// to have `any` (default) or `all` of them
+/// `/questions?tag=rust&tag=warp&match=all`
+/// # Example usage
+/// ```rust
+/// use rust_web_dev::types::tag::{extract_tag_filter, TagMatch};
+///
+/// let query = vec![
+///     ("tag".to_string(), "rust".to_string()),
+///     ("tag".to_string(), "warp".to_string()),
+///     ("match".to_string(), "all".to_string()),
+/// ];
+/// let f = extract_tag_filter(&query).unwrap();
+/// assert_eq!(f.tags, vec!["rust", "warp"]);
+/// assert_eq!(f.mode, TagMatch::All);
+/// ```
+pub fn extract_tag_filter(params: &[(String, String)]) -> Result<TagFilter, Error> {
+    let tags = params
+        .iter()
+        .filter(|(key, value)| key == "tag" && !value.trim().is_empty())
+        .map(|(_, value)| value.trim().to_string())
+        .collect();
+
+    let mode = match params.iter().find(|(key, _)| key == "match") {
+        None => TagMatch::default(),
+        Some((_, value)) => match value.as_str() {
+            "any" => TagMatch::Any,
+            "all" => TagMatch::All,
+            other => {
+                return Err(Error::InvalidParameter(format!(
+                    "match must be any or all, got {}",
+                    other
+                )))
+            }
+        },
+    };
+
+    Ok(TagFilter { tags, mode })
+}
+
+/// Number of tags `/tags` returns, the `limit` parameter capped
+/// like the page size of the other listings
+pub fn extract_tag_limit(params: &HashMap<String, String>) -> Result<i32, Error> {
+    let limit = match params.get("limit") {
+        Some(limit) => limit.parse::<i32>().map_err(Error::ParseError)?,
+        None => return Ok(DEFAULT_PAGE_SIZE),
+    };
+
+    if limit < 0 {
+        return Err(Error::InvalidParameter(
+            "limit must not be negative".to_string(),
+        ));
+    }
+
+    Ok(limit.min(MAX_PAGE_SIZE))
+}
+
+#[cfg(test)]
+mod tag_tests {
+    use super::{extract_tag_filter, extract_tag_limit, Error, HashMap, TagFilter, TagMatch};
+    use crate::types::pagination::{DEFAULT_PAGE_SIZE, MAX_PAGE_SIZE};
+
+    fn params(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn no_tags() {
+        let filter = extract_tag_filter(&params(&[("limit", "1")])).unwrap();
+        assert_eq!(filter, TagFilter::default());
+        assert_eq!(filter.any_of(), None);
+        assert_eq!(filter.all_of(), None);
+    }
+
+    #[test]
+    fn multiple_tags_default_to_any() {
+        let filter = extract_tag_filter(&params(&[("tag", "rust"), ("tag", "warp")])).unwrap();
+        assert_eq!(filter.mode, TagMatch::Any);
+        assert_eq!(
+            filter.any_of(),
+            Some(vec!["rust".to_string(), "warp".to_string()])
+        );
+        assert_eq!(filter.all_of(), None);
+    }
+
+    #[test]
+    fn all_tags() {
+        let filter = extract_tag_filter(&params(&[("tag", "rust"), ("match", "all")])).unwrap();
+        assert_eq!(filter.any_of(), None);
+        assert_eq!(filter.all_of(), Some(vec!["rust".to_string()]));
+    }
+
+    #[test]
+    fn wrong_match_value() {
+        let result = format!(
+            "{}",
+            extract_tag_filter(&params(&[("match", "some")])).unwrap_err()
+        );
+        let expected = format!(
+            "{}",
+            Error::InvalidParameter("match must be any or all, got some".to_string())
+        );
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn tag_limit() {
+        let limit = |value: Option<&str>| {
+            let mut params = HashMap::new();
+            if let Some(value) = value {
+                params.insert(String::from("limit"), value.to_string());
+            }
+            extract_tag_limit(&params)
+        };
+
+        assert_eq!(limit(None).unwrap(), DEFAULT_PAGE_SIZE);
+        assert_eq!(limit(Some("5")).unwrap(), 5);
+        assert_eq!(limit(Some("100000")).unwrap(), MAX_PAGE_SIZE);
+        assert!(matches!(limit(Some("-1")), Err(Error::InvalidParameter(_))));
+        assert!(matches!(limit(Some("many")), Err(Error::ParseError(_))));
+    }
+}
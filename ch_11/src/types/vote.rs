@@ -0,0 +1,69 @@
+use serde::{Deserialize, Serialize};
+
+use handle_errors::Error;
+
+use crate::types::{answer::AnswerId, question::QuestionId};
+use crate::validation::Validate;
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum VoteDirection {
+    Up,
+    Down,
+}
+
+impl VoteDirection {
+    /// The value which gets stored in the `votes` table
+    pub fn value(&self) -> i16 {
+        match self {
+            VoteDirection::Up => 1,
+            VoteDirection::Down => -1,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct NewVote {
+    pub direction: VoteDirection,
+}
+
+impl Validate for NewVote {
+    fn validate(&self) -> Result<(), Error> {
+        // The direction is checked while deserializing
+        Ok(())
+    }
+}
+
+/// The post a vote is cast on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VoteTarget {
+    Question(QuestionId),
+    Answer(AnswerId),
+}
+
+/// Aggregated score of a question or answer after a vote changed
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Score {
+    pub score: i64,
+}
+
+#[cfg(test)]
+mod vote_tests {
+    use super::{NewVote, VoteDirection};
+
+    #[test]
+    fn parse_vote_direction() {
+        let up: NewVote = serde_json::from_str(r#"{"direction": "up"}"#).unwrap();
+        let down: NewVote = serde_json::from_str(r#"{"direction": "down"}"#).unwrap();
+
+        assert_eq!(up.direction, VoteDirection::Up);
+        assert_eq!(up.direction.value(), 1);
+        assert_eq!(down.direction.value(), -1);
+    }
+
+    #[test]
+    fn reject_unknown_direction() {
+        let vote = serde_json::from_str::<NewVote>(r#"{"direction": "sideways"}"#);
+        assert!(vote.is_err());
+    }
+}
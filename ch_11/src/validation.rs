@@ -0,0 +1,130 @@
+use serde::de::DeserializeOwned;
+use warp::Filter;
+
+use handle_errors::{Error, FieldError};
+
+/// Request bodies check their fields with this trait before
+/// they reach a route handler
+pub trait Validate {
+    fn validate(&self) -> Result<(), Error>;
+}
+
+/// Collects the field errors of a request body, so the client
+/// gets all of them at once and not just the first one
+#[derive(Default, Debug)]
+pub struct Validator {
+    errors: Vec<FieldError>,
+}
+
+impl Validator {
+    pub fn new() -> Self {
+        Validator::default()
+    }
+
+    /// Adds an error for `field` if the condition does not hold
+    pub fn check(&mut self, valid: bool, field: &str, message: &str) -> &mut Self {
+        if !valid {
+            self.errors.push(FieldError {
+                field: field.to_string(),
+                message: message.to_string(),
+            });
+        }
+        self
+    }
+
+    /// Checks that the trimmed value isn't empty if `min > 0`, and that
+    /// it has at most `max` characters, like a `VARCHAR(max)` column
+    pub fn length(&mut self, field: &str, value: &str, min: usize, max: usize) -> &mut Self {
+        if min > 0 && value.trim().is_empty() {
+            return self.check(false, field, "must not be empty");
+        }
+
+        let length = value.chars().count();
+        self.check(
+            length >= min,
+            field,
+            &format!("must be at least {} characters long", min),
+        )
+        .check(
+            length <= max,
+            field,
+            &format!("must be at most {} characters long", max),
+        )
+    }
+
+    pub fn email(&mut self, field: &str, value: &str) -> &mut Self {
+        let valid = match value.split_once('@') {
+            Some((local, domain)) => {
+                !local.is_empty() && domain.contains('.') && !value.contains(char::is_whitespace)
+            }
+            None => false,
+        };
+        self.check(valid, field, "must be a valid email address")
+            .length(field, value, 1, 255)
+    }
+
+    pub fn finish(&mut self) -> Result<(), Error> {
+        match self.errors.is_empty() {
+            true => Ok(()),
+            false => Err(Error::ValidationError(std::mem::take(&mut self.errors))),
+        }
+    }
+}
+
+/// Like `warp::body::json()`, but rejects bodies which fail
+/// their validation with a 400 and the list of field errors
+pub fn json_body<T>() -> impl Filter<Extract = (T,), Error = warp::Rejection> + Clone
+where
+    T: DeserializeOwned + Validate + Send,
+{
+    warp::body::json().and_then(|body: T| async move {
+        match body.validate() {
+            Ok(_) => Ok(body),
+            Err(e) => Err(warp::reject::custom(e)),
+        }
+    })
+}
+
+#[cfg(test)]
+mod validation_tests {
+    use super::{Error, Validator};
+
+    #[test]
+    fn valid_fields() {
+        let result = Validator::new()
+            .length("title", "A title", 1, 255)
+            .email("email", "test@email.com")
+            .finish();
+
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn collects_all_errors() {
+        let result = Validator::new()
+            .length("title", "   ", 1, 255)
+            .length("content", &"a".repeat(11), 1, 10)
+            .email("email", "not-an-email")
+            .finish();
+
+        match result {
+            Err(Error::ValidationError(errors)) => {
+                let fields: Vec<&str> = errors.iter().map(|e| e.field.as_str()).collect();
+                assert_eq!(fields, vec!["title", "content", "email"]);
+                assert_eq!(errors[0].message, "must not be empty");
+                assert_eq!(errors[1].message, "must be at most 10 characters long");
+            }
+            _ => panic!("Expected validation errors"),
+        }
+    }
+
+    #[test]
+    fn length_counts_characters() {
+        // 255 multi-byte characters still fit into a VARCHAR(255)
+        let title = "ü".repeat(255);
+        assert!(Validator::new()
+            .length("title", &title, 1, 255)
+            .finish()
+            .is_ok());
+    }
+}